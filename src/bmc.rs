@@ -0,0 +1,574 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 带外管理：通过 ipmitool -I lanplus 或 Redfish 访问主机 BMC ，设置启动设备、读取电源状态和断电重启，凭据按厂商和序列号选择
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::process::Command;
+
+use crate::boot_mode::BootMode;
+use crate::config::{IpmiConfig, RedfishConfig};
+use crate::credentials::{decrypt, encrypt};
+use crate::redfish::{BootOverride, RedfishClient};
+
+static IPMI_CONFIG: OnceLock<IpmiConfig> = OnceLock::new();
+static REDFISH_CONFIG: OnceLock<RedfishConfig> = OnceLock::new();
+static DB_POOL: OnceLock<Pool<SqliteConnectionManager>> = OnceLock::new();
+
+// 访问 BMC 使用的协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BmcProtocol {
+    Ipmi,
+    Redfish,
+}
+
+impl BmcProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BmcProtocol::Ipmi => "ipmi",
+            BmcProtocol::Redfish => "redfish",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ipmi" => Some(BmcProtocol::Ipmi),
+            "redfish" => Some(BmcProtocol::Redfish),
+            _ => None,
+        }
+    }
+}
+
+// 要访问的 BMC ，厂商和序列号用于选择凭据
+#[derive(Debug, Clone)]
+pub struct BmcTarget {
+    pub address: String,
+    pub vendor: Option<String>,
+    pub serial: Option<String>,
+    pub protocol: BmcProtocol,
+    // 主机发现时检测到的启动模式，为空时按 UEFI 处理
+    pub boot_mode: Option<BootMode>,
+}
+
+impl BmcTarget {
+    // 按 IPMI 地址查找主机的厂商、序列号和启动模式；主机指定的协议优先，否则按厂商选择
+    pub fn lookup(conn: &Connection, address: &str) -> BmcTarget {
+        type Row = (
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        );
+        let (vendor, serial, protocol, boot_mode): Row = conn
+            .query_row(
+                "SELECT vendor, serial, bmc_protocol, boot_mode FROM hosts WHERE ipmi_address = ?1",
+                params![address],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()
+            .unwrap()
+            .unwrap_or_default();
+        let protocol = protocol
+            .as_deref()
+            .and_then(BmcProtocol::parse)
+            .unwrap_or_else(|| {
+                let redfish_vendors = &redfish_config().vendors;
+                match &vendor {
+                    Some(vendor) if redfish_vendors.iter().any(|v| v == vendor) => {
+                        BmcProtocol::Redfish
+                    }
+                    _ => BmcProtocol::Ipmi,
+                }
+            });
+        BmcTarget {
+            address: address.to_string(),
+            vendor,
+            serial,
+            protocol,
+            boot_mode: boot_mode.as_deref().and_then(BootMode::parse),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BmcError {
+    // 没有可用的凭据或 ipmitool
+    NotConfigured(String),
+    // BMC 无响应
+    Unreachable(String),
+    // BMC 有响应但命令失败，例如认证失败
+    Failed(String),
+}
+
+impl BmcError {
+    // 只有 BMC 不可用时才改用带内方式
+    pub fn can_fall_back(&self) -> bool {
+        matches!(self, BmcError::NotConfigured(_) | BmcError::Unreachable(_))
+    }
+}
+
+impl fmt::Display for BmcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BmcError::NotConfigured(e) => write!(f, "BMC not configured: {e}"),
+            BmcError::Unreachable(e) => write!(f, "BMC unreachable: {e}"),
+            BmcError::Failed(e) => write!(f, "BMC command failed: {e}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerState {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootDevice {
+    Pxe,
+    Disk,
+}
+
+// 解密后的 BMC 凭据
+#[derive(Clone)]
+pub struct BmcCredential {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize)]
+pub struct BmcCredentialRecord {
+    id: i64,
+    vendor: Option<String>,
+    serial: Option<String>,
+    username: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewBmcCredential {
+    vendor: Option<String>,
+    serial: Option<String>,
+    username: String,
+    password: String,
+}
+
+// 启动时设置默认凭据、Redfish 参数和凭据所在的数据库
+pub fn init(ipmi: &IpmiConfig, redfish: &RedfishConfig, db_pool: Pool<SqliteConnectionManager>) {
+    IPMI_CONFIG.set(ipmi.clone()).ok();
+    REDFISH_CONFIG.set(redfish.clone()).ok();
+    DB_POOL.set(db_pool).ok();
+}
+
+fn redfish_config() -> RedfishConfig {
+    REDFISH_CONFIG.get().cloned().unwrap_or_default()
+}
+
+// 配置文件中的默认凭据，未配置密码时为空
+fn resolve_from_config(config: &IpmiConfig) -> Option<BmcCredential> {
+    Some(BmcCredential {
+        username: config.user.clone(),
+        password: config.password.clone(),
+    })
+    .filter(|credential| !credential.password.is_empty())
+}
+
+// 选择最匹配的凭据：序列号匹配优先于厂商匹配，均未匹配时使用通用凭据，数据库中没有时使用配置文件
+pub fn resolve(
+    conn: &Connection,
+    vendor: Option<&str>,
+    serial: Option<&str>,
+    config: &IpmiConfig,
+) -> Option<BmcCredential> {
+    let row: Option<(i64, String, String)> = conn
+        .query_row(
+            r#"
+            SELECT id, username, password
+            FROM bmc_credentials
+            WHERE (vendor IS NULL OR vendor = ?1)
+              AND (serial IS NULL OR serial = ?2)
+            ORDER BY serial IS NOT NULL DESC, vendor IS NOT NULL DESC
+            LIMIT 1
+            "#,
+            params![vendor, serial],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .unwrap();
+    let Some((id, username, password)) = row else {
+        return resolve_from_config(config);
+    };
+    match decrypt(&password) {
+        Ok(password) => Some(BmcCredential { username, password }),
+        Err(e) => {
+            println!("[ERROR] Cannot decrypt BMC credential {id}: {e}");
+            None
+        }
+    }
+}
+
+fn resolve_credential(target: &BmcTarget) -> Option<BmcCredential> {
+    let config = IPMI_CONFIG.get().cloned().unwrap_or_default();
+    match DB_POOL.get() {
+        Some(db_pool) => resolve(
+            &db_pool.get().unwrap(),
+            target.vendor.as_deref(),
+            target.serial.as_deref(),
+            &config,
+        ),
+        None => resolve_from_config(&config),
+    }
+}
+
+// 区分 BMC 无响应和认证等其他失败，认证失败时 ipmitool 同样会报 Unable to establish 会话
+fn classify_ipmitool_error(stderr: &str) -> BmcError {
+    let lower = stderr.to_lowercase();
+    let auth_failed = ["rakp", "unauthorized", "invalid", "insufficient privilege"]
+        .iter()
+        .any(|marker| lower.contains(marker));
+    if !auth_failed && (lower.contains("unable to establish") || lower.contains("timeout")) {
+        BmcError::Unreachable(stderr.to_string())
+    } else {
+        BmcError::Failed(stderr.to_string())
+    }
+}
+
+fn credential(target: &BmcTarget) -> Result<BmcCredential, BmcError> {
+    // 没有读到带外管理地址的主机（旧版本记录为 unknown）只能改用带内方式
+    if target.address.is_empty() || target.address == "unknown" {
+        return Err(BmcError::NotConfigured("no BMC address".to_string()));
+    }
+    resolve_credential(target)
+        .ok_or_else(|| BmcError::NotConfigured(format!("no credential for BMC {}", target.address)))
+}
+
+fn redfish_client(target: &BmcTarget) -> Result<RedfishClient, BmcError> {
+    RedfishClient::new(
+        &target.address,
+        credential(target)?,
+        redfish_config().timeout_secs,
+    )
+}
+
+// 运行带外 ipmitool 命令，密码通过环境变量传递以免出现在进程列表中；超时视为 BMC 无响应
+async fn ipmitool(target: &BmcTarget, args: &[&str]) -> Result<String, BmcError> {
+    let credential = credential(target)?;
+    let timeout_secs = IPMI_CONFIG.get().cloned().unwrap_or_default().timeout_secs;
+    let command = Command::new("ipmitool")
+        .args([
+            "-I",
+            "lanplus",
+            "-H",
+            &target.address,
+            "-U",
+            &credential.username,
+            "-E",
+        ])
+        .args(args)
+        .env("IPMI_PASSWORD", &credential.password)
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(timeout_secs), command)
+        .await
+        .map_err(|_| {
+            BmcError::Unreachable(format!(
+                "ipmitool on {} timed out after {timeout_secs}s",
+                target.address
+            ))
+        })?
+        .map_err(|e| BmcError::NotConfigured(format!("failed to execute ipmitool: {e}")))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        Err(classify_ipmitool_error(
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ))
+    }
+}
+
+// 设置下次启动设备，启动模式与主机当前的启动模式一致
+pub async fn set_boot_device(target: &BmcTarget, device: BootDevice) -> Result<(), BmcError> {
+    let boot_mode = target.boot_mode.unwrap_or(BootMode::Uefi);
+    if target.protocol == BmcProtocol::Redfish {
+        let boot_override = match device {
+            // UEFI HTTP 启动只能用于 UEFI 模式
+            BootDevice::Pxe if boot_mode == BootMode::Uefi => redfish_config().boot_target,
+            BootDevice::Pxe => BootOverride::Pxe,
+            BootDevice::Disk => BootOverride::Hdd,
+        };
+        return redfish_client(target)?
+            .set_boot_override(boot_override, boot_mode)
+            .await;
+    }
+    let device = match device {
+        BootDevice::Pxe => "pxe",
+        BootDevice::Disk => "disk",
+    };
+    let mut args = vec!["chassis", "bootdev", device];
+    args.extend(BootMode::ipmi_bootdev_options(target.boot_mode));
+    ipmitool(target, &args).await.map(|_| ())
+}
+
+// 读取电源状态
+pub async fn power_state(target: &BmcTarget) -> Result<PowerState, BmcError> {
+    if target.protocol == BmcProtocol::Redfish {
+        return redfish_client(target)?.power_state().await;
+    }
+    let status = ipmitool(target, &["chassis", "power", "status"]).await?;
+    if status.ends_with("on") {
+        Ok(PowerState::On)
+    } else if status.ends_with("off") {
+        Ok(PowerState::Off)
+    } else {
+        Err(BmcError::Failed(format!(
+            "unexpected power status: {status}"
+        )))
+    }
+}
+
+// 断电重启，已关机的主机无法 power cycle ，改为开机
+pub async fn power_cycle(target: &BmcTarget) -> Result<(), BmcError> {
+    if target.protocol == BmcProtocol::Redfish {
+        return redfish_client(target)?.power_cycle().await;
+    }
+    let action = match power_state(target).await? {
+        PowerState::On => "cycle",
+        PowerState::Off => "on",
+    };
+    ipmitool(target, &["chassis", "power", action])
+        .await
+        .map(|_| ())
+}
+
+// 设置下次从 PXE 启动并断电重启；Redfish 可以读取序列号，重启前确认 BMC 属于该主机
+pub async fn pxe_reboot(target: &BmcTarget) -> Result<(), BmcError> {
+    if target.protocol == BmcProtocol::Redfish
+        && let Some(serial) = &target.serial
+    {
+        let bmc_serial = redfish_client(target)?.serial_number().await?;
+        if &bmc_serial != serial {
+            return Err(BmcError::Failed(format!(
+                "BMC {} belongs to {bmc_serial}, not {serial}",
+                target.address
+            )));
+        }
+    }
+    set_boot_device(target, BootDevice::Pxe).await?;
+    power_cycle(target).await
+}
+
+// 处理 GET /api/hosts/{serial}/power ，通过 BMC 读取主机电源状态
+pub async fn get_power_state(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let target = {
+        let conn = db_pool.get().unwrap();
+        let address: Option<Option<String>> = conn
+            .query_row(
+                "SELECT ipmi_address FROM hosts WHERE serial = ?1",
+                params![serial],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        match address {
+            None => {
+                return HttpResponse::NotFound()
+                    .json(json!({ "error": format!("host {serial} not found") }));
+            }
+            Some(None) => {
+                return HttpResponse::Conflict()
+                    .json(json!({ "error": format!("host {serial} has no ipmi_address") }));
+            }
+            Some(Some(address)) => BmcTarget::lookup(&conn, &address),
+        }
+    };
+    match power_state(&target).await {
+        Ok(power) => HttpResponse::Ok().json(json!({
+            "serial": serial,
+            "ipmi_address": target.address,
+            "power": power,
+        })),
+        Err(e) => HttpResponse::BadGateway().json(json!({ "error": e.to_string() })),
+    }
+}
+
+// 处理 GET /api/bmc-credentials ，不返回密码
+pub async fn list_bmc_credentials(
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare("SELECT id, vendor, serial, username FROM bmc_credentials ORDER BY id")
+        .unwrap();
+    let records: Vec<BmcCredentialRecord> = stmt
+        .query_map([], |row| {
+            Ok(BmcCredentialRecord {
+                id: row.get(0)?,
+                vendor: row.get(1)?,
+                serial: row.get(2)?,
+                username: row.get(3)?,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(records)
+}
+
+// 处理 POST /api/bmc-credentials ，同一厂商和序列号的凭据会被替换
+pub async fn create_bmc_credential(
+    credential: web::Json<NewBmcCredential>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let mut credential = credential.into_inner();
+    // 空字符串视为不限定
+    credential.vendor = credential
+        .vendor
+        .map(|vendor| vendor.trim().to_string())
+        .filter(|vendor| !vendor.is_empty());
+    credential.serial = credential.serial.filter(|serial| !serial.trim().is_empty());
+    if credential.username.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "username must not be empty" }));
+    }
+    if credential.password.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "password must not be empty" }));
+    }
+    let password = match encrypt(&credential.password) {
+        Ok(password) => password,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let conn = db_pool.get().unwrap();
+    conn.execute(
+        "DELETE FROM bmc_credentials WHERE vendor IS ?1 AND serial IS ?2",
+        params![credential.vendor, credential.serial],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO bmc_credentials (vendor, serial, username, password) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            credential.vendor,
+            credential.serial,
+            credential.username.trim(),
+            password
+        ],
+    )
+    .unwrap();
+    let id = conn.last_insert_rowid();
+    println!(
+        "[INFO] BMC credential {id} stored (vendor: {}, serial: {})",
+        credential.vendor.as_deref().unwrap_or("*"),
+        credential.serial.as_deref().unwrap_or("*")
+    );
+    HttpResponse::Created().json(json!({ "id": id }))
+}
+
+// 处理 DELETE /api/bmc-credentials/{id}
+pub async fn delete_bmc_credential(
+    id: web::Path<i64>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let conn = db_pool.get().unwrap();
+    let deleted = conn
+        .execute("DELETE FROM bmc_credentials WHERE id = ?1", params![id])
+        .unwrap();
+    if deleted == 0 {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("BMC credential {id} not found") }));
+    }
+    println!("[INFO] BMC credential {id} deleted");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    #[test]
+    fn ipmitool_errors() {
+        assert!(matches!(
+            classify_ipmitool_error("Error: Unable to establish IPMI v2 / RMCP+ session"),
+            BmcError::Unreachable(_)
+        ));
+        assert!(matches!(
+            classify_ipmitool_error(
+                "Error in open session response message : invalid authentication algorithm\nError: Unable to establish IPMI v2 / RMCP+ session"
+            ),
+            BmcError::Failed(_)
+        ));
+        assert!(matches!(
+            classify_ipmitool_error("RAKP 2 HMAC is invalid"),
+            BmcError::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn host_protocol_overrides_vendor() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, ipmi_address, vendor) VALUES ('S1', '10.1.0.5', 'Dell Inc.')",
+            [],
+        )
+        .unwrap();
+        let target = BmcTarget::lookup(&conn, "10.1.0.5");
+        assert_eq!(target.serial.as_deref(), Some("S1"));
+        assert_eq!(target.protocol, BmcProtocol::Ipmi);
+        conn.execute("UPDATE hosts SET bmc_protocol = 'redfish'", [])
+            .unwrap();
+        assert_eq!(
+            BmcTarget::lookup(&conn, "10.1.0.5").protocol,
+            BmcProtocol::Redfish
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_address_falls_back_in_band() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        for address in ["", "unknown"] {
+            let result = pxe_reboot(&BmcTarget::lookup(&conn, address)).await;
+            assert!(matches!(result, Err(BmcError::NotConfigured(_))));
+            assert!(result.unwrap_err().can_fall_back());
+        }
+    }
+
+    #[test]
+    fn config_credential_is_used_without_database_match() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let mut config = IpmiConfig::default();
+        assert!(resolve(&conn, Some("Dell Inc."), Some("S1"), &config).is_none());
+        config.password = "secret".to_string();
+        let credential = resolve(&conn, Some("Dell Inc."), Some("S1"), &config).unwrap();
+        assert_eq!(
+            (credential.username.as_str(), credential.password.as_str()),
+            ("admin", "secret")
+        );
+    }
+}
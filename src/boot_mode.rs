@@ -0,0 +1,147 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 启动模式：主机发现时检测主机以传统 BIOS 还是 UEFI 启动，重启方式、iPXE 脚本和分区方案据此选择
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+
+// 在主机上检测启动模式的命令，UEFI 启动的 Linux 才有 /sys/firmware/efi
+pub const DETECT_COMMAND: &str = "[ -d /sys/firmware/efi ] && echo uefi || echo bios";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootMode {
+    Bios,
+    Uefi,
+}
+
+impl BootMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootMode::Bios => "bios",
+            BootMode::Uefi => "uefi",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "bios" => Some(BootMode::Bios),
+            "uefi" => Some(BootMode::Uefi),
+            _ => None,
+        }
+    }
+
+    // ipmitool chassis bootdev 的启动选项，未检测到启动模式时按 UEFI 处理
+    pub fn ipmi_bootdev_options(mode: Option<BootMode>) -> &'static [&'static str] {
+        match mode {
+            Some(BootMode::Bios) => &[],
+            Some(BootMode::Uefi) | None => &["options=efiboot"],
+        }
+    }
+
+    // Redfish BootSourceOverrideMode 的取值
+    pub fn as_redfish(&self) -> &'static str {
+        match self {
+            BootMode::Bios => "Legacy",
+            BootMode::Uefi => "UEFI",
+        }
+    }
+
+    // kickstart 中的引导程序和分区：UEFI 需要 EFI 系统分区，BIOS 在 GPT 磁盘上需要 biosboot 分区
+    pub fn kickstart_partitioning(&self) -> String {
+        let boot_partition = match self {
+            BootMode::Uefi => "part /boot/efi --fstype=efi --size=600",
+            BootMode::Bios => "part biosboot --fstype=biosboot --size=1",
+        };
+        format!(
+            "zerombr\n\
+             clearpart --all --initlabel --disklabel=gpt\n\
+             bootloader --location=mbr\n\
+             {boot_partition}\n\
+             part /boot --fstype=xfs --size=1024\n\
+             part pv.01 --size=1 --grow\n\
+             volgroup vg0 pv.01\n\
+             logvol / --vgname=vg0 --name=root --fstype=xfs --size=1 --grow\n"
+        )
+    }
+}
+
+impl fmt::Display for BootMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 读取主机记录的启动模式，尚未检测到时返回 None
+pub fn host_boot_mode(conn: &Connection, serial: &str) -> Option<BootMode> {
+    conn.query_row(
+        "SELECT boot_mode FROM hosts WHERE serial = ?1",
+        params![serial],
+        |row| row.get::<_, Option<String>>(0),
+    )
+    .optional()
+    .unwrap()
+    .flatten()
+    .and_then(|mode| BootMode::parse(&mode))
+}
+
+// 处理 GET /api/partitioning/{serial} ，返回与主机启动模式匹配的分区方案，供 kickstart 在 %pre 中下载后 %include
+pub async fn get_partitioning(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let conn = db_pool.get().unwrap();
+    let mode: Option<Option<String>> = conn
+        .query_row(
+            "SELECT boot_mode FROM hosts WHERE serial = ?1",
+            params![serial],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap();
+    let Some(mode) = mode else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    };
+    // 与重启和 iPXE 脚本一致，未检测到启动模式时按 UEFI 处理
+    let mode = mode
+        .as_deref()
+        .and_then(BootMode::parse)
+        .unwrap_or(BootMode::Uefi);
+    println!("[INFO] Offering {mode} partitioning for {serial}");
+    HttpResponse::Ok().body(mode.kickstart_partitioning())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitioning_follows_boot_mode() {
+        let uefi = BootMode::Uefi.kickstart_partitioning();
+        assert!(uefi.contains("part /boot/efi --fstype=efi"));
+        assert!(!uefi.contains("biosboot"));
+        let bios = BootMode::Bios.kickstart_partitioning();
+        assert!(bios.contains("part biosboot --fstype=biosboot"));
+        assert!(!bios.contains("/boot/efi"));
+    }
+}
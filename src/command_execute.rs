@@ -14,38 +14,400 @@
  * limitations under the License.
 */
 
-use tokio::process::Command;
-
-const SSH_PASS: &str = "abc123";
-
-// SSH 到指定主机并运行命令，返回命令的运行结果
-pub async fn run_ssh_command_on_host(ip_addr: &str, command: &str) -> Option<String> {
-    let output = Command::new("sshpass")
-        .arg("-p")
-        .arg(SSH_PASS)
-        .arg("ssh")
-        .arg("-o")
-        .arg("LogLevel=ERROR")
-        .arg("-o")
-        .arg("StrictHostKeyChecking=no")
-        .arg("-o")
-        .arg("UserKnownHostsFile=/dev/null")
-        .arg("-o")
-        .arg("ConnectTimeout=3")
-        .arg(ip_addr)
-        .arg(command)
-        .output()
-        .await;
-    match output {
-        Ok(output) if output.status.success() => {
-            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
-        }
-        _ => {
-            println!(
-                "[INFO] Run SSH command \"{}\" on host {} failed!",
-                command, ip_addr
-            );
+// SSH 命令执行代码：使用进程内 SSH 客户端，并对每台主机复用连接
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use ssh2::{Channel, ErrorCode, Session};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::SshConfig;
+use crate::credentials::{self, Credential, InstallPhase};
+use crate::host_keys::{self, HostKey, HostKeyError};
+
+// libssh2 中表示阻塞操作超时的错误码
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+
+static SSH_CONFIG: OnceLock<SshConfig> = OnceLock::new();
+static DB_POOL: OnceLock<Pool<SqliteConnectionManager>> = OnceLock::new();
+
+// 每台主机每个装机阶段一个 SSH 连接，同一主机上的命令串行执行
+static SESSIONS: LazyLock<Mutex<HashMap<String, Arc<Mutex<HostSession>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// 已认证的连接及其主机密钥
+struct HostSession {
+    session: Session,
+    host_key: HostKey,
+}
+
+// SSH 目标主机，登录凭据按装机阶段、操作系统和序列号选择
+#[derive(Debug, Clone)]
+pub struct SshTarget {
+    pub ip_address: String,
+    pub phase: InstallPhase,
+    pub os: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl SshTarget {
+    pub fn new(
+        ip_address: &str,
+        phase: InstallPhase,
+        os: Option<&str>,
+        serial: Option<&str>,
+    ) -> Self {
+        SshTarget {
+            ip_address: ip_address.to_string(),
+            phase,
+            os: os.map(String::from),
+            serial: serial.map(String::from),
+        }
+    }
+
+    fn session_key(&self) -> String {
+        format!("{}@{}", self.phase, self.ip_address)
+    }
+}
+
+// 命令执行结果
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub exit_status: i32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == 0
+    }
+
+    // 命令执行成功时返回去掉首尾空白的标准输出
+    pub fn into_stdout(self) -> Option<String> {
+        if self.success() {
+            Some(self.stdout.trim().to_string())
+        } else {
             None
         }
     }
 }
+
+#[derive(Debug)]
+pub enum SshError {
+    Connect(String),
+    Handshake(String),
+    Auth(String),
+    // 主机密钥未固定在该阶段，可换用另一阶段重试
+    HostKeyUnknown(String),
+    // 主机密钥与固定的不一致，已告警
+    HostKeyChanged(String),
+    Channel(String),
+    Timeout,
+}
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SshError::Connect(e) => write!(f, "connect failed: {e}"),
+            SshError::Handshake(e) => write!(f, "handshake failed: {e}"),
+            SshError::Auth(e) => write!(f, "authentication failed: {e}"),
+            SshError::HostKeyUnknown(e) => write!(f, "host key not trusted: {e}"),
+            SshError::HostKeyChanged(e) => write!(f, "host key verification failed: {e}"),
+            SshError::Channel(e) => write!(f, "channel error: {e}"),
+            SshError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl From<HostKeyError> for SshError {
+    fn from(e: HostKeyError) -> Self {
+        match e {
+            HostKeyError::Unknown(e) => SshError::HostKeyUnknown(e),
+            HostKeyError::Changed(e) => SshError::HostKeyChanged(e),
+        }
+    }
+}
+
+impl From<ssh2::Error> for SshError {
+    fn from(e: ssh2::Error) -> Self {
+        if e.code() == ErrorCode::Session(LIBSSH2_ERROR_TIMEOUT) {
+            SshError::Timeout
+        } else {
+            SshError::Channel(e.to_string())
+        }
+    }
+}
+
+// 启动时设置 SSH 登录参数和凭据所在的数据库
+pub fn init(config: &SshConfig, db_pool: Pool<SqliteConnectionManager>) {
+    SSH_CONFIG.set(config.clone()).ok();
+    DB_POOL.set(db_pool).ok();
+}
+
+fn ssh_config() -> SshConfig {
+    SSH_CONFIG.get().cloned().unwrap_or_default()
+}
+
+fn resolve_credential(target: &SshTarget, config: &SshConfig) -> Credential {
+    match DB_POOL.get() {
+        Some(db_pool) => credentials::resolve(
+            &db_pool.get().unwrap(),
+            target.phase,
+            target.os.as_deref(),
+            target.serial.as_deref(),
+            config,
+        ),
+        None => credentials::resolve_from_config(config),
+    }
+}
+
+// 认证前校验主机密钥，返回按主机密钥识别出的序列号
+fn verify_host_key(target: &SshTarget, host_key: &HostKey) -> Result<Option<String>, SshError> {
+    match DB_POOL.get() {
+        Some(db_pool) => Ok(host_keys::verify(
+            &db_pool.get().unwrap(),
+            &target.ip_address,
+            target.phase,
+            target.serial.as_deref(),
+            host_key,
+        )?),
+        None => Ok(target.serial.clone()),
+    }
+}
+
+// 建立 SSH 连接并认证，有私钥时优先使用私钥
+fn connect(target: &SshTarget, config: &SshConfig) -> Result<HostSession, SshError> {
+    let ip_addr = &target.ip_address;
+    let addr: SocketAddr = format!("{ip_addr}:22")
+        .parse()
+        .map_err(|_| SshError::Connect(format!("invalid address {ip_addr}")))?;
+    let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(config.connect_timeout_secs))
+        .map_err(|e| SshError::Connect(e.to_string()))?;
+    let mut session = Session::new().map_err(|e| SshError::Connect(e.to_string()))?;
+    session.set_tcp_stream(tcp);
+    session.set_timeout((config.connect_timeout_secs * 1000) as u32);
+    session
+        .handshake()
+        .map_err(|e| SshError::Handshake(e.to_string()))?;
+    // 主机密钥通过校验后才发送凭据，凭据按密钥识别出的序列号选择
+    let host_key = HostKey::from_session(&session)
+        .ok_or_else(|| SshError::Handshake("no host key".to_string()))?;
+    let serial = verify_host_key(target, &host_key)?;
+    let credential = resolve_credential(
+        &SshTarget {
+            serial,
+            ..target.clone()
+        },
+        config,
+    );
+    if let Some(private_key) = &credential.private_key {
+        session
+            .userauth_pubkey_memory(&credential.username, None, private_key, None)
+            .ok();
+    }
+    if let Some(private_key_file) = &credential.private_key_file
+        && !session.authenticated()
+    {
+        session
+            .userauth_pubkey_file(
+                &credential.username,
+                None,
+                Path::new(private_key_file),
+                None,
+            )
+            .ok();
+    }
+    if let Some(password) = &credential.password
+        && !session.authenticated()
+    {
+        session
+            .userauth_password(&credential.username, password)
+            .map_err(|e| SshError::Auth(e.to_string()))?;
+    }
+    if !session.authenticated() {
+        return Err(SshError::Auth(format!(
+            "no usable credentials for {}",
+            credential.username
+        )));
+    }
+    session.set_keepalive(true, 30);
+    session.set_timeout((config.command_timeout_secs * 1000) as u32);
+    Ok(HostSession { session, host_key })
+}
+
+// 切换为非阻塞模式交替读取标准输出和标准错误，避免一方写满通道窗口后另一方一直等待；
+// 从命令开始超过命令超时时间仍未结束，或设置了 idle_timeout 且超过该时间没有任何输出时返回 Timeout
+fn read_output(
+    session: &Session,
+    channel: &mut Channel,
+    idle_timeout: Option<Duration>,
+) -> Result<(String, String), SshError> {
+    let timeout = Duration::from_millis(session.timeout() as u64);
+    let started = Instant::now();
+    let mut last_output = started;
+    let mut streams = [Vec::new(), Vec::new()];
+    let mut buf = [0u8; 8192];
+    session.set_blocking(false);
+    let result = loop {
+        let mut progressed = false;
+        let mut error = None;
+        for (stream_id, output) in streams.iter_mut().enumerate() {
+            match channel.stream(stream_id as i32).read(&mut buf) {
+                Ok(0) => {}
+                Ok(n) => {
+                    output.extend_from_slice(&buf[..n]);
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => error = Some(SshError::Channel(e.to_string())),
+            }
+        }
+        if let Some(e) = error {
+            break Err(e);
+        }
+        if progressed {
+            last_output = Instant::now();
+        } else if channel.eof() {
+            break Ok(());
+        }
+        // 持续输出的命令同样受命令超时时间限制
+        if !timeout.is_zero() && started.elapsed() >= timeout {
+            break Err(SshError::Timeout);
+        }
+        if idle_timeout.is_some_and(|idle| last_output.elapsed() >= idle) {
+            break Err(SshError::Timeout);
+        }
+        if !progressed {
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+    session.set_blocking(true);
+    result?;
+    let [stdout, stderr] = streams.map(|output| String::from_utf8_lossy(&output).into_owned());
+    Ok((stdout, stderr))
+}
+
+// 在已建立的连接上执行命令，分别收集标准输出和标准错误
+fn exec(session: &Session, command: &str) -> Result<CommandOutput, SshError> {
+    let mut channel = session.channel_session()?;
+    channel.exec(command)?;
+    let idle_timeout = ssh_config().idle_timeout_secs.map(Duration::from_secs);
+    let (stdout, stderr) = read_output(session, &mut channel, idle_timeout)?;
+    channel.wait_close()?;
+    Ok(CommandOutput {
+        exit_status: channel.exit_status()?,
+        stdout,
+        stderr,
+    })
+}
+
+fn cached_session(key: &str) -> Option<Arc<Mutex<HostSession>>> {
+    SESSIONS.lock().unwrap().get(key).cloned()
+}
+
+fn drop_session(key: &str) {
+    SESSIONS.lock().unwrap().remove(key);
+}
+
+fn run_blocking(target: &SshTarget, command: &str) -> Result<CommandOutput, SshError> {
+    let key = target.session_key();
+    // 优先复用已有连接，连接失效（例如主机已重启）时重新连接后重试一次
+    if let Some(session) = cached_session(&key) {
+        let session = session.lock().unwrap();
+        // 复用前发送 keepalive ，空闲期间已断开的连接在这里发现并重新连接
+        let result = session
+            .session
+            .keepalive_send()
+            .map_err(SshError::from)
+            .and_then(|_| exec(&session.session, command));
+        drop(session);
+        match result {
+            Ok(output) => return Ok(output),
+            Err(SshError::Timeout) => {
+                drop_session(&key);
+                return Err(SshError::Timeout);
+            }
+            Err(_) => drop_session(&key),
+        }
+    }
+    let session = Arc::new(Mutex::new(connect(target, &ssh_config())?));
+    SESSIONS
+        .lock()
+        .unwrap()
+        .insert(key.clone(), session.clone());
+    let result = exec(&session.lock().unwrap().session, command);
+    if result.is_err() {
+        drop_session(&key);
+    }
+    result
+}
+
+// SSH 到指定主机并运行命令，返回退出码、标准输出和标准错误
+pub async fn run_ssh_command_on_host(
+    target: &SshTarget,
+    command: &str,
+) -> Result<CommandOutput, SshError> {
+    let ip_addr = &target.ip_address;
+    let blocking_target = target.clone();
+    let cmd = command.to_string();
+    let result = tokio::task::spawn_blocking(move || run_blocking(&blocking_target, &cmd))
+        .await
+        .unwrap_or_else(|e| Err(SshError::Channel(e.to_string())));
+    match &result {
+        Err(e) => println!(
+            "[INFO] Run SSH command \"{}\" on host {} failed: {}",
+            command, ip_addr, e
+        ),
+        Ok(output) if !output.success() => println!(
+            "[INFO] Run SSH command \"{}\" on host {} exited with {}: {}",
+            command,
+            ip_addr,
+            output.exit_status,
+            output.stderr.trim()
+        ),
+        Ok(_) => {}
+    }
+    result
+}
+
+fn pin_blocking(target: &SshTarget, serial: &str) -> Result<(), SshError> {
+    let key = target.session_key();
+    let Some(session) = cached_session(&key) else {
+        return Err(SshError::Channel("not connected".to_string()));
+    };
+    let Some(db_pool) = DB_POOL.get() else {
+        return Ok(());
+    };
+    let host_key = session.lock().unwrap().host_key.clone();
+    let result = host_keys::pin(
+        &db_pool.get().unwrap(),
+        &target.ip_address,
+        target.phase,
+        serial,
+        &host_key,
+    );
+    if result.is_err() {
+        drop_session(&key);
+    }
+    Ok(result?)
+}
+
+// 主机报告序列号后固定当前连接的主机密钥，与已固定的密钥不符时断开连接
+pub async fn pin_host_key(target: &SshTarget, serial: &str) -> Result<(), SshError> {
+    let blocking_target = target.clone();
+    let serial = serial.to_string();
+    let result = tokio::task::spawn_blocking(move || pin_blocking(&blocking_target, &serial))
+        .await
+        .unwrap_or_else(|e| Err(SshError::Channel(e.to_string())));
+    if let Err(e) = &result {
+        println!("[INFO] Pin host key of {} failed: {}", target.ip_address, e);
+    }
+    result
+}
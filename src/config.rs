@@ -0,0 +1,596 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 配置文件与命令行参数：优先级为 命令行 > 环境变量 > 配置文件 > 默认值
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::install_state::Progress;
+use crate::ipxe_script::LocalBoot;
+use crate::redfish::BootOverride;
+use crate::template;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "CloudBoot Lite (Clientless Edition)")]
+pub struct Cli {
+    /// TOML 配置文件路径
+    #[arg(long, env = "CLOUDBOOT_CONFIG")]
+    config: Option<PathBuf>,
+    /// HTTP 服务监听地址，例如 127.0.0.1:8000
+    #[arg(long, env = "CLOUDBOOT_BIND")]
+    bind: Option<String>,
+    /// 管理接口监听地址，例如 127.0.0.1:8001
+    #[arg(long, env = "CLOUDBOOT_ADMIN_BIND")]
+    admin_bind: Option<String>,
+    /// SQLite 数据库文件路径
+    #[arg(long, env = "CLOUDBOOT_DB_PATH")]
+    db_path: Option<String>,
+    /// 数据库连接池大小
+    #[arg(long, env = "CLOUDBOOT_DB_POOL_SIZE")]
+    db_pool_size: Option<u32>,
+    /// dhcpd.leases 文件路径
+    #[arg(long, env = "CLOUDBOOT_LEASES_FILE")]
+    leases_file: Option<String>,
+    /// 主机发现间隔（秒）
+    #[arg(long, env = "CLOUDBOOT_DISCOVERY_INTERVAL")]
+    discovery_interval: Option<u64>,
+    /// 装机进度控制间隔（秒）
+    #[arg(long, env = "CLOUDBOOT_PROGRESS_INTERVAL")]
+    progress_interval: Option<u64>,
+    /// SSH 登录密码
+    #[arg(long, env = "CLOUDBOOT_SSH_PASSWORD", hide_env_values = true)]
+    ssh_password: Option<String>,
+    /// SSH 登录私钥路径
+    #[arg(long, env = "CLOUDBOOT_SSH_PRIVATE_KEY")]
+    ssh_private_key: Option<String>,
+    /// IPMI 登录密码
+    #[arg(long, env = "CLOUDBOOT_IPMI_PASSWORD", hide_env_values = true)]
+    ipmi_password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub discovery: DiscoveryConfig,
+    pub progress: ProgressConfig,
+    pub ssh: SshConfig,
+    pub ipmi: IpmiConfig,
+    pub redfish: RedfishConfig,
+    pub templates: TemplateConfig,
+    pub ipxe: IpxeConfig,
+    pub installer: InstallerConfig,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    // 装机接口，由 nginx 代理给装机网络中的主机
+    pub bind: String,
+    // 管理接口，只应监听本机或管理网络
+    pub admin_bind: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1:8000".to_string(),
+            admin_bind: "127.0.0.1:8001".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+    pub pool_size: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            path: "./cloudboot-lce.db".to_string(),
+            pool_size: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    pub leases_file: String,
+    pub interval_secs: u64,
+    pub concurrency: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            leases_file: "/var/lib/dhcpd/dhcpd.leases".to_string(),
+            interval_secs: 10,
+            concurrency: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProgressConfig {
+    pub interval_secs: u64,
+    // 每台主机最多安装次数，超时后未达到该次数时重新排队
+    pub max_attempts: u32,
+    // 装机后下发网络配置时，等待业务地址生效的最长时间（秒），超过后主机自动回滚到原来的网络配置
+    pub network_rollback_secs: u64,
+    pub timeouts: StageTimeouts,
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        ProgressConfig {
+            interval_secs: 10,
+            max_attempts: 1,
+            network_rollback_secs: 120,
+            timeouts: StageTimeouts::default(),
+        }
+    }
+}
+
+// 各安装阶段的超时时间（秒），0 表示不限制
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StageTimeouts {
+    pub rebooting_to_kickstart: u64,
+    pub kickstart_loaded: u64,
+    pub pre_install_finished: u64,
+    pub post_install_finished: u64,
+    pub install_finished: u64,
+    pub rebooted_to_system: u64,
+}
+
+impl Default for StageTimeouts {
+    fn default() -> Self {
+        StageTimeouts {
+            rebooting_to_kickstart: 1800,
+            kickstart_loaded: 1800,
+            pre_install_finished: 7200,
+            post_install_finished: 3600,
+            install_finished: 1800,
+            rebooted_to_system: 3600,
+        }
+    }
+}
+
+impl StageTimeouts {
+    // 返回该阶段的超时时间，不限制时返回 None
+    pub fn for_stage(&self, progress: Progress) -> Option<u64> {
+        let secs = match progress {
+            Progress::RebootingToKickstart => self.rebooting_to_kickstart,
+            Progress::KickstartLoaded => self.kickstart_loaded,
+            Progress::PreInstallFinished => self.pre_install_finished,
+            Progress::PostInstallFinished => self.post_install_finished,
+            Progress::InstallFinished => self.install_finished,
+            Progress::RebootedToSystem => self.rebooted_to_system,
+            _ => 0,
+        };
+        Some(secs).filter(|secs| *secs > 0)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    pub user: String,
+    pub password: String,
+    pub private_key: Option<String>,
+    // 加密数据库中 SSH 凭据的密钥文件（base64 编码的 32 字节）
+    pub secret_key_file: Option<String>,
+    pub connect_timeout_secs: u64,
+    // 从命令开始计时，超过后命令失败
+    pub command_timeout_secs: u64,
+    // 命令没有任何输出超过该时间时提前失败，未设置时只按 command_timeout_secs 计时
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        SshConfig {
+            user: "root".to_string(),
+            // 不提供默认密码，未配置私钥时必须在配置文件、环境变量或命令行中设置
+            password: String::new(),
+            private_key: None,
+            secret_key_file: None,
+            connect_timeout_secs: 3,
+            command_timeout_secs: 60,
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+// 带外管理（BMC）登录参数
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpmiConfig {
+    pub user: String,
+    pub password: String,
+    // 单条 ipmitool 命令超时（秒）
+    pub timeout_secs: u64,
+}
+
+impl Default for IpmiConfig {
+    fn default() -> Self {
+        IpmiConfig {
+            user: "admin".to_string(),
+            password: String::new(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+// Redfish 参数，凭据与 IPMI 共用
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedfishConfig {
+    // 使用 Redfish 的厂商，与主机的 vendor 完全匹配，主机可以单独指定 bmc_protocol
+    pub vendors: Vec<String>,
+    // 重启进入装机环境时的一次性启动覆盖：pxe 或 uefi_http
+    pub boot_target: BootOverride,
+    pub timeout_secs: u64,
+}
+
+impl Default for RedfishConfig {
+    fn default() -> Self {
+        RedfishConfig {
+            vendors: Vec::new(),
+            boot_target: BootOverride::Pxe,
+            timeout_secs: 10,
+        }
+    }
+}
+
+// iPXE 脚本等模板中可以使用的全局变量
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateConfig {
+    // 本服务对主机可见的地址，模板中为 {{ server_url }}
+    pub server_url: String,
+    // 通过 /api/os 导入 iPXE 脚本和应答文件模板时，文件必须位于该目录中
+    pub import_dir: String,
+    // 其他全局变量，与主机字段同名时以主机字段为准
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        TemplateConfig {
+            server_url: "http://osinstall.pxe".to_string(),
+            import_dir: "/opt/cloudboot-lce".to_string(),
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+// /api/ipxe/{serial} 在主机不需要安装时返回的脚本
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpxeConfig {
+    // 各架构（iPXE 的 ${buildarch} ，例如 x86_64 、arm64）进入装机环境 BootOS 的脚本模板路径
+    pub bootos: BTreeMap<String, String>,
+    // 已装好的主机从本地磁盘启动的方式：auto 、exit 或 sanboot
+    pub local_boot: LocalBoot,
+}
+
+// 渲染 kickstart 、preseed 等应答文件时使用的参数
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct InstallerConfig {
+    // 装好的操作系统的 root 密码哈希（openssl passwd -6 生成），模板中为 {{ root_password_hash }}
+    pub root_password_hash: Option<String>,
+}
+
+impl Config {
+    // 读取配置文件
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read config file {}: {e}", path.display()))?;
+        toml::from_str(&content).map_err(|e| format!("invalid config file {}: {e}", path.display()))
+    }
+
+    // 用命令行参数和环境变量覆盖配置文件中的值
+    fn apply_cli(&mut self, cli: Cli) {
+        if let Some(bind) = cli.bind {
+            self.server.bind = bind;
+        }
+        if let Some(admin_bind) = cli.admin_bind {
+            self.server.admin_bind = admin_bind;
+        }
+        if let Some(db_path) = cli.db_path {
+            self.database.path = db_path;
+        }
+        if let Some(db_pool_size) = cli.db_pool_size {
+            self.database.pool_size = db_pool_size;
+        }
+        if let Some(leases_file) = cli.leases_file {
+            self.discovery.leases_file = leases_file;
+        }
+        if let Some(discovery_interval) = cli.discovery_interval {
+            self.discovery.interval_secs = discovery_interval;
+        }
+        if let Some(progress_interval) = cli.progress_interval {
+            self.progress.interval_secs = progress_interval;
+        }
+        if let Some(ssh_password) = cli.ssh_password {
+            self.ssh.password = ssh_password;
+        }
+        if let Some(ssh_private_key) = cli.ssh_private_key {
+            self.ssh.private_key = Some(ssh_private_key);
+        }
+        if let Some(ipmi_password) = cli.ipmi_password {
+            self.ipmi.password = ipmi_password;
+        }
+    }
+
+    // 启动时检查配置是否合法
+    pub fn validate(&self) -> Result<(), String> {
+        let bind = self
+            .server
+            .bind
+            .parse::<SocketAddr>()
+            .map_err(|_| format!("server.bind: invalid address {}", self.server.bind))?;
+        let admin_bind = self.server.admin_bind.parse::<SocketAddr>().map_err(|_| {
+            format!(
+                "server.admin_bind: invalid address {}",
+                self.server.admin_bind
+            )
+        })?;
+        if admin_bind == bind {
+            return Err("server.admin_bind must differ from server.bind".to_string());
+        }
+        if self.database.path.trim().is_empty() {
+            return Err("database.path must not be empty".to_string());
+        }
+        if self.database.pool_size == 0 {
+            return Err("database.pool_size must be at least 1".to_string());
+        }
+        if !Path::new(&self.discovery.leases_file).is_file() {
+            return Err(format!(
+                "discovery.leases_file: {} does not exist",
+                self.discovery.leases_file
+            ));
+        }
+        if self.discovery.interval_secs == 0 {
+            return Err("discovery.interval_secs must be at least 1".to_string());
+        }
+        if self.discovery.concurrency == 0 {
+            return Err("discovery.concurrency must be at least 1".to_string());
+        }
+        if self.progress.interval_secs == 0 {
+            return Err("progress.interval_secs must be at least 1".to_string());
+        }
+        if self.progress.max_attempts == 0 {
+            return Err("progress.max_attempts must be at least 1".to_string());
+        }
+        if self.progress.network_rollback_secs == 0 {
+            return Err("progress.network_rollback_secs must be at least 1".to_string());
+        }
+        if self.ipmi.timeout_secs == 0 {
+            return Err("ipmi.timeout_secs must be at least 1".to_string());
+        }
+        if self.redfish.timeout_secs == 0 {
+            return Err("redfish.timeout_secs must be at least 1".to_string());
+        }
+        if self.templates.server_url.trim().is_empty() {
+            return Err("templates.server_url must not be empty".to_string());
+        }
+        if let Some(name) = self
+            .templates
+            .vars
+            .keys()
+            .find(|name| !template::is_valid_name(name))
+        {
+            return Err(format!("templates.vars: invalid variable name {name:?}"));
+        }
+        for (arch, path) in &self.ipxe.bootos {
+            if !Path::new(path).is_file() {
+                return Err(format!("ipxe.bootos.{arch}: {path} does not exist"));
+            }
+        }
+        if let Some(hash) = &self.installer.root_password_hash
+            && !hash.starts_with('$')
+        {
+            return Err(
+                "installer.root_password_hash must be a crypt hash, e.g. from openssl passwd -6"
+                    .to_string(),
+            );
+        }
+        if self.ssh.user.is_empty() {
+            return Err("ssh.user must not be empty".to_string());
+        }
+        match &self.ssh.private_key {
+            Some(private_key) if !Path::new(private_key).is_file() => {
+                return Err(format!("ssh.private_key: {private_key} does not exist"));
+            }
+            None if self.ssh.password.is_empty() => {
+                return Err("ssh.password or ssh.private_key must be set".to_string());
+            }
+            _ => {}
+        }
+        if let Some(secret_key_file) = &self.ssh.secret_key_file
+            && !Path::new(secret_key_file).is_file()
+        {
+            return Err(format!(
+                "ssh.secret_key_file: {secret_key_file} does not exist"
+            ));
+        }
+        if self.ssh.connect_timeout_secs == 0
+            || self.ssh.command_timeout_secs == 0
+            || self.ssh.idle_timeout_secs == Some(0)
+        {
+            return Err("ssh timeouts must be at least 1 second".to_string());
+        }
+        Ok(())
+    }
+}
+
+// 读取配置文件并用命令行参数和环境变量覆盖
+fn from_cli(cli: Cli) -> Result<Config, String> {
+    let mut config = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    config.apply_cli(cli);
+    Ok(config)
+}
+
+// 解析命令行参数并加载配置
+pub fn load() -> Result<Config, String> {
+    let config = from_cli(Cli::parse())?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("cloudboot-{name}-{}.toml", std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    // 除了被测字段外都合法的配置
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.discovery.leases_file = "Cargo.toml".to_string();
+        config.ssh.password = "secret".to_string();
+        config
+    }
+
+    #[test]
+    fn cli_overrides_env_and_env_overrides_file() {
+        let path = write_config(
+            "precedence",
+            "[database]\npool_size = 4\n[discovery]\ninterval_secs = 20\n[progress]\ninterval_secs = 30\n",
+        );
+        // 只有这个测试解析命令行参数，设置的环境变量不影响其他测试
+        unsafe {
+            std::env::set_var("CLOUDBOOT_PROGRESS_INTERVAL", "40");
+            std::env::set_var("CLOUDBOOT_DB_POOL_SIZE", "5");
+        }
+        let cli = Cli::try_parse_from([
+            "cloudboot-lce",
+            "--config",
+            path.to_str().unwrap(),
+            "--db-pool-size",
+            "6",
+        ])
+        .unwrap();
+        let config = from_cli(cli).unwrap();
+        unsafe {
+            std::env::remove_var("CLOUDBOOT_PROGRESS_INTERVAL");
+            std::env::remove_var("CLOUDBOOT_DB_POOL_SIZE");
+        }
+        fs::remove_file(path).ok();
+        // 命令行 > 环境变量 > 配置文件 > 默认值
+        assert_eq!(config.database.pool_size, 6);
+        assert_eq!(config.progress.interval_secs, 40);
+        assert_eq!(config.discovery.interval_secs, 20);
+        assert_eq!(config.discovery.concurrency, 10);
+    }
+
+    #[test]
+    fn progress_config_fills_defaults() {
+        let path = write_config(
+            "progress",
+            "[progress]\nmax_attempts = 3\n[progress.timeouts]\nkickstart_loaded = 60\nrebooted_to_system = 0\n",
+        );
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(path).ok();
+        let progress = config.progress;
+        assert_eq!(progress.max_attempts, 3);
+        assert_eq!(progress.interval_secs, 10);
+        assert_eq!(
+            progress.timeouts.for_stage(Progress::KickstartLoaded),
+            Some(60)
+        );
+        assert_eq!(
+            progress.timeouts.for_stage(Progress::PreInstallFinished),
+            Some(7200)
+        );
+        // 0 表示不限制，不在安装过程中的进度没有超时
+        assert_eq!(
+            progress.timeouts.for_stage(Progress::RebootedToSystem),
+            None
+        );
+        assert_eq!(progress.timeouts.for_stage(Progress::NotConfigured), None);
+        assert_eq!(progress.timeouts.for_stage(Progress::Installed), None);
+
+        let path = write_config("unknown", "[progress.timeouts]\nkickstart = 60\n");
+        assert!(Config::from_file(&path).is_err());
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        valid_config().validate().unwrap();
+        type Mutation = fn(&mut Config);
+        let cases: [(Mutation, &str); 8] = [
+            (|c| c.ssh.password = String::new(), "ssh.password"),
+            (
+                |c| c.server.admin_bind = c.server.bind.clone(),
+                "admin_bind",
+            ),
+            (|c| c.server.bind = "localhost".to_string(), "server.bind"),
+            (|c| c.database.pool_size = 0, "database.pool_size"),
+            (
+                |c| c.discovery.leases_file = "/nonexistent".to_string(),
+                "leases_file",
+            ),
+            (|c| c.progress.max_attempts = 0, "max_attempts"),
+            (|c| c.ssh.idle_timeout_secs = Some(0), "ssh timeouts"),
+            (
+                |c| c.installer.root_password_hash = Some("plain".to_string()),
+                "root_password_hash",
+            ),
+        ];
+        for (mutate, expected) in cases {
+            let mut config = valid_config();
+            mutate(&mut config);
+            let error = config.validate().unwrap_err();
+            assert!(error.contains(expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn default_password_must_be_configured() {
+        // 未配置密码和私钥时拒绝启动，显式配置的密码可以使用
+        let mut config = Config::default();
+        config.discovery.leases_file = "Cargo.toml".to_string();
+        assert!(config.validate().unwrap_err().contains("ssh.password"));
+        let path = write_config("password", "[ssh]\npassword = \"abc123\"\n");
+        let mut config = Config::from_file(&path).unwrap();
+        fs::remove_file(path).ok();
+        config.discovery.leases_file = "Cargo.toml".to_string();
+        config.validate().unwrap();
+    }
+}
@@ -0,0 +1,308 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// SSH 凭据管理：按装机阶段、操作系统和序列号选择凭据，密码和私钥加密后存入数据库
+use actix_web::{HttpResponse, Responder, web};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::config::SshConfig;
+use crate::install_state::Progress;
+
+// 加密后的字段前缀
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+static SECRET_KEY: OnceLock<Key<Aes256Gcm>> = OnceLock::new();
+
+// 装机阶段：装机环境（BootOS 与 kickstart 安装程序）和装好的操作系统使用不同的凭据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallPhase {
+    Installer,
+    System,
+}
+
+impl InstallPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallPhase::Installer => "installer",
+            InstallPhase::System => "system",
+        }
+    }
+
+    // 根据安装进度判断主机当前运行的环境
+    pub fn from_progress(progress: Option<i32>) -> Self {
+        match progress {
+            Some(p) if p >= Progress::RebootedToSystem as i32 => InstallPhase::System,
+            _ => InstallPhase::Installer,
+        }
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            InstallPhase::Installer => InstallPhase::System,
+            InstallPhase::System => InstallPhase::Installer,
+        }
+    }
+}
+
+impl fmt::Display for InstallPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 解密后的 SSH 凭据
+#[derive(Clone)]
+pub struct Credential {
+    pub username: String,
+    pub password: Option<String>,
+    // 私钥内容（PEM）
+    pub private_key: Option<String>,
+    // 私钥文件路径，仅来自配置文件
+    pub private_key_file: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CredentialRecord {
+    id: i64,
+    phase: String,
+    os: Option<String>,
+    serial: Option<String>,
+    username: String,
+    has_password: bool,
+    has_private_key: bool,
+}
+
+#[derive(Deserialize)]
+pub struct NewCredential {
+    phase: InstallPhase,
+    os: Option<String>,
+    serial: Option<String>,
+    username: String,
+    password: Option<String>,
+    private_key: Option<String>,
+}
+
+// 读取 base64 编码的 32 字节 AES-256 密钥
+pub fn load_secret_key(path: &str) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("cannot read secret key {path}: {e}"))?;
+    let key = BASE64
+        .decode(content.trim())
+        .map_err(|e| format!("secret key {path} is not valid base64: {e}"))?;
+    if key.len() != 32 {
+        return Err(format!(
+            "secret key {path} must be 32 bytes, got {}",
+            key.len()
+        ));
+    }
+    SECRET_KEY
+        .set(*Key::<Aes256Gcm>::from_slice(&key))
+        .map_err(|_| "secret key already loaded".to_string())
+}
+
+pub(crate) fn encrypt(plaintext: &str) -> Result<String, String> {
+    let key = SECRET_KEY
+        .get()
+        .ok_or("ssh.secret_key_file is not configured, refusing to store secrets")?;
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|e| format!("encrypt failed: {e}"))?;
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+    Ok(format!("{ENCRYPTED_PREFIX}{}", BASE64.encode(data)))
+}
+
+pub(crate) fn decrypt(value: &str) -> Result<String, String> {
+    let encoded = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .ok_or("secret is not encrypted")?;
+    let key = SECRET_KEY.get().ok_or("secret key is not loaded")?;
+    let data = BASE64
+        .decode(encoded)
+        .map_err(|e| format!("invalid secret: {e}"))?;
+    if data.len() < 12 {
+        return Err("invalid secret: too short".to_string());
+    }
+    let (nonce, ciphertext) = data.split_at(12);
+    let plaintext = Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "decrypt failed, wrong secret key?".to_string())?;
+    String::from_utf8(plaintext).map_err(|e| format!("invalid secret: {e}"))
+}
+
+// 配置文件中的默认凭据
+pub fn resolve_from_config(config: &SshConfig) -> Credential {
+    Credential {
+        username: config.user.clone(),
+        password: Some(config.password.clone()).filter(|p| !p.is_empty()),
+        private_key: None,
+        private_key_file: config.private_key.clone(),
+    }
+}
+
+// 选择最匹配的凭据：序列号匹配优先于操作系统匹配，均未匹配时使用通用凭据，数据库中没有时使用配置文件
+pub fn resolve(
+    conn: &Connection,
+    phase: InstallPhase,
+    os: Option<&str>,
+    serial: Option<&str>,
+    config: &SshConfig,
+) -> Credential {
+    let row: Option<(i64, String, Option<String>, Option<String>)> = conn
+        .query_row(
+            r#"
+            SELECT id, username, password, private_key
+            FROM ssh_credentials
+            WHERE phase = ?1
+              AND (os IS NULL OR os = ?2)
+              AND (serial IS NULL OR serial = ?3)
+            ORDER BY serial IS NOT NULL DESC, os IS NOT NULL DESC
+            LIMIT 1
+            "#,
+            params![phase.as_str(), os, serial],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .unwrap();
+    let Some((id, username, password, private_key)) = row else {
+        return resolve_from_config(config);
+    };
+    let decrypt_field = |value: Option<String>| {
+        value.and_then(|v| match decrypt(&v) {
+            Ok(plaintext) => Some(plaintext),
+            Err(e) => {
+                println!("[ERROR] Cannot decrypt SSH credential {id}: {e}");
+                None
+            }
+        })
+    };
+    Credential {
+        username,
+        password: decrypt_field(password),
+        private_key: decrypt_field(private_key),
+        private_key_file: None,
+    }
+}
+
+// 处理 GET /api/credentials ，不返回密码和私钥
+pub async fn list_credentials(db_pool: web::Data<Pool<SqliteConnectionManager>>) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, phase, os, serial, username, password IS NOT NULL, private_key IS NOT NULL FROM ssh_credentials ORDER BY id",
+        )
+        .unwrap();
+    let records: Vec<CredentialRecord> = stmt
+        .query_map([], |row| {
+            Ok(CredentialRecord {
+                id: row.get(0)?,
+                phase: row.get(1)?,
+                os: row.get(2)?,
+                serial: row.get(3)?,
+                username: row.get(4)?,
+                has_password: row.get(5)?,
+                has_private_key: row.get(6)?,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(records)
+}
+
+// 处理 POST /api/credentials ，同一阶段、操作系统和序列号的凭据会被替换
+pub async fn create_credential(
+    credential: web::Json<NewCredential>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let mut credential = credential.into_inner();
+    // 空字符串视为不限定
+    credential.os = credential.os.filter(|os| !os.trim().is_empty());
+    credential.serial = credential.serial.filter(|serial| !serial.trim().is_empty());
+    if credential.username.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "username must not be empty" }));
+    }
+    if credential.password.is_none() && credential.private_key.is_none() {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "password or private_key must be set" }));
+    }
+    let password = match credential.password.as_deref().map(encrypt).transpose() {
+        Ok(password) => password,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let private_key = match credential.private_key.as_deref().map(encrypt).transpose() {
+        Ok(private_key) => private_key,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let conn = db_pool.get().unwrap();
+    conn.execute(
+        "DELETE FROM ssh_credentials WHERE phase = ?1 AND os IS ?2 AND serial IS ?3",
+        params![credential.phase.as_str(), credential.os, credential.serial],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO ssh_credentials (phase, os, serial, username, password, private_key) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            credential.phase.as_str(),
+            credential.os,
+            credential.serial,
+            credential.username.trim(),
+            password,
+            private_key
+        ],
+    )
+    .unwrap();
+    let id = conn.last_insert_rowid();
+    println!(
+        "[INFO] SSH credential {id} stored for phase {} (os: {}, serial: {})",
+        credential.phase,
+        credential.os.as_deref().unwrap_or("*"),
+        credential.serial.as_deref().unwrap_or("*")
+    );
+    HttpResponse::Created().json(json!({ "id": id }))
+}
+
+// 处理 DELETE /api/credentials/{id}
+pub async fn delete_credential(
+    id: web::Path<i64>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let conn = db_pool.get().unwrap();
+    let deleted = conn
+        .execute("DELETE FROM ssh_credentials WHERE id = ?1", params![id])
+        .unwrap();
+    if deleted == 0 {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("credential {id} not found") }));
+    }
+    println!("[INFO] SSH credential {id} deleted");
+    HttpResponse::NoContent().finish()
+}
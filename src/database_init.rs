@@ -16,6 +16,25 @@
 
 use rusqlite::Connection;
 
+// 如果表中不存在该字段则添加，用于升级旧版本数据库
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) {
+    let mut stmt = conn
+        .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+        .unwrap();
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .unwrap()
+        .filter_map(Result::ok)
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )
+        .unwrap();
+    }
+}
+
 // 初始化数据库
 pub fn init_db(conn: &Connection) {
     conn.execute(
@@ -28,19 +47,106 @@ pub fn init_db(conn: &Connection) {
             public_ip_addr TEXT,
             vlan_id INTEGER,
             install_progress INTEGER,
-            last_updated TEXT
+            last_updated TEXT,
+            last_seen TEXT,
+            installer_action TEXT,
+            progress_message TEXT,
+            progress_updated TEXT,
+            install_attempts INTEGER NOT NULL DEFAULT 0,
+            vendor TEXT,
+            bmc_protocol TEXT,
+            boot_mode TEXT,
+            mac_address TEXT,
+            ipxe_version INTEGER,
+            host_group TEXT,
+            kickstart_rebooted TEXT,
+            install_ipxe_version INTEGER
+        )",
+        [],
+    )
+    .unwrap();
+    // 旧版本数据库的 hosts 表缺少的字段
+    add_column_if_missing(conn, "hosts", "last_seen", "TEXT");
+    add_column_if_missing(conn, "hosts", "installer_action", "TEXT");
+    add_column_if_missing(conn, "hosts", "progress_message", "TEXT");
+    add_column_if_missing(conn, "hosts", "progress_updated", "TEXT");
+    add_column_if_missing(
+        conn,
+        "hosts",
+        "install_attempts",
+        "INTEGER NOT NULL DEFAULT 0",
+    );
+    add_column_if_missing(conn, "hosts", "vendor", "TEXT");
+    add_column_if_missing(conn, "hosts", "bmc_protocol", "TEXT");
+    add_column_if_missing(conn, "hosts", "boot_mode", "TEXT");
+    add_column_if_missing(conn, "hosts", "mac_address", "TEXT");
+    add_column_if_missing(conn, "hosts", "ipxe_version", "INTEGER");
+    add_column_if_missing(conn, "hosts", "host_group", "TEXT");
+    add_column_if_missing(conn, "hosts", "kickstart_rebooted", "TEXT");
+    add_column_if_missing(conn, "hosts", "install_ipxe_version", "INTEGER");
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS install_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            serial TEXT NOT NULL,
+            os TEXT,
+            from_progress INTEGER,
+            to_progress INTEGER NOT NULL,
+            source TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            ipxe_version INTEGER
         )",
         [],
     )
     .unwrap();
+    add_column_if_missing(conn, "install_events", "ipxe_version", "INTEGER");
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS install_events_serial ON install_events (serial)",
+        [],
+    )
+    .unwrap();
     conn.execute(
         "CREATE TABLE IF NOT EXISTS ipxe (
             os TEXT PRIMARY KEY,
-            script TEXT
+            script TEXT,
+            bios_script TEXT,
+            active_version INTEGER,
+            installer TEXT,
+            profile TEXT,
+            repo_url TEXT,
+            network_format TEXT
         )",
         [],
     )
     .unwrap();
+    // script 和 bios_script 为旧版本保存的脚本路径，启动时导入 ipxe_versions
+    add_column_if_missing(conn, "ipxe", "bios_script", "TEXT");
+    add_column_if_missing(conn, "ipxe", "active_version", "INTEGER");
+    // 安装程序类型，为空时为 anaconda
+    add_column_if_missing(conn, "ipxe", "installer", "TEXT");
+    // 操作系统配置档和安装源地址
+    add_column_if_missing(conn, "ipxe", "profile", "TEXT");
+    add_column_if_missing(conn, "ipxe", "repo_url", "TEXT");
+    // 装机后网络配置文件的格式，为空时为 keyfile
+    add_column_if_missing(conn, "ipxe", "network_format", "TEXT");
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ipxe_versions (
+            os TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            script TEXT NOT NULL,
+            bios_script TEXT,
+            script_file TEXT,
+            bios_script_file TEXT,
+            answer_template TEXT,
+            answer_template_file TEXT,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (os, version)
+        )",
+        [],
+    )
+    .unwrap();
+    // 与 iPXE 脚本同一版本的应答文件模板
+    add_column_if_missing(conn, "ipxe_versions", "answer_template", "TEXT");
+    add_column_if_missing(conn, "ipxe_versions", "answer_template_file", "TEXT");
     conn.execute(
         "CREATE TABLE IF NOT EXISTS install_queue (
             ipmi_address TEXT PRIMARY KEY
@@ -48,4 +154,97 @@ pub fn init_db(conn: &Connection) {
         [],
     )
     .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS ssh_credentials (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            phase TEXT NOT NULL,
+            os TEXT,
+            serial TEXT,
+            username TEXT NOT NULL,
+            password TEXT,
+            private_key TEXT
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS bmc_credentials (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            vendor TEXT,
+            serial TEXT,
+            username TEXT NOT NULL,
+            password TEXT NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS host_key_pins (
+            serial TEXT NOT NULL,
+            phase TEXT NOT NULL,
+            key_type TEXT NOT NULL,
+            fingerprint TEXT NOT NULL,
+            first_seen TEXT NOT NULL,
+            last_seen TEXT NOT NULL,
+            PRIMARY KEY (serial, phase)
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS host_key_alerts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            serial TEXT,
+            phase TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            key_type TEXT NOT NULL,
+            fingerprint TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS host_nics (
+            serial TEXT NOT NULL,
+            name TEXT NOT NULL,
+            mac_address TEXT NOT NULL,
+            driver TEXT,
+            speed INTEGER,
+            pci_address TEXT,
+            port TEXT,
+            lldp_system TEXT,
+            lldp_port TEXT,
+            collected_at TEXT NOT NULL,
+            PRIMARY KEY (serial, name)
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS nic_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            host_group TEXT,
+            serial TEXT,
+            driver TEXT,
+            port TEXT,
+            min_speed INTEGER,
+            pci_slot TEXT,
+            mac_addresses TEXT,
+            lldp_neighbor TEXT,
+            count INTEGER NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS network_profiles (
+            serial TEXT PRIMARY KEY,
+            profile TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )
+    .unwrap();
 }
@@ -0,0 +1,135 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 命令执行接口：主机发现和装机进度控制通过它访问主机，测试中可替换为 FakeExecutor
+use std::future::Future;
+use tokio::process::Command;
+
+use crate::bmc::{self, BmcError, BmcTarget};
+use crate::boot_mode::BootMode;
+use crate::command_execute::{self, CommandOutput, SshError, SshTarget};
+
+pub trait CommandExecutor: Send + Sync {
+    // 在主机上运行 SSH 命令
+    fn run_ssh_command(
+        &self,
+        target: &SshTarget,
+        command: &str,
+    ) -> impl Future<Output = Result<CommandOutput, SshError>> + Send;
+
+    // 主机报告序列号后固定当前连接的主机密钥
+    fn pin_host_key(
+        &self,
+        target: &SshTarget,
+        serial: &str,
+    ) -> impl Future<Output = Result<(), SshError>> + Send;
+
+    // ping 地址是否可达
+    fn ping(&self, ip_address: &str) -> impl Future<Output = bool> + Send;
+
+    // 通过带内 IPMI 读取主机的带外管理地址
+    fn ipmi_lan_address(&self, target: &SshTarget) -> impl Future<Output = Option<String>> + Send;
+
+    // 通过带内 IPMI 按主机的启动模式设置下次从 PXE 启动并重启主机
+    fn ipmi_pxe_reboot(
+        &self,
+        target: &SshTarget,
+        boot_mode: Option<BootMode>,
+    ) -> impl Future<Output = Result<(), SshError>> + Send;
+
+    // 通过 BMC 设置下次从 PXE 启动并对主机断电重启，不依赖主机上的操作系统
+    fn bmc_pxe_reboot(
+        &self,
+        target: &BmcTarget,
+    ) -> impl Future<Output = Result<(), BmcError>> + Send;
+}
+
+// 生产环境使用的实现：SSH 到真实主机，调用系统 ping 和 ipmitool 命令
+#[derive(Debug, Clone, Default)]
+pub struct SystemExecutor;
+
+impl SystemExecutor {
+    pub fn new() -> Self {
+        SystemExecutor
+    }
+}
+
+impl CommandExecutor for SystemExecutor {
+    async fn run_ssh_command(
+        &self,
+        target: &SshTarget,
+        command: &str,
+    ) -> Result<CommandOutput, SshError> {
+        command_execute::run_ssh_command_on_host(target, command).await
+    }
+
+    async fn pin_host_key(&self, target: &SshTarget, serial: &str) -> Result<(), SshError> {
+        command_execute::pin_host_key(target, serial).await
+    }
+
+    async fn ping(&self, ip_address: &str) -> bool {
+        let result = Command::new("ping")
+            .arg("-c")
+            .arg("1")
+            .arg("-W")
+            .arg("1")
+            .arg(ip_address)
+            .output()
+            .await;
+        match result {
+            Ok(output) if output.status.success() => true,
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                println!(
+                    "[INFO] Ping to {} failed with non-zero exit code. stdout: {}, stderr: {}",
+                    ip_address, stdout, stderr
+                );
+                false
+            }
+            Err(e) => {
+                println!("[ERROR] Failed to execute ping command: {}", e);
+                false
+            }
+        }
+    }
+
+    async fn ipmi_lan_address(&self, target: &SshTarget) -> Option<String> {
+        self.run_ssh_command(
+            target,
+            "ipmitool lan print | grep \"^IP Address\" | grep -v \"Source\" | awk '{print $4}'",
+        )
+        .await
+        .ok()
+        .and_then(CommandOutput::into_stdout)
+    }
+
+    async fn ipmi_pxe_reboot(
+        &self,
+        target: &SshTarget,
+        boot_mode: Option<BootMode>,
+    ) -> Result<(), SshError> {
+        let mut bootdev = vec!["ipmitool", "chassis", "bootdev", "pxe"];
+        bootdev.extend(BootMode::ipmi_bootdev_options(boot_mode));
+        self.run_ssh_command(target, &format!("{};/sbin/reboot", bootdev.join(" ")))
+            .await
+            .map(|_| ())
+    }
+
+    async fn bmc_pxe_reboot(&self, target: &BmcTarget) -> Result<(), BmcError> {
+        bmc::pxe_reboot(target).await
+    }
+}
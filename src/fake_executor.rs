@@ -0,0 +1,364 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 用于测试的命令执行实现：在内存中模拟主机，支持 cat 和 echo 重定向读写文件，其余命令按预设应答
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use crate::bmc::{BmcError, BmcTarget};
+use crate::boot_mode::{self, BootMode};
+use crate::command_execute::{CommandOutput, SshError, SshTarget};
+use crate::credentials::InstallPhase;
+use crate::executor::CommandExecutor;
+use crate::host_keys::{self, HostKey};
+
+pub const PRODUCT_SERIAL_FILE: &str = "/sys/devices/virtual/dmi/id/product_serial";
+
+// 模拟的主机
+#[derive(Debug, Clone)]
+pub struct FakeHost {
+    pub serial: String,
+    pub ipmi_address: Option<String>,
+    // 主机当前运行的环境，SSH 使用另一阶段的凭据时认证失败
+    pub phase: InstallPhase,
+    // 为 false 时 SSH 连接失败
+    pub reachable: bool,
+    pub files: HashMap<String, String>,
+    // 命令包含指定字符串时返回预设结果，先添加的优先
+    pub responses: Vec<(String, CommandOutput)>,
+    // 收到的所有 SSH 命令
+    pub commands: Vec<String>,
+    pub reboots: u32,
+    // 为 false 时 BMC 无响应
+    pub bmc_reachable: bool,
+    // 通过 BMC 断电重启的次数
+    pub power_cycles: u32,
+    // 主机固件的启动模式
+    pub boot_mode: BootMode,
+    // 最近一次 PXE 重启时请求的启动模式，未指定时为空
+    pub pxe_boot_mode: Option<BootMode>,
+    // 装机环境的主机密钥指纹，每次从 PXE 启动时重新生成
+    pub installer_key: String,
+    // 操作系统的主机密钥指纹
+    pub system_key: String,
+}
+
+impl FakeHost {
+    pub fn new(serial: &str, ipmi_address: Option<&str>) -> Self {
+        FakeHost {
+            serial: serial.to_string(),
+            ipmi_address: ipmi_address.map(String::from),
+            phase: InstallPhase::Installer,
+            reachable: true,
+            files: HashMap::from([(PRODUCT_SERIAL_FILE.to_string(), format!("{serial}\n"))]),
+            responses: Vec::new(),
+            commands: Vec::new(),
+            reboots: 0,
+            bmc_reachable: true,
+            power_cycles: 0,
+            boot_mode: BootMode::Uefi,
+            pxe_boot_mode: None,
+            installer_key: format!("SHA256:{serial}-installer-0"),
+            system_key: format!("SHA256:{serial}-system"),
+        }
+    }
+
+    pub fn with_file(mut self, path: &str, content: &str) -> Self {
+        self.set_file(path, content);
+        self
+    }
+
+    pub fn set_file(&mut self, path: &str, content: &str) {
+        self.files.insert(path.to_string(), content.to_string());
+    }
+
+    pub fn file(&self, path: &str) -> Option<&str> {
+        self.files.get(path).map(|content| content.trim())
+    }
+
+    pub fn respond(&mut self, pattern: &str, stdout: &str) {
+        self.responses.push((
+            pattern.to_string(),
+            CommandOutput {
+                exit_status: 0,
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+            },
+        ));
+    }
+
+    // 模拟从 PXE 重启：清空 /tmp 并进入装机环境，装机环境生成新的主机密钥
+    pub fn pxe_reboot(&mut self) {
+        self.reboots += 1;
+        self.phase = InstallPhase::Installer;
+        self.files.retain(|path, _| !path.starts_with("/tmp/"));
+        self.installer_key = format!("SHA256:{}-installer-{}", self.serial, self.reboots);
+    }
+
+    // 主机当前环境的主机密钥
+    pub fn host_key(&self) -> HostKey {
+        HostKey {
+            key_type: "ssh-ed25519",
+            fingerprint: match self.phase {
+                InstallPhase::Installer => self.installer_key.clone(),
+                InstallPhase::System => self.system_key.clone(),
+            },
+        }
+    }
+
+    fn run(&mut self, command: &str) -> CommandOutput {
+        self.commands.push(command.to_string());
+        if let Some((_, output)) = self
+            .responses
+            .iter()
+            .find(|(pattern, _)| command.contains(pattern.as_str()))
+        {
+            return output.clone();
+        }
+        let command = command.trim();
+        if command == boot_mode::DETECT_COMMAND {
+            return output(0, &format!("{}\n", self.boot_mode), "");
+        }
+        if let Some(path) = command.strip_prefix("cat ") {
+            return match self.files.get(path.trim()) {
+                Some(content) => output(0, content, ""),
+                None => output(1, "", &format!("cat: {path}: No such file or directory")),
+            };
+        }
+        if let Some(rest) = command.strip_prefix("echo ")
+            && let Some((value, path)) = rest.split_once('>')
+        {
+            let value = value.trim().trim_matches('"');
+            self.set_file(path.trim(), &format!("{value}\n"));
+            return output(0, "", "");
+        }
+        output(0, "", "")
+    }
+}
+
+fn output(exit_status: i32, stdout: &str, stderr: &str) -> CommandOutput {
+    CommandOutput {
+        exit_status,
+        stdout: stdout.to_string(),
+        stderr: stderr.to_string(),
+    }
+}
+
+// 以 IP 地址为键的模拟主机集合
+#[derive(Default)]
+pub struct FakeExecutor {
+    hosts: Mutex<HashMap<String, FakeHost>>,
+    pingable: Mutex<HashSet<String>>,
+    // 设置后与真实连接一样校验并固定主机密钥
+    db_pool: Option<Pool<SqliteConnectionManager>>,
+}
+
+impl FakeExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_host_keys(mut self, db_pool: Pool<SqliteConnectionManager>) -> Self {
+        self.db_pool = Some(db_pool);
+        self
+    }
+
+    pub fn add_host(&self, ip_address: &str, host: FakeHost) {
+        self.hosts
+            .lock()
+            .unwrap()
+            .insert(ip_address.to_string(), host);
+    }
+
+    // 修改模拟主机，用于模拟装机程序在主机上的动作
+    pub fn with_host<T>(&self, ip_address: &str, f: impl FnOnce(&mut FakeHost) -> T) -> T {
+        f(self
+            .hosts
+            .lock()
+            .unwrap()
+            .get_mut(ip_address)
+            .expect("unknown fake host"))
+    }
+
+    pub fn host(&self, ip_address: &str) -> FakeHost {
+        self.with_host(ip_address, |host| host.clone())
+    }
+
+    pub fn set_pingable(&self, ip_address: &str, pingable: bool) {
+        let mut pingable_ips = self.pingable.lock().unwrap();
+        if pingable {
+            pingable_ips.insert(ip_address.to_string());
+        } else {
+            pingable_ips.remove(ip_address);
+        }
+    }
+
+    fn with_reachable_host<T>(
+        &self,
+        target: &SshTarget,
+        f: impl FnOnce(&mut FakeHost) -> T,
+    ) -> Result<T, SshError> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts
+            .get_mut(&target.ip_address)
+            .filter(|host| host.reachable)
+            .ok_or_else(|| SshError::Connect("connection refused".to_string()))?;
+        // 与真实连接一样在认证前校验主机密钥
+        if let Some(db_pool) = &self.db_pool {
+            host_keys::verify(
+                &db_pool.get().unwrap(),
+                &target.ip_address,
+                target.phase,
+                target.serial.as_deref(),
+                &host.host_key(),
+            )?;
+        }
+        if host.phase != target.phase {
+            return Err(SshError::Auth(format!(
+                "host is running {}, not {}",
+                host.phase, target.phase
+            )));
+        }
+        Ok(f(host))
+    }
+}
+
+impl CommandExecutor for FakeExecutor {
+    async fn run_ssh_command(
+        &self,
+        target: &SshTarget,
+        command: &str,
+    ) -> Result<CommandOutput, SshError> {
+        self.with_reachable_host(target, |host| host.run(command))
+    }
+
+    async fn pin_host_key(&self, target: &SshTarget, serial: &str) -> Result<(), SshError> {
+        let host_key = self.with_reachable_host(target, |host| host.host_key())?;
+        match &self.db_pool {
+            Some(db_pool) => Ok(host_keys::pin(
+                &db_pool.get().unwrap(),
+                &target.ip_address,
+                target.phase,
+                serial,
+                &host_key,
+            )?),
+            None => Ok(()),
+        }
+    }
+
+    async fn ping(&self, ip_address: &str) -> bool {
+        self.pingable.lock().unwrap().contains(ip_address)
+    }
+
+    async fn ipmi_lan_address(&self, target: &SshTarget) -> Option<String> {
+        self.with_reachable_host(target, |host| host.ipmi_address.clone())
+            .ok()
+            .flatten()
+    }
+
+    async fn ipmi_pxe_reboot(
+        &self,
+        target: &SshTarget,
+        boot_mode: Option<BootMode>,
+    ) -> Result<(), SshError> {
+        self.with_reachable_host(target, |host| {
+            host.pxe_boot_mode = boot_mode;
+            host.pxe_reboot();
+        })
+    }
+
+    async fn bmc_pxe_reboot(&self, target: &BmcTarget) -> Result<(), BmcError> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts
+            .values_mut()
+            .find(|host| host.ipmi_address.as_deref() == Some(target.address.as_str()))
+            .filter(|host| host.bmc_reachable)
+            .ok_or_else(|| BmcError::Unreachable(format!("no BMC at {}", target.address)))?;
+        host.power_cycles += 1;
+        host.pxe_boot_mode = target.boot_mode;
+        host.reachable = true;
+        host.pxe_reboot();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(phase: InstallPhase) -> SshTarget {
+        SshTarget::new("10.0.0.5", phase, None, None)
+    }
+
+    #[tokio::test]
+    async fn reads_and_writes_files() {
+        let executor = FakeExecutor::new();
+        executor.add_host("10.0.0.5", FakeHost::new("S1", None));
+        let target = target(InstallPhase::Installer);
+        let serial = executor
+            .run_ssh_command(&target, &format!("cat {PRODUCT_SERIAL_FILE}"))
+            .await
+            .unwrap();
+        assert_eq!(serial.into_stdout().as_deref(), Some("S1"));
+        executor
+            .run_ssh_command(&target, "echo \"5\">/tmp/install-progress.ack")
+            .await
+            .unwrap();
+        assert_eq!(
+            executor.host("10.0.0.5").file("/tmp/install-progress.ack"),
+            Some("5")
+        );
+        let missing = executor
+            .run_ssh_command(&target, "cat /tmp/install-progress")
+            .await
+            .unwrap();
+        assert!(!missing.success());
+    }
+
+    #[tokio::test]
+    async fn rejects_credentials_of_other_phase() {
+        let executor = FakeExecutor::new();
+        executor.add_host("10.0.0.5", FakeHost::new("S1", None));
+        let result = executor
+            .run_ssh_command(&target(InstallPhase::System), "true")
+            .await;
+        assert!(matches!(result, Err(SshError::Auth(_))));
+        executor.with_host("10.0.0.5", |host| host.reachable = false);
+        let result = executor
+            .run_ssh_command(&target(InstallPhase::Installer), "true")
+            .await;
+        assert!(matches!(result, Err(SshError::Connect(_))));
+    }
+
+    #[tokio::test]
+    async fn pxe_reboot_clears_tmp() {
+        let executor = FakeExecutor::new();
+        let host = FakeHost::new("S1", None).with_file("/tmp/install-progress", "85\n");
+        executor.add_host("10.0.0.5", host);
+        executor.with_host("10.0.0.5", |host| host.phase = InstallPhase::System);
+        executor
+            .ipmi_pxe_reboot(&target(InstallPhase::System), None)
+            .await
+            .unwrap();
+        let host = executor.host("10.0.0.5");
+        assert_eq!(host.reboots, 1);
+        assert_eq!(host.phase, InstallPhase::Installer);
+        assert_eq!(host.file("/tmp/install-progress"), None);
+    }
+}
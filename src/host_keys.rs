@@ -0,0 +1,493 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// SSH 主机密钥固定：按序列号和装机阶段记录首次见到的主机密钥，密钥变化或被其他序列号使用时告警
+use actix_web::{HttpResponse, Responder, web};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD as BASE64_NO_PAD;
+use chrono::Local;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use ssh2::{HashType, HostKeyType, Session};
+
+use crate::credentials::InstallPhase;
+use crate::install_state::Progress;
+
+// 主机密钥类型和 SHA256 指纹（与 ssh-keygen -l 的格式一致）
+#[derive(Debug, Clone)]
+pub struct HostKey {
+    pub key_type: &'static str,
+    pub fingerprint: String,
+}
+
+impl HostKey {
+    // 从完成握手的 SSH 连接中读取主机密钥
+    pub fn from_session(session: &Session) -> Option<HostKey> {
+        let (_, key_type) = session.host_key()?;
+        let hash = session.host_key_hash(HashType::Sha256)?;
+        Some(HostKey {
+            key_type: match key_type {
+                HostKeyType::Rsa => "ssh-rsa",
+                HostKeyType::Dss => "ssh-dss",
+                HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+                HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+                HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+                HostKeyType::Ed25519 => "ssh-ed25519",
+                HostKeyType::Unknown => "unknown",
+            },
+            fingerprint: format!("SHA256:{}", BASE64_NO_PAD.encode(hash)),
+        })
+    }
+}
+
+// 主机密钥校验失败的原因
+#[derive(Debug)]
+pub enum HostKeyError {
+    // 密钥未固定在该阶段，主机可能处于另一装机阶段，换用另一阶段重试
+    Unknown(String),
+    // 密钥与固定的不一致或属于其他序列号，已告警
+    Changed(String),
+}
+
+#[derive(Serialize)]
+pub struct HostKeyPin {
+    serial: String,
+    phase: String,
+    key_type: String,
+    fingerprint: String,
+    first_seen: String,
+    last_seen: String,
+}
+
+#[derive(Serialize)]
+pub struct HostKeyAlert {
+    id: i64,
+    serial: Option<String>,
+    phase: String,
+    ip_address: String,
+    key_type: String,
+    fingerprint: String,
+    reason: String,
+    created_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct HostKeyQuery {
+    serial: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetQuery {
+    phase: Option<InstallPhase>,
+}
+
+fn now() -> String {
+    Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+// 查找固定了该指纹的序列号和阶段
+fn pin_owner(conn: &Connection, fingerprint: &str) -> Option<(String, InstallPhase)> {
+    conn.query_row(
+        "SELECT serial, phase FROM host_key_pins WHERE fingerprint = ?1 ORDER BY last_seen DESC LIMIT 1",
+        params![fingerprint],
+        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+    )
+    .optional()
+    .unwrap()
+    .map(|(serial, phase)| {
+        let phase = match phase.as_str() {
+            "system" => InstallPhase::System,
+            _ => InstallPhase::Installer,
+        };
+        (serial, phase)
+    })
+}
+
+fn pinned_fingerprint(conn: &Connection, serial: &str, phase: InstallPhase) -> Option<String> {
+    conn.query_row(
+        "SELECT fingerprint FROM host_key_pins WHERE serial = ?1 AND phase = ?2",
+        params![serial, phase.as_str()],
+        |row| row.get(0),
+    )
+    .optional()
+    .unwrap()
+}
+
+fn raise_alert(
+    conn: &Connection,
+    serial: Option<&str>,
+    phase: InstallPhase,
+    ip_address: &str,
+    key: &HostKey,
+    reason: &str,
+) {
+    conn.execute(
+        "INSERT INTO host_key_alerts (serial, phase, ip_address, key_type, fingerprint, reason, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![serial, phase.as_str(), ip_address, key.key_type, key.fingerprint, reason, now()],
+    )
+    .unwrap();
+    println!(
+        "[ALERT] Host key {} {} on {} ({}, serial: {}): {}",
+        key.key_type,
+        key.fingerprint,
+        ip_address,
+        phase,
+        serial.unwrap_or("unknown"),
+        reason
+    );
+}
+
+// 认证前校验主机密钥，serial 为按 IP 推测的序列号，返回按密钥识别出的序列号
+pub fn verify(
+    conn: &Connection,
+    ip_address: &str,
+    phase: InstallPhase,
+    serial: Option<&str>,
+    key: &HostKey,
+) -> Result<Option<String>, HostKeyError> {
+    if let Some((owner, owner_phase)) = pin_owner(conn, &key.fingerprint) {
+        if owner_phase != phase {
+            return Err(HostKeyError::Unknown(format!(
+                "host key of {owner} belongs to the {owner_phase} phase"
+            )));
+        }
+        if serial.is_some_and(|serial| serial != owner) {
+            println!(
+                "[INFO] Host key on {ip_address} belongs to {owner}, not {}",
+                serial.unwrap_or_default()
+            );
+        }
+        conn.execute(
+            "UPDATE host_key_pins SET last_seen = ?1 WHERE serial = ?2 AND phase = ?3",
+            params![now(), owner, phase.as_str()],
+        )
+        .unwrap();
+        return Ok(Some(owner));
+    }
+    let Some(serial) = serial else {
+        // 尚未纳管的主机，读取序列号后再固定
+        return Ok(None);
+    };
+    let Some(expected) = pinned_fingerprint(conn, serial, phase) else {
+        check_transition(conn, ip_address, phase, serial, key)?;
+        return Ok(Some(serial.to_string()));
+    };
+    // 装机环境重启后密钥已重新生成，读取序列号后重新固定
+    if is_ephemeral(conn, serial, phase) {
+        return Ok(Some(serial.to_string()));
+    }
+    // 另一阶段尚未固定且安装进度表明主机应处于另一阶段时，换用另一阶段重试
+    if pinned_fingerprint(conn, serial, phase.other()).is_none()
+        && expected_phase(conn, serial) == Some(phase.other())
+    {
+        return Err(HostKeyError::Unknown(format!(
+            "host key {} is not the pinned {phase} key of {serial}",
+            key.fingerprint
+        )));
+    }
+    let reason = format!("host key changed, expected {expected}");
+    raise_alert(conn, Some(serial), phase, ip_address, key, &reason);
+    Err(HostKeyError::Changed(reason))
+}
+
+fn install_progress(conn: &Connection, serial: &str) -> Option<i32> {
+    conn.query_row(
+        "SELECT install_progress FROM hosts WHERE serial = ?1",
+        params![serial],
+        |row| row.get(0),
+    )
+    .optional()
+    .unwrap()
+    .flatten()
+}
+
+// 按记录的安装进度判断主机应处于的装机阶段：装机完成重启后为 system ，失败、超时或取消时无法判断
+fn expected_phase(conn: &Connection, serial: &str) -> Option<InstallPhase> {
+    match install_progress(conn, serial)? {
+        p if p >= Progress::InstallFinished as i32 => Some(InstallPhase::System),
+        p if p >= Progress::NotConfigured as i32 => Some(InstallPhase::Installer),
+        _ => None,
+    }
+}
+
+// 装机环境（BootOS）每次启动都会生成新的主机密钥，主机不在安装过程中时装机阶段的密钥只在本次启动内有效
+fn is_ephemeral(conn: &Connection, serial: &str, phase: InstallPhase) -> bool {
+    phase == InstallPhase::Installer
+        && !install_progress(conn, serial)
+            .and_then(|p| Progress::try_from(p).ok())
+            .is_some_and(Progress::is_installing)
+}
+
+// 该阶段尚未固定密钥时，另一阶段已固定的序列号只有在安装进度表明主机已切换到该阶段时才首次固定，
+// 否则视为密钥变化并告警
+fn check_transition(
+    conn: &Connection,
+    ip_address: &str,
+    phase: InstallPhase,
+    serial: &str,
+    key: &HostKey,
+) -> Result<(), HostKeyError> {
+    let Some(other) = pinned_fingerprint(conn, serial, phase.other()) else {
+        return Ok(());
+    };
+    if expected_phase(conn, serial) == Some(phase) || is_ephemeral(conn, serial, phase) {
+        return Ok(());
+    }
+    let reason = format!(
+        "unexpected {phase} host key, {serial} is not installing and has {} key {other}",
+        phase.other()
+    );
+    raise_alert(conn, Some(serial), phase, ip_address, key, &reason);
+    Err(HostKeyError::Changed(reason))
+}
+
+// 主机报告序列号后固定主机密钥，序列号与已固定的密钥不符时告警
+pub fn pin(
+    conn: &Connection,
+    ip_address: &str,
+    phase: InstallPhase,
+    serial: &str,
+    key: &HostKey,
+) -> Result<(), HostKeyError> {
+    if let Some((owner, _)) = pin_owner(conn, &key.fingerprint)
+        && owner != serial
+    {
+        let reason = format!("same host key is pinned to {owner}");
+        raise_alert(conn, Some(serial), phase, ip_address, key, &reason);
+        return Err(HostKeyError::Changed(reason));
+    }
+    if let Some(expected) = pinned_fingerprint(conn, serial, phase) {
+        if expected == key.fingerprint {
+            return Ok(());
+        }
+        if is_ephemeral(conn, serial, phase) {
+            conn.execute(
+                "UPDATE host_key_pins SET key_type = ?1, fingerprint = ?2, first_seen = ?3, last_seen = ?3 WHERE serial = ?4 AND phase = ?5",
+                params![key.key_type, key.fingerprint, now(), serial, phase.as_str()],
+            )
+            .unwrap();
+            println!(
+                "[INFO] Re-pinned {phase} host key {} {} for {serial} ({ip_address}), replacing {expected}",
+                key.key_type, key.fingerprint
+            );
+            return Ok(());
+        }
+        let reason = format!("host key changed, expected {expected}");
+        raise_alert(conn, Some(serial), phase, ip_address, key, &reason);
+        return Err(HostKeyError::Changed(reason));
+    }
+    check_transition(conn, ip_address, phase, serial, key)?;
+    let now = now();
+    conn.execute(
+        "INSERT INTO host_key_pins (serial, phase, key_type, fingerprint, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+        params![serial, phase.as_str(), key.key_type, key.fingerprint, now],
+    )
+    .unwrap();
+    println!(
+        "[INFO] Pinned {phase} host key {} {} for {serial} ({ip_address})",
+        key.key_type, key.fingerprint
+    );
+    Ok(())
+}
+
+// 清除主机密钥，phase 为空时清除所有阶段
+pub fn reset(conn: &Connection, serial: &str, phase: Option<InstallPhase>) -> usize {
+    conn.execute(
+        "DELETE FROM host_key_pins WHERE serial = ?1 AND (?2 IS NULL OR phase = ?2)",
+        params![serial, phase.map(|p| p.as_str())],
+    )
+    .unwrap()
+}
+
+// 处理 GET /api/host-keys ，可按 ?serial= 过滤
+pub async fn list_host_keys(
+    query: web::Query<HostKeyQuery>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT serial, phase, key_type, fingerprint, first_seen, last_seen FROM host_key_pins WHERE ?1 IS NULL OR serial = ?1 ORDER BY serial, phase",
+        )
+        .unwrap();
+    let pins: Vec<HostKeyPin> = stmt
+        .query_map(params![query.serial], |row| {
+            Ok(HostKeyPin {
+                serial: row.get(0)?,
+                phase: row.get(1)?,
+                key_type: row.get(2)?,
+                fingerprint: row.get(3)?,
+                first_seen: row.get(4)?,
+                last_seen: row.get(5)?,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(pins)
+}
+
+// 处理 GET /api/host-keys/alerts
+pub async fn list_host_key_alerts(
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, serial, phase, ip_address, key_type, fingerprint, reason, created_at FROM host_key_alerts ORDER BY id DESC",
+        )
+        .unwrap();
+    let alerts: Vec<HostKeyAlert> = stmt
+        .query_map([], |row| {
+            Ok(HostKeyAlert {
+                id: row.get(0)?,
+                serial: row.get(1)?,
+                phase: row.get(2)?,
+                ip_address: row.get(3)?,
+                key_type: row.get(4)?,
+                fingerprint: row.get(5)?,
+                reason: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(alerts)
+}
+
+// 处理 DELETE /api/host-keys/{serial} ，重装主机前清除固定的主机密钥，可用 ?phase= 只清除一个阶段
+pub async fn reset_host_keys(
+    serial: web::Path<String>,
+    query: web::Query<ResetQuery>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let conn = db_pool.get().unwrap();
+    let deleted = reset(&conn, &serial, query.phase);
+    if deleted == 0 {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("no host key pinned for {serial}") }));
+    }
+    println!("[INFO] {deleted} host key(s) of {serial} reset by API");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    fn key(fingerprint: &str) -> HostKey {
+        HostKey {
+            key_type: "ssh-ed25519",
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    fn alerts(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM host_key_alerts", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn other_phase_is_trusted_only_on_install_transition() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, ip_address, install_progress) VALUES ('S1', '10.0.0.5', 20)",
+            [],
+        )
+        .unwrap();
+        let installer = InstallPhase::Installer;
+        let system = InstallPhase::System;
+        pin(&conn, "10.0.0.5", installer, "S1", &key("SHA256:a")).unwrap();
+
+        // 安装过程中出现另一把密钥：不换用 system 阶段重试，另一阶段也不会静默固定
+        let result = verify(&conn, "10.0.0.5", installer, Some("S1"), &key("SHA256:b"));
+        assert!(matches!(result, Err(HostKeyError::Changed(_))));
+        let result = verify(&conn, "10.0.0.5", system, Some("S1"), &key("SHA256:b"));
+        assert!(matches!(result, Err(HostKeyError::Changed(_))));
+        assert!(pin(&conn, "10.0.0.5", system, "S1", &key("SHA256:b")).is_err());
+        assert_eq!(alerts(&conn), 3);
+        assert_eq!(pinned_fingerprint(&conn, "S1", system), None);
+
+        // 装机完成后主机重启进入操作系统，换用 system 阶段并首次固定
+        conn.execute("UPDATE hosts SET install_progress = 80", [])
+            .unwrap();
+        let result = verify(&conn, "10.0.0.5", installer, Some("S1"), &key("SHA256:c"));
+        assert!(matches!(result, Err(HostKeyError::Unknown(_))));
+        let result = verify(&conn, "10.0.0.5", system, Some("S1"), &key("SHA256:c"));
+        assert_eq!(result.unwrap(), Some("S1".to_string()));
+        pin(&conn, "10.0.0.5", system, "S1", &key("SHA256:c")).unwrap();
+        assert_eq!(alerts(&conn), 3);
+    }
+
+    #[test]
+    fn installer_key_is_re_pinned_outside_installs() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, ip_address, install_progress) VALUES ('S1', '10.0.0.5', 0)",
+            [],
+        )
+        .unwrap();
+        let installer = InstallPhase::Installer;
+        pin(&conn, "10.0.0.5", installer, "S1", &key("SHA256:a")).unwrap();
+
+        // 空闲主机重启后装机环境生成新密钥，重新固定且不告警
+        let result = verify(&conn, "10.0.0.5", installer, Some("S1"), &key("SHA256:b"));
+        assert_eq!(result.unwrap(), Some("S1".to_string()));
+        pin(&conn, "10.0.0.5", installer, "S1", &key("SHA256:b")).unwrap();
+        assert_eq!(
+            pinned_fingerprint(&conn, "S1", installer).as_deref(),
+            Some("SHA256:b")
+        );
+
+        // 超时后重新进入装机环境同样重新固定，已装好系统的密钥仍然不会静默变化
+        conn.execute(
+            "INSERT INTO host_key_pins (serial, phase, key_type, fingerprint, first_seen, last_seen) VALUES ('S1', 'system', 'ssh-ed25519', 'SHA256:s', '', '')",
+            [],
+        )
+        .unwrap();
+        conn.execute("UPDATE hosts SET install_progress = -2", [])
+            .unwrap();
+        pin(&conn, "10.0.0.5", installer, "S1", &key("SHA256:c")).unwrap();
+        assert!(
+            pin(
+                &conn,
+                "10.0.0.5",
+                InstallPhase::System,
+                "S1",
+                &key("SHA256:d")
+            )
+            .is_err()
+        );
+        assert_eq!(alerts(&conn), 1);
+
+        // 安装过程中装机环境的密钥变化仍然告警
+        conn.execute("UPDATE hosts SET install_progress = 10", [])
+            .unwrap();
+        assert!(pin(&conn, "10.0.0.5", installer, "S1", &key("SHA256:e")).is_err());
+        assert_eq!(alerts(&conn), 2);
+    }
+}
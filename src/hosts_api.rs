@@ -0,0 +1,571 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 主机管理 API：对 hosts 表进行增删改查
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, Row, ToSql, params};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::bmc::BmcProtocol;
+use crate::boot_mode::BootMode;
+use crate::host_keys;
+use crate::installer_api::INSTALLER_ACTION_ABORT;
+use crate::ipxe_catalog;
+use crate::os_profile;
+
+const HOST_COLUMNS: &str = "serial, ip_address, ipmi_address, os, hostname, public_ip_addr, vlan_id, install_progress, last_updated, last_seen, installer_action, progress_message, progress_updated, install_attempts, vendor, bmc_protocol, boot_mode, mac_address, ipxe_version, host_group, install_ipxe_version";
+
+#[derive(Serialize)]
+pub struct HostRecord {
+    serial: Option<String>,
+    ip_address: Option<String>,
+    ipmi_address: Option<String>,
+    os: Option<String>,
+    hostname: Option<String>,
+    public_ip_addr: Option<String>,
+    vlan_id: Option<u32>,
+    install_progress: Option<i32>,
+    last_updated: Option<String>,
+    last_seen: Option<String>,
+    installer_action: Option<String>,
+    progress_message: Option<String>,
+    progress_updated: Option<String>,
+    install_attempts: u32,
+    vendor: Option<String>,
+    bmc_protocol: Option<String>,
+    boot_mode: Option<String>,
+    mac_address: Option<String>,
+    ipxe_version: Option<i64>,
+    host_group: Option<String>,
+    // 本次安装使用的 iPXE 脚本版本，开始安装时确定
+    install_ipxe_version: Option<i64>,
+}
+
+impl HostRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(HostRecord {
+            serial: row.get(0)?,
+            ip_address: row.get(1)?,
+            ipmi_address: row.get(2)?,
+            os: row.get(3)?,
+            hostname: row.get(4)?,
+            public_ip_addr: row.get(5)?,
+            vlan_id: row.get(6)?,
+            install_progress: row.get(7)?,
+            last_updated: row.get(8)?,
+            last_seen: row.get(9)?,
+            installer_action: row.get(10)?,
+            progress_message: row.get(11)?,
+            progress_updated: row.get(12)?,
+            install_attempts: row.get(13)?,
+            vendor: row.get(14)?,
+            bmc_protocol: row.get(15)?,
+            boot_mode: row.get(16)?,
+            mac_address: row.get(17)?,
+            ipxe_version: row.get(18)?,
+            host_group: row.get(19)?,
+            install_ipxe_version: row.get(20)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewHost {
+    serial: String,
+    ipmi_address: Option<String>,
+    os: Option<String>,
+    hostname: Option<String>,
+    public_ip_addr: Option<String>,
+    vlan_id: Option<u32>,
+    host_group: Option<String>,
+}
+
+// PATCH 请求中未出现的字段保持不变，显式传 null 的字段被清空
+#[derive(Deserialize)]
+pub struct HostPatch {
+    #[serde(default, deserialize_with = "nullable")]
+    ipmi_address: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    os: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    hostname: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    public_ip_addr: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    vlan_id: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    installer_action: Option<Option<String>>,
+    // 为空时按厂商选择
+    #[serde(default, deserialize_with = "nullable")]
+    bmc_protocol: Option<Option<BmcProtocol>>,
+    // 主机发现时自动检测，检测结果不对时可以手工指定
+    #[serde(default, deserialize_with = "nullable")]
+    boot_mode: Option<Option<BootMode>>,
+    // 固定使用的 iPXE 脚本版本，为空时使用操作系统启用的版本
+    #[serde(default, deserialize_with = "nullable")]
+    ipxe_version: Option<Option<i64>>,
+    // 主机组，用于选择网卡规则
+    #[serde(default, deserialize_with = "nullable")]
+    host_group: Option<Option<String>>,
+}
+
+pub(crate) fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+fn validate_ipmi_address(ipmi_address: &str) -> Result<(), String> {
+    ipmi_address
+        .parse::<IpAddr>()
+        .map(|_| ())
+        .map_err(|_| format!("invalid ipmi_address: {ipmi_address}"))
+}
+
+// 装机后网络配置按 IPv4 地址推算网关，因此业务 IP 只接受 IPv4
+fn validate_public_ip_addr(public_ip_addr: &str) -> Result<(), String> {
+    public_ip_addr
+        .parse::<Ipv4Addr>()
+        .map(|_| ())
+        .map_err(|_| format!("invalid public_ip_addr: {public_ip_addr}"))
+}
+
+fn validate_vlan_id(vlan_id: u32) -> Result<(), String> {
+    if (1..=4094).contains(&vlan_id) {
+        Ok(())
+    } else {
+        Err(format!("vlan_id must be between 1 and 4094, got {vlan_id}"))
+    }
+}
+
+fn validate_hostname(hostname: &str) -> Result<(), String> {
+    let valid = !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid hostname: {hostname}"))
+    }
+}
+
+fn validate_installer_action(action: &str) -> Result<(), String> {
+    if action == INSTALLER_ACTION_ABORT {
+        Ok(())
+    } else {
+        Err(format!("invalid installer_action: {action}"))
+    }
+}
+
+fn get_host(conn: &Connection, serial: &str) -> Option<HostRecord> {
+    conn.query_row(
+        &format!("SELECT {HOST_COLUMNS} FROM hosts WHERE serial = ?1"),
+        params![serial],
+        HostRecord::from_row,
+    )
+    .optional()
+    .unwrap()
+}
+
+// 检查 IPMI 地址是否已被其他主机占用（ipmi_address 为主键）
+fn ipmi_address_owner(conn: &Connection, ipmi_address: &str) -> Option<String> {
+    conn.query_row(
+        "SELECT serial FROM hosts WHERE ipmi_address = ?1",
+        params![ipmi_address],
+        |row| row.get::<_, Option<String>>(0),
+    )
+    .optional()
+    .unwrap()
+    .map(|serial| serial.unwrap_or_default())
+}
+
+// 并发请求可能同时通过上面的检查，由唯一约束兜底（serial 为 UNIQUE ，ipmi_address 为主键）
+fn is_unique_violation(error: &rusqlite::Error) -> bool {
+    matches!(
+        error,
+        rusqlite::Error::SqliteFailure(e, _)
+            if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+                || e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+    )
+}
+
+// 处理 GET /api/hosts
+pub async fn list_hosts(db_pool: web::Data<Pool<SqliteConnectionManager>>) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(&format!("SELECT {HOST_COLUMNS} FROM hosts ORDER BY serial"))
+        .unwrap();
+    let hosts: Vec<HostRecord> = stmt
+        .query_map([], HostRecord::from_row)
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(hosts)
+}
+
+// 处理 GET /api/hosts/{serial}
+pub async fn get_host_by_serial(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    match get_host(&conn, &serial) {
+        Some(host) => HttpResponse::Ok().json(host),
+        None => {
+            HttpResponse::NotFound().json(json!({ "error": format!("host {serial} not found") }))
+        }
+    }
+}
+
+// 处理 POST /api/hosts ，预先登记主机
+pub async fn create_host(
+    host: web::Json<NewHost>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let host = host.into_inner();
+    let serial = host.serial.trim().to_string();
+    if serial.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "serial must not be empty" }));
+    }
+    let validation = host
+        .ipmi_address
+        .as_deref()
+        .map_or(Ok(()), validate_ipmi_address)
+        .and(host.hostname.as_deref().map_or(Ok(()), validate_hostname))
+        .and(
+            host.public_ip_addr
+                .as_deref()
+                .map_or(Ok(()), validate_public_ip_addr),
+        )
+        .and(host.vlan_id.map_or(Ok(()), validate_vlan_id));
+    if let Err(message) = validation {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+    let conn = db_pool.get().unwrap();
+    if get_host(&conn, &serial).is_some() {
+        return HttpResponse::Conflict()
+            .json(json!({ "error": format!("host {serial} already exists") }));
+    }
+    if let Some(ipmi_address) = &host.ipmi_address
+        && let Some(owner) = ipmi_address_owner(&conn, ipmi_address)
+    {
+        return HttpResponse::Conflict().json(json!({ "error": format!(
+            "ipmi_address {ipmi_address} is already used by host {owner}"
+        ) }));
+    }
+    if let Some(os) = &host.os
+        && os_profile::os_settings(&conn, os).is_none()
+    {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": format!("os {os} is not registered") }));
+    }
+    let result = conn.execute(
+        "INSERT INTO hosts (serial, ipmi_address, os, hostname, public_ip_addr, vlan_id, host_group) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            serial,
+            host.ipmi_address,
+            host.os,
+            host.hostname,
+            host.public_ip_addr,
+            host.vlan_id,
+            host.host_group
+        ],
+    );
+    match result {
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => {
+            return HttpResponse::Conflict().json(json!({ "error": format!(
+                "host {serial} or its ipmi_address already exists"
+            ) }));
+        }
+        Err(e) => panic!("Failed to create host {serial}: {e}"),
+    }
+    println!("[INFO] Host {serial} created by API");
+    HttpResponse::Created().json(get_host(&conn, &serial))
+}
+
+// 处理 PATCH /api/hosts/{serial}
+pub async fn update_host(
+    serial: web::Path<String>,
+    patch: web::Json<HostPatch>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let patch = patch.into_inner();
+    let validation = patch
+        .ipmi_address
+        .as_ref()
+        .and_then(Option::as_deref)
+        .map_or(Ok(()), validate_ipmi_address)
+        .and(
+            patch
+                .hostname
+                .as_ref()
+                .and_then(Option::as_deref)
+                .map_or(Ok(()), validate_hostname),
+        )
+        .and(
+            patch
+                .public_ip_addr
+                .as_ref()
+                .and_then(Option::as_deref)
+                .map_or(Ok(()), validate_public_ip_addr),
+        )
+        .and(patch.vlan_id.flatten().map_or(Ok(()), validate_vlan_id))
+        .and(
+            patch
+                .installer_action
+                .as_ref()
+                .and_then(Option::as_deref)
+                .map_or(Ok(()), validate_installer_action),
+        );
+    if let Err(message) = validation {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+    let conn = db_pool.get().unwrap();
+    let Some(current) = get_host(&conn, &serial) else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    };
+    if let Some(Some(ipmi_address)) = &patch.ipmi_address
+        && let Some(owner) = ipmi_address_owner(&conn, ipmi_address)
+        && owner != serial
+    {
+        return HttpResponse::Conflict().json(json!({ "error": format!(
+            "ipmi_address {ipmi_address} is already used by host {owner}"
+        ) }));
+    }
+    // 固定的版本必须属于主机修改后的操作系统
+    if let Some(Some(version)) = patch.ipxe_version {
+        let os = match &patch.os {
+            Some(os) => os.clone(),
+            None => current.os.clone(),
+        };
+        let Some(os) = os else {
+            return HttpResponse::Conflict()
+                .json(json!({ "error": format!("host {serial} has no os to pin") }));
+        };
+        if !ipxe_catalog::version_exists(&conn, &os, version) {
+            return HttpResponse::BadRequest()
+                .json(json!({ "error": format!("os {os} has no iPXE script version {version}") }));
+        }
+    }
+    let mut columns: Vec<&str> = Vec::new();
+    let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+    let new_ipmi_address = patch.ipmi_address.clone();
+    if let Some(value) = patch.ipmi_address {
+        columns.push("ipmi_address");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.os {
+        columns.push("os");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.hostname {
+        columns.push("hostname");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.public_ip_addr {
+        columns.push("public_ip_addr");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.vlan_id {
+        columns.push("vlan_id");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.installer_action {
+        columns.push("installer_action");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.bmc_protocol {
+        columns.push("bmc_protocol");
+        values.push(Box::new(value.map(|protocol| protocol.as_str())));
+    }
+    if let Some(value) = patch.boot_mode {
+        columns.push("boot_mode");
+        values.push(Box::new(value.map(|mode| mode.as_str())));
+    }
+    if let Some(value) = patch.ipxe_version {
+        columns.push("ipxe_version");
+        values.push(Box::new(value));
+    }
+    if let Some(value) = patch.host_group {
+        columns.push("host_group");
+        values.push(Box::new(value));
+    }
+    if !columns.is_empty() {
+        let assignments = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{column} = ?{}", i + 1))
+            .collect::<Vec<_>>()
+            .join(", ");
+        values.push(Box::new(serial.clone()));
+        let result = conn.execute(
+            &format!(
+                "UPDATE hosts SET {assignments} WHERE serial = ?{}",
+                values.len()
+            ),
+            rusqlite::params_from_iter(values.iter()),
+        );
+        match result {
+            Ok(_) => {}
+            Err(e) if is_unique_violation(&e) => {
+                return HttpResponse::Conflict().json(json!({ "error": format!(
+                    "ipmi_address of host {serial} is already used by another host"
+                ) }));
+            }
+            Err(e) => panic!("Failed to update host {serial}: {e}"),
+        }
+        // 装机队列以 ipmi_address 关联主机，主机更新成功后再同步更新队列
+        if let Some(value) = new_ipmi_address
+            && let Some(old_ipmi_address) = &current.ipmi_address
+        {
+            conn.execute(
+                "UPDATE install_queue SET ipmi_address = ?1 WHERE ipmi_address = ?2",
+                params![value, old_ipmi_address],
+            )
+            .unwrap();
+            conn.execute("DELETE FROM install_queue WHERE ipmi_address IS NULL", [])
+                .unwrap();
+        }
+        println!(
+            "[INFO] Host {serial} updated by API: {}",
+            columns.join(", ")
+        );
+    }
+    HttpResponse::Ok().json(get_host(&conn, &serial))
+}
+
+// 处理 DELETE /api/hosts/{serial} ，同时从装机队列中移除
+pub async fn delete_host(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let conn = db_pool.get().unwrap();
+    let Some(host) = get_host(&conn, &serial) else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    };
+    if let Some(ipmi_address) = &host.ipmi_address {
+        conn.execute(
+            "DELETE FROM install_queue WHERE ipmi_address = ?1",
+            params![ipmi_address],
+        )
+        .unwrap();
+    }
+    conn.execute("DELETE FROM hosts WHERE serial = ?1", params![serial])
+        .unwrap();
+    conn.execute("DELETE FROM host_nics WHERE serial = ?1", params![serial])
+        .unwrap();
+    conn.execute(
+        "DELETE FROM network_profiles WHERE serial = ?1",
+        params![serial],
+    )
+    .unwrap();
+    host_keys::reset(&conn, &serial, None);
+    println!("[INFO] Host {serial} deleted by API");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    #[actix_web::test]
+    async fn create_host_rejects_unregistered_os() {
+        use actix_web::{App, http::StatusCode, test};
+
+        let db_pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        init_db(&db_pool.get().unwrap());
+        db_pool
+            .get()
+            .unwrap()
+            .execute("INSERT INTO ipxe (os) VALUES ('Kylin-V10SP4-X86')", [])
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_pool.clone()))
+                .route("/api/hosts", web::post().to(create_host)),
+        )
+        .await;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/api/hosts")
+                .set_json(json!({ "serial": "S1", "os": "Missing-OS" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(get_host(&db_pool.get().unwrap(), "S1").is_none());
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/api/hosts")
+                .set_json(json!({ "serial": "S1", "os": "Kylin-V10SP4-X86" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[test]
+    fn unique_violations_are_detected() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, ipmi_address) VALUES ('S1', '10.0.0.10')",
+            [],
+        )
+        .unwrap();
+        let duplicate_serial = conn
+            .execute(
+                "INSERT INTO hosts (serial, ipmi_address) VALUES ('S1', '10.0.0.11')",
+                [],
+            )
+            .unwrap_err();
+        assert!(is_unique_violation(&duplicate_serial));
+        let duplicate_ipmi_address = conn
+            .execute(
+                "INSERT INTO hosts (serial, ipmi_address) VALUES ('S2', '10.0.0.10')",
+                [],
+            )
+            .unwrap_err();
+        assert!(is_unique_violation(&duplicate_ipmi_address));
+        let other = conn
+            .execute("INSERT INTO missing_table (serial) VALUES ('S3')", [])
+            .unwrap_err();
+        assert!(!is_unique_violation(&other));
+    }
+}
@@ -19,26 +19,38 @@ use chrono::{Local, NaiveDateTime, Utc};
 use futures::stream::{self, StreamExt};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
-use std::collections::HashSet;
+use rusqlite::{OptionalExtension, params};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use tokio::time::Duration;
 
-use crate::command_execute::run_ssh_command_on_host;
+use crate::boot_mode::{self, BootMode};
+use crate::command_execute::{CommandOutput, SshError, SshTarget};
+use crate::credentials::InstallPhase;
+use crate::executor::CommandExecutor;
+use crate::install_events::record_event;
+use crate::install_state::{Progress, TransitionSource, set_install_progress};
+use crate::nic_rules;
 
 #[derive(Debug)]
 struct Host {
     ip_address: String,
-    ipmi_address: String,
+    // 读取失败时为空，保留之前记录的地址
+    ipmi_address: Option<String>,
     serial: String,
-    install_progress: i32,
+    // 从 dhcp.leases 中获取的 PXE 网卡 MAC 地址
+    mac_address: Option<String>,
+    vendor: Option<String>,
+    boot_mode: Option<BootMode>,
+    install_progress: Progress,
     last_updated: String,
 }
 
-// 处理 dhcp.leases 里每一个 lease 块
-fn process_lease_block(lease_block: &str) -> Option<String> {
+// 处理 dhcp.leases 里每一个 lease 块，返回未到期租约的 IP 地址和 MAC 地址
+fn process_lease_block(lease_block: &str) -> Option<(String, Option<String>)> {
     let mut ip_address = None;
+    let mut mac_address = None;
     let mut ends_timestamp = None;
     for line in lease_block.lines() {
         if line.split_whitespace().next() == Some("lease") {
@@ -51,22 +63,24 @@ fn process_lease_block(lease_block: &str) -> Option<String> {
                 .join(" ");
             ends_timestamp =
                 NaiveDateTime::parse_from_str(&timestamp_str, "%Y/%m/%d %H:%M:%S;").ok();
+        } else if let Some(mac) = line.trim().strip_prefix("hardware ethernet ") {
+            mac_address = Some(mac.trim_end_matches(';').trim().to_lowercase());
         }
     }
     if let (Some(ip), Some(ends)) = (ip_address, ends_timestamp) {
         let current_time = Utc::now().naive_utc();
         if ends > current_time {
-            return Some(ip);
+            return Some((ip, mac_address));
         }
     }
     None
 }
 
-// 找到所有 dhcp.leases 里 lease 块并解析
-fn parse_dhcp_leases(file_path: &str) -> HashSet<String> {
+// 找到所有 dhcp.leases 里 lease 块并解析，同一 IP 地址以最后一个租约为准
+fn parse_dhcp_leases(file_path: &str) -> HashMap<String, Option<String>> {
     let file = File::open(file_path).expect("Failed to open dhcpd.leases file");
     let reader = BufReader::new(file);
-    let mut active_ips = HashSet::new();
+    let mut active_ips = HashMap::new();
     // current_lease 用于存储正在被解析的 lease 块
     let mut current_lease = String::new();
     for line in reader.lines() {
@@ -76,14 +90,14 @@ fn parse_dhcp_leases(file_path: &str) -> HashSet<String> {
         if line.split_whitespace().next() == Some("lease") {
             current_lease = line.clone();
         } else if !line.trim().is_empty() {
-            current_lease.push_str("\n");
+            current_lease.push('\n');
             current_lease.push_str(&line);
         }
         // 如果当前行是右括号，则 current_lease 里内容为当前完整 lease 块
         if line.trim() == "}" {
             // 处理当前 lease 如果有 IP 地址且未过期，则将其加入 active_ips
-            if let Some(ip) = process_lease_block(&current_lease) {
-                active_ips.insert(ip);
+            if let Some((ip, mac)) = process_lease_block(&current_lease) {
+                active_ips.insert(ip, mac);
             }
             // 清空当前 current_lease
             current_lease.clear();
@@ -92,7 +106,24 @@ fn parse_dhcp_leases(file_path: &str) -> HashSet<String> {
     active_ips
 }
 
-fn add_host_to_db(host: Host, db_pool: &Pool<SqliteConnectionManager>) {
+// 查找已纳管主机的序列号、操作系统和安装进度，用于选择 SSH 凭据
+fn find_known_host(
+    column: &str,
+    value: &str,
+    db_pool: &Pool<SqliteConnectionManager>,
+) -> Option<(Option<String>, Option<String>, Option<i32>)> {
+    let conn = db_pool.get().unwrap();
+    conn.query_row(
+        &format!("SELECT serial, os, install_progress FROM hosts WHERE {column} = ?1"),
+        params![value],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()
+    .unwrap()
+}
+
+// 入库主机信息，返回安装进度是否被接受
+fn add_host_to_db(host: Host, db_pool: &Pool<SqliteConnectionManager>) -> bool {
     let conn = db_pool.get().unwrap();
     // 检查序列号是否存在
     let exists: bool = conn
@@ -102,106 +133,189 @@ fn add_host_to_db(host: Host, db_pool: &Pool<SqliteConnectionManager>) {
             |row| row.get(0),
         )
         .unwrap_or(false);
-    // 如果序列号不存在则插入，否则更新
+    // 如果序列号不存在则以 NotConfigured 插入，否则更新
     if !exists {
         conn.execute(
-                    "INSERT INTO hosts (ip_address, serial, install_progress, last_updated, ipmi_address) VALUES (?1, ?2, ?3, ?4, ?5)",
-                    params![host.ip_address, host.serial, host.install_progress, host.last_updated, host.ipmi_address],
+                    "INSERT INTO hosts (ip_address, serial, install_progress, last_updated, progress_updated, ipmi_address, vendor, boot_mode, mac_address) VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6, ?7, ?8)",
+                    params![host.ip_address, host.serial, Progress::NotConfigured as i32, host.last_updated, host.ipmi_address, host.vendor, host.boot_mode.map(|mode| mode.as_str()), host.mac_address],
                 )
                 .unwrap();
+        record_event(
+            &conn,
+            &host.serial,
+            None,
+            Progress::NotConfigured,
+            TransitionSource::Discovery,
+            &host.last_updated,
+        );
     } else {
         conn.execute(
-                    "UPDATE hosts SET ip_address = ?1, install_progress = ?2, last_updated = ?3 , ipmi_address = ?4 WHERE serial = ?5",
-                    params![host.ip_address, host.install_progress, host.last_updated, host.ipmi_address, host.serial],
+                    "UPDATE hosts SET ip_address = ?1, last_updated = ?2 , ipmi_address = COALESCE(?3, ipmi_address), vendor = COALESCE(?4, vendor), boot_mode = COALESCE(?5, boot_mode), mac_address = COALESCE(?6, mac_address) WHERE serial = ?7",
+                    params![host.ip_address, host.last_updated, host.ipmi_address, host.vendor, host.boot_mode.map(|mode| mode.as_str()), host.mac_address, host.serial],
                 )
                 .unwrap();
     }
+    // 安装进度需经过状态机校验，不允许的转换只记录日志
+    set_install_progress(
+        &conn,
+        &host.serial,
+        host.install_progress,
+        TransitionSource::Discovery,
+    )
+    .is_ok()
 }
 
-// 持续监控当前 dhcp.leases 文件
-pub async fn monitor_dhcp_leases(
+// 对 dhcp.leases 中所有租约未到期的主机执行一轮信息收集
+pub async fn discover_hosts<E: CommandExecutor>(
+    executor: &E,
     file_path: &str,
-    interval_secs: u64,
+    concurrency_limit: usize,
     db_pool: Pool<SqliteConnectionManager>,
 ) {
-    loop {
-        // 记录开始时间
-        let start_time = Utc::now();
-        // 获取当前还在 dhcp.leases 文件且租约没到期的 IP 地址
-        let active_ips = parse_dhcp_leases(file_path);
-        // 并发上限
-        let concurrency_limit = 10;
-        // 循环所有 IP 地址进行主机获取
-        stream::iter(active_ips)
-            .for_each_concurrent(concurrency_limit, |ip| {
-                let db_pool = db_pool.clone();
-                async move {
-                    // 记录当前时间
-                    let current_time = Local::now()
-                        .naive_local()
-                        .format("%Y-%m-%d %H:%M:%S")
-                        .to_string();
-                    // 收集序列号信息
-                    let serial = run_ssh_command_on_host(
-                        &ip,
-                        "cat /sys/devices/virtual/dmi/id/product_serial",
-                    ).await;
-                    // 当序列号收集到时，才进行后续操作，以防止浪潮读不出序列号问题
-                    let serial = match serial {
-                        Some(s) => s.trim().to_string(),
-                        None => {
-                            println!("[INFO] No serial found for IP: {}", ip);
-                            return;
-                        }
-                    };
-                    if serial.is_empty() {
-                        println!("[INFO] Empty serial found for IP: {}", ip);
+    // 获取当前还在 dhcp.leases 文件且租约没到期的 IP 地址
+    let active_ips = parse_dhcp_leases(file_path);
+    // 循环所有 IP 地址进行主机获取
+    stream::iter(active_ips)
+        .for_each_concurrent(concurrency_limit, |(ip, mac_address)| {
+            let db_pool = db_pool.clone();
+            async move {
+                // 记录当前时间
+                let current_time = Local::now()
+                    .naive_local()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string();
+                // 按上次记录的主机信息选择 SSH 凭据，未纳管的主机视为处于装机环境
+                let (known_serial, known_os, known_progress) =
+                    find_known_host("ip_address", &ip, &db_pool).unwrap_or_default();
+                let mut target = SshTarget::new(
+                    &ip,
+                    InstallPhase::from_progress(known_progress),
+                    known_os.as_deref(),
+                    known_serial.as_deref(),
+                );
+                // 收集序列号信息，认证失败或主机密钥不属于该阶段时（例如主机已重新进入装机环境）换用另一阶段
+                let serial_command = "cat /sys/devices/virtual/dmi/id/product_serial";
+                let serial = match executor.run_ssh_command(&target, serial_command).await {
+                    Err(SshError::Auth(_) | SshError::HostKeyUnknown(_)) => {
+                        target.phase = target.phase.other();
+                        executor.run_ssh_command(&target, serial_command).await
+                    }
+                    result => result,
+                }
+                .ok()
+                .and_then(CommandOutput::into_stdout);
+                // 当序列号收集到时，才进行后续操作，以防止浪潮读不出序列号问题
+                let serial = match serial {
+                    Some(s) => s.trim().to_string(),
+                    None => {
+                        println!("[INFO] No serial found for IP: {}", ip);
                         return;
-                    };
-                    // 收集带外管理IP地址信息
-                    let ipmi_addr = run_ssh_command_on_host(
-                        &ip,
-                        "ipmitool lan print | grep \"^IP Address\" | grep -v \"Source\" | awk '{print $4}'",
-                    ).await
-                    .unwrap_or("unknown".to_string());
-                    // 收集安装进度信息，如果能收集到合法信息则入库
-                    let install_progress =
-                        run_ssh_command_on_host(&ip, "cat /tmp/install-progress").await;
-                    match install_progress {
-                        Some(progress) => match progress.parse::<i32>() {
-                            Ok(progress) => {
-                                println!(
-                                    "[INFO] Install progress for IP {} ({}): {}",
-                                    ip, serial, progress
-                                );
-                                let host = Host {
-                                    ip_address: ip.clone(),
-                                    ipmi_address: ipmi_addr,
-                                    serial,
-                                    install_progress: progress,
-                                    last_updated: current_time,
-                                };
-                                // 入库并告诉客户端信息已收集
-                                add_host_to_db(host, &db_pool);
-                                run_ssh_command_on_host(
-                                    &ip,
-                                    &format!("echo \"{}\">/tmp/install-progress.ack", progress),
-                                ).await;
-                            }
-                            _ => {
-                                println!(
-                                    "[INFO] Invalid install progress for IP {}: {}",
-                                    ip, progress
-                                );
+                    }
+                };
+                if serial.is_empty() {
+                    println!("[INFO] Empty serial found for IP: {}", ip);
+                    return;
+                };
+                if target.serial.as_deref() != Some(serial.as_str()) {
+                    let (_, os, _) =
+                        find_known_host("serial", &serial, &db_pool).unwrap_or_default();
+                    target.os = os;
+                    target.serial = Some(serial.clone());
+                }
+                // 固定主机密钥，密钥与该序列号不符时不再继续
+                if executor.pin_host_key(&target, &serial).await.is_err() {
+                    return;
+                }
+                // 收集带外管理IP地址信息
+                let ipmi_addr = executor.ipmi_lan_address(&target).await;
+                // 收集厂商信息，用于选择 BMC 凭据
+                let vendor = executor
+                    .run_ssh_command(&target, "cat /sys/devices/virtual/dmi/id/sys_vendor")
+                    .await
+                    .ok()
+                    .and_then(CommandOutput::into_stdout)
+                    .map(|vendor| vendor.trim().to_string())
+                    .filter(|vendor| !vendor.is_empty());
+                // 收集启动模式，决定重启方式、iPXE 脚本和分区方案
+                let boot_mode = executor
+                    .run_ssh_command(&target, boot_mode::DETECT_COMMAND)
+                    .await
+                    .ok()
+                    .and_then(CommandOutput::into_stdout)
+                    .and_then(|mode| BootMode::parse(&mode));
+                // 收集网卡信息，用于预览和执行网卡选择规则
+                if let Some(nics) = nic_rules::collect(executor, &target).await {
+                    nic_rules::store_facts(&db_pool.get().unwrap(), &serial, &nics, &current_time);
+                }
+                // 收集安装进度信息，如果能收集到合法信息则入库
+                let install_progress = executor
+                    .run_ssh_command(&target, "cat /tmp/install-progress")
+                    .await
+                    .ok()
+                    .and_then(CommandOutput::into_stdout);
+                match install_progress {
+                    Some(progress) => match progress
+                        .parse::<i32>()
+                        .ok()
+                        .and_then(|p| Progress::try_from(p).ok())
+                    {
+                        Some(progress) => {
+                            println!(
+                                "[INFO] Install progress for IP {} ({}): {}",
+                                ip, serial, progress as i32
+                            );
+                            let host = Host {
+                                ip_address: ip.clone(),
+                                ipmi_address: ipmi_addr,
+                                serial,
+                                mac_address,
+                                vendor,
+                                boot_mode,
+                                install_progress: progress,
+                                last_updated: current_time,
+                            };
+                            // 入库并告诉客户端信息已收集，被状态机拒绝的进度不确认
+                            if add_host_to_db(host, &db_pool) {
+                                executor
+                                    .run_ssh_command(
+                                        &target,
+                                        &format!(
+                                            "echo \"{}\">/tmp/install-progress.ack",
+                                            progress as i32
+                                        ),
+                                    )
+                                    .await
+                                    .ok();
                             }
-                        },
+                        }
                         None => {
-                            println!("[INFO] No install progress found for IP: {}", ip);
+                            println!(
+                                "[INFO] Invalid install progress for IP {}: {}",
+                                ip, progress
+                            );
                         }
+                    },
+                    None => {
+                        println!("[INFO] No install progress found for IP: {}", ip);
                     }
                 }
-            })
-            .await;
+            }
+        })
+        .await;
+}
+
+// 持续监控当前 dhcp.leases 文件
+pub async fn monitor_dhcp_leases<E: CommandExecutor>(
+    executor: E,
+    file_path: &str,
+    interval_secs: u64,
+    concurrency_limit: usize,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
+    loop {
+        // 记录开始时间
+        let start_time = Utc::now();
+        discover_hosts(&executor, file_path, concurrency_limit, db_pool.clone()).await;
         // 如果当前时间与上次检查时间间隔小于指定的间隔，则等待剩余时间
         let elapsed_time = Utc::now().signed_duration_since(start_time).num_seconds();
         if elapsed_time < interval_secs as i64 {
@@ -0,0 +1,298 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 装机历史：install_events 表只追加不修改，记录每次安装进度的变化，用于查看主机时间线和统计各阶段耗时
+use actix_web::{HttpResponse, Responder, web};
+use chrono::NaiveDateTime;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::install_state::{Progress, TransitionSource};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallEvent {
+    id: i64,
+    serial: String,
+    // 发生变化时主机配置的操作系统
+    os: Option<String>,
+    from_progress: Option<i32>,
+    to_progress: i32,
+    source: String,
+    created_at: String,
+    // 发生变化时主机使用的 iPXE 脚本版本
+    ipxe_version: Option<i64>,
+    // 停留在 to_progress 的时间，最后一条记录为空
+    duration_secs: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct StageStats {
+    progress: i32,
+    stage: String,
+    count: usize,
+    min_secs: i64,
+    max_secs: i64,
+    avg_secs: i64,
+}
+
+#[derive(Serialize)]
+struct OsStats {
+    os: Option<String>,
+    // 服务端触发安装的次数
+    installs: usize,
+    installed: usize,
+    failed: usize,
+    timed_out: usize,
+    cancelled: usize,
+    stages: Vec<StageStats>,
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    os: Option<String>,
+}
+
+// 记录一次安装进度变化，操作系统取主机当前的配置，iPXE 脚本版本取本次安装使用的版本，尚未开始安装时取主机固定的版本或操作系统启用的版本
+pub fn record_event(
+    conn: &Connection,
+    serial: &str,
+    from: Option<Progress>,
+    to: Progress,
+    source: TransitionSource,
+    created_at: &str,
+) {
+    conn.execute(
+        "INSERT INTO install_events (serial, os, ipxe_version, from_progress, to_progress, source, created_at)
+         VALUES (
+             ?1,
+             (SELECT os FROM hosts WHERE serial = ?1),
+             (SELECT COALESCE(hosts.install_ipxe_version, hosts.ipxe_version, ipxe.active_version)
+              FROM hosts LEFT JOIN ipxe ON ipxe.os = hosts.os WHERE hosts.serial = ?1),
+             ?2, ?3, ?4, ?5
+         )",
+        params![
+            serial,
+            from.map(|p| p as i32),
+            to as i32,
+            source.as_str(),
+            created_at
+        ],
+    )
+    .unwrap();
+}
+
+// 读取装机历史，按主机和发生顺序排列，并计算每条记录的停留时间
+fn load_events(conn: &Connection, serial: Option<&str>, os: Option<&str>) -> Vec<InstallEvent> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, serial, os, from_progress, to_progress, source, created_at, ipxe_version FROM install_events
+             WHERE (?1 IS NULL OR serial = ?1) ORDER BY serial, id",
+        )
+        .unwrap();
+    let mut events: Vec<InstallEvent> = stmt
+        .query_map(params![serial], |row| {
+            Ok(InstallEvent {
+                id: row.get(0)?,
+                serial: row.get(1)?,
+                os: row.get(2)?,
+                from_progress: row.get(3)?,
+                to_progress: row.get(4)?,
+                source: row.get(5)?,
+                created_at: row.get(6)?,
+                ipxe_version: row.get(7)?,
+                duration_secs: None,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    fill_durations(&mut events);
+    // 按操作系统过滤放在计算停留时间之后，主机中途更换操作系统时不影响前一条记录
+    if let Some(os) = os {
+        events.retain(|event| event.os.as_deref() == Some(os));
+    }
+    events
+}
+
+fn fill_durations(events: &mut [InstallEvent]) {
+    for i in 1..events.len() {
+        let (previous, current) = (&events[i - 1], &events[i]);
+        if previous.serial != current.serial {
+            continue;
+        }
+        let start = NaiveDateTime::parse_from_str(&previous.created_at, TIME_FORMAT);
+        let end = NaiveDateTime::parse_from_str(&current.created_at, TIME_FORMAT);
+        if let (Ok(start), Ok(end)) = (start, end) {
+            events[i - 1].duration_secs = Some((end - start).num_seconds());
+        }
+    }
+}
+
+// 按操作系统汇总安装次数、结果和安装过程中各阶段的耗时
+fn summarize(events: &[InstallEvent]) -> Vec<OsStats> {
+    let mut by_os: BTreeMap<Option<&str>, OsStats> = BTreeMap::new();
+    let mut durations: BTreeMap<(Option<&str>, i32), Vec<i64>> = BTreeMap::new();
+    for event in events {
+        let os = event.os.as_deref();
+        let stats = by_os.entry(os).or_insert_with(|| OsStats {
+            os: event.os.clone(),
+            installs: 0,
+            installed: 0,
+            failed: 0,
+            timed_out: 0,
+            cancelled: 0,
+            stages: Vec::new(),
+        });
+        let Ok(to) = Progress::try_from(event.to_progress) else {
+            continue;
+        };
+        match to {
+            Progress::RebootingToKickstart => stats.installs += 1,
+            Progress::Installed => stats.installed += 1,
+            Progress::Failed => stats.failed += 1,
+            Progress::TimedOut => stats.timed_out += 1,
+            Progress::Cancelled => stats.cancelled += 1,
+            _ => {}
+        }
+        if to.is_installing()
+            && let Some(duration) = event.duration_secs
+        {
+            durations.entry((os, to as i32)).or_default().push(duration);
+        }
+    }
+    for ((os, progress), secs) in durations {
+        by_os.get_mut(&os).unwrap().stages.push(StageStats {
+            progress,
+            stage: Progress::try_from(progress)
+                .map(|p| format!("{p:?}"))
+                .unwrap_or_default(),
+            count: secs.len(),
+            min_secs: secs.iter().copied().min().unwrap_or(0),
+            max_secs: secs.iter().copied().max().unwrap_or(0),
+            avg_secs: secs.iter().sum::<i64>() / secs.len() as i64,
+        });
+    }
+    by_os.into_values().collect()
+}
+
+// 处理 GET /api/hosts/{serial}/events ，返回主机的装机时间线
+pub async fn get_host_events(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let conn = db_pool.get().unwrap();
+    let events = load_events(&conn, Some(&serial), None);
+    if events.is_empty() {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("no install events for {serial}") }));
+    }
+    HttpResponse::Ok().json(events)
+}
+
+// 处理 GET /api/install-stats ，可按 ?os= 过滤
+pub async fn get_install_stats(
+    query: web::Query<StatsQuery>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let events = load_events(&conn, None, query.os.as_deref());
+    HttpResponse::Ok().json(summarize(&events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    #[test]
+    fn stage_durations_per_os() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, os) VALUES ('S1', 'Kylin'), ('S2', 'Kylin')",
+            [],
+        )
+        .unwrap();
+        use Progress::*;
+        for (serial, from, to, time) in [
+            ("S1", None, NotConfigured, "2025-01-01 00:00:00"),
+            ("S2", None, NotConfigured, "2025-01-01 00:00:00"),
+            (
+                "S1",
+                Some(NotConfigured),
+                RebootingToKickstart,
+                "2025-01-01 01:00:00",
+            ),
+            (
+                "S2",
+                Some(NotConfigured),
+                RebootingToKickstart,
+                "2025-01-01 01:00:00",
+            ),
+            (
+                "S1",
+                Some(RebootingToKickstart),
+                KickstartLoaded,
+                "2025-01-01 01:05:00",
+            ),
+            (
+                "S2",
+                Some(RebootingToKickstart),
+                TimedOut,
+                "2025-01-01 01:30:00",
+            ),
+            (
+                "S1",
+                Some(KickstartLoaded),
+                Installed,
+                "2025-01-01 01:25:00",
+            ),
+        ] {
+            record_event(&conn, serial, from, to, TransitionSource::Server, time);
+        }
+        let events = load_events(&conn, Some("S1"), None);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].duration_secs, Some(300));
+        assert_eq!(events[3].duration_secs, None);
+
+        let stats = summarize(&load_events(&conn, None, Some("Kylin")));
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!(
+            (stats.installs, stats.installed, stats.timed_out),
+            (2, 1, 1)
+        );
+        let rebooting = &stats.stages[0];
+        assert_eq!(rebooting.progress, 5);
+        assert_eq!(
+            (
+                rebooting.count,
+                rebooting.min_secs,
+                rebooting.max_secs,
+                rebooting.avg_secs
+            ),
+            (2, 300, 1800, 1050)
+        );
+        assert_eq!(stats.stages[1].avg_secs, 1200);
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 装机队列 API：批量加入 install_queue、查看排队状态以及取消排队
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::install_state::Progress;
+
+#[derive(Deserialize)]
+pub struct EnqueueRequest {
+    // 序列号或 IPMI 地址
+    hosts: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct EnqueueResult {
+    host: String,
+    ipmi_address: Option<String>,
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct QueueItem {
+    ipmi_address: String,
+    serial: Option<String>,
+    os: Option<String>,
+    install_progress: Option<i32>,
+    eligible: bool,
+    // 尚不满足装机条件的原因
+    reasons: Vec<String>,
+}
+
+// 按序列号或 IPMI 地址查找主机，返回 (serial, ipmi_address)
+fn find_host(conn: &Connection, key: &str) -> Option<(Option<String>, Option<String>)> {
+    conn.query_row(
+        "SELECT serial, ipmi_address FROM hosts WHERE serial = ?1 OR ipmi_address = ?1 ORDER BY serial = ?1 DESC LIMIT 1",
+        params![key],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .unwrap()
+}
+
+// 与 start_kickstart_installation 的筛选条件保持一致
+fn not_eligible_reasons(
+    serial: &Option<String>,
+    os: &Option<String>,
+    has_script: bool,
+    install_progress: Option<i32>,
+) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if serial.is_none() {
+        reasons.push("host not found".to_string());
+        return reasons;
+    }
+    match os {
+        None => reasons.push("no os configured".to_string()),
+        Some(os) if !has_script => reasons.push(format!("no iPXE script registered for os {os}")),
+        _ => {}
+    }
+    // 已安装的主机排队后重装
+    if install_progress != Some(Progress::NotConfigured as i32)
+        && install_progress != Some(Progress::Installed as i32)
+    {
+        reasons.push(format!(
+            "install progress is {}, not NotConfigured ({}) or Installed ({})",
+            install_progress
+                .map(|p| p.to_string())
+                .unwrap_or("unknown".to_string()),
+            Progress::NotConfigured as i32,
+            Progress::Installed as i32
+        ));
+    }
+    reasons
+}
+
+// 处理 GET /api/install-queue
+pub async fn list_install_queue(
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(
+            r#"
+            SELECT
+                iq.ipmi_address,
+                h.serial,
+                h.os,
+                h.install_progress,
+                EXISTS (SELECT 1 FROM ipxe WHERE os = h.os AND active_version IS NOT NULL)
+            FROM install_queue iq
+            LEFT JOIN hosts h ON iq.ipmi_address = h.ipmi_address
+            ORDER BY iq.ipmi_address
+            "#,
+        )
+        .unwrap();
+    let items: Vec<QueueItem> = stmt
+        .query_map([], |row| {
+            let serial: Option<String> = row.get(1)?;
+            let os: Option<String> = row.get(2)?;
+            let install_progress: Option<i32> = row.get(3)?;
+            let has_script: bool = row.get(4)?;
+            let reasons = not_eligible_reasons(&serial, &os, has_script, install_progress);
+            Ok(QueueItem {
+                ipmi_address: row.get(0)?,
+                serial,
+                os,
+                install_progress,
+                eligible: reasons.is_empty(),
+                reasons,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(items)
+}
+
+// 处理 POST /api/install-queue ，批量加入装机队列
+pub async fn enqueue_hosts(
+    request: web::Json<EnqueueRequest>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let results: Vec<EnqueueResult> = request
+        .into_inner()
+        .hosts
+        .into_iter()
+        .map(|key| key.trim().to_string())
+        .map(|key| match find_host(&conn, &key) {
+            None => EnqueueResult {
+                host: key,
+                ipmi_address: None,
+                status: "not_found",
+            },
+            Some((_, None)) => EnqueueResult {
+                host: key,
+                ipmi_address: None,
+                status: "no_ipmi_address",
+            },
+            Some((serial, Some(ipmi_address))) => {
+                let inserted = conn
+                    .execute(
+                        "INSERT OR IGNORE INTO install_queue (ipmi_address) VALUES (?1)",
+                        params![ipmi_address],
+                    )
+                    .unwrap();
+                if inserted > 0 {
+                    // 手动排队时重新计算安装次数
+                    conn.execute(
+                        "UPDATE hosts SET install_attempts = 0 WHERE ipmi_address = ?1",
+                        params![ipmi_address],
+                    )
+                    .unwrap();
+                    println!(
+                        "[INFO] Host {} (IPMI: {}) added to install queue",
+                        serial.unwrap_or_default(),
+                        ipmi_address
+                    );
+                }
+                EnqueueResult {
+                    host: key,
+                    ipmi_address: Some(ipmi_address),
+                    status: if inserted > 0 {
+                        "queued"
+                    } else {
+                        "already_queued"
+                    },
+                }
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(results)
+}
+
+// 处理 DELETE /api/install-queue/{host} ，在重启前取消排队
+pub async fn cancel_install(
+    host: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let key = host.into_inner();
+    let conn = db_pool.get().unwrap();
+    // 队列中可能存在尚未被发现的主机，此时直接按 IPMI 地址删除
+    let ipmi_address = match find_host(&conn, &key) {
+        Some((_, Some(ipmi_address))) => ipmi_address,
+        _ => key.clone(),
+    };
+    let deleted = conn
+        .execute(
+            "DELETE FROM install_queue WHERE ipmi_address = ?1",
+            params![ipmi_address],
+        )
+        .unwrap();
+    if deleted > 0 {
+        println!("[INFO] Host {key} (IPMI: {ipmi_address}) removed from install queue");
+        return HttpResponse::NoContent().finish();
+    }
+    let install_progress: Option<i32> = conn
+        .query_row(
+            "SELECT install_progress FROM hosts WHERE ipmi_address = ?1",
+            params![ipmi_address],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+        .flatten();
+    if install_progress
+        .and_then(|p| Progress::try_from(p).ok())
+        .is_some_and(Progress::is_installing)
+    {
+        return HttpResponse::Conflict().json(json!({
+            "error": format!("installation of {key} has already started"),
+            "install_progress": install_progress,
+        }));
+    }
+    HttpResponse::NotFound().json(json!({ "error": format!("{key} is not in install queue") }))
+}
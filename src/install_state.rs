@@ -0,0 +1,295 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 装机状态机：定义安装进度及其允许的状态转换，所有对 hosts.install_progress 的修改都经过这里校验
+use chrono::Local;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::fmt;
+
+use crate::install_events;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Progress {
+    Cancelled = -3,
+    TimedOut = -2,
+    Failed = -1,
+    NotConfigured = 0,
+    RebootingToKickstart = 5,
+    KickstartLoaded = 10,
+    PreInstallFinished = 20,
+    PostInstallFinished = 60,
+    InstallFinished = 80,
+    RebootedToSystem = 85,
+    Installed = 100,
+}
+
+impl TryFrom<i32> for Progress {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            -3 => Ok(Progress::Cancelled),
+            -2 => Ok(Progress::TimedOut),
+            -1 => Ok(Progress::Failed),
+            0 => Ok(Progress::NotConfigured),
+            5 => Ok(Progress::RebootingToKickstart),
+            10 => Ok(Progress::KickstartLoaded),
+            20 => Ok(Progress::PreInstallFinished),
+            60 => Ok(Progress::PostInstallFinished),
+            80 => Ok(Progress::InstallFinished),
+            85 => Ok(Progress::RebootedToSystem),
+            100 => Ok(Progress::Installed),
+            _ => Err(value),
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, *self as i32)
+    }
+}
+
+// 状态转换的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionSource {
+    // 主机发现时通过 SSH 读取 /tmp/install-progress
+    Discovery,
+    // 装机程序调用 /api/progress 或 /api/ping
+    Installer,
+    // 装机进度控制
+    Server,
+}
+
+impl TransitionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransitionSource::Discovery => "discovery",
+            TransitionSource::Installer => "installer",
+            TransitionSource::Server => "server",
+        }
+    }
+}
+
+impl fmt::Display for TransitionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Progress {
+    // 服务端已触发安装，主机正处于安装流程中
+    pub fn is_installing(self) -> bool {
+        (Progress::RebootingToKickstart..=Progress::RebootedToSystem).contains(&self)
+    }
+
+    // 操作系统已写入磁盘，主机再次 PXE 启动时应从本地磁盘启动，而不是进入装机环境
+    pub fn boots_from_disk(self) -> bool {
+        matches!(
+            self,
+            Progress::InstallFinished | Progress::RebootedToSystem | Progress::Installed
+        )
+    }
+
+    // 安装已结束（成功或失败），主机重新进入装机环境后回到 NotConfigured
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            Progress::Installed | Progress::Failed | Progress::TimedOut | Progress::Cancelled
+        )
+    }
+
+    // 检查能否从当前状态转换到 to ，不允许时返回原因
+    pub fn check_transition(self, to: Progress) -> Result<(), String> {
+        use Progress::*;
+        match (self, to) {
+            (from, to) if from == to => Ok(()),
+            (NotConfigured, RebootingToKickstart) => Ok(()),
+            (from, Failed | TimedOut | Cancelled) if !from.is_final() => Ok(()),
+            // 轮询间隔内可能错过中间进度，允许跳过
+            (from, to) if from.is_installing() && to.is_installing() && to > from => Ok(()),
+            (RebootedToSystem, Installed) => Ok(()),
+            (from, NotConfigured) if from.is_final() => Ok(()),
+            (NotConfigured, _) => {
+                Err("installation has not been started by the server".to_string())
+            }
+            (from, Installed) if from.is_installing() => {
+                Err("host has not rebooted into the installed system".to_string())
+            }
+            (from, _) if from.is_installing() => {
+                Err("install progress cannot go backwards".to_string())
+            }
+            (Installed, _) => {
+                Err("host is already installed, it must report NotConfigured first".to_string())
+            }
+            (from, _) => Err(format!(
+                "installation {}, it must report NotConfigured first",
+                match from {
+                    Failed => "failed",
+                    TimedOut => "timed out",
+                    _ => "was cancelled",
+                }
+            )),
+        }
+    }
+}
+
+// 读取主机当前的安装进度，数据库中的旧值无法识别时返回 None
+pub fn current_progress(conn: &Connection, serial: &str) -> Option<Option<Progress>> {
+    conn.query_row(
+        "SELECT install_progress FROM hosts WHERE serial = ?1",
+        params![serial],
+        |row| row.get::<_, Option<i32>>(0),
+    )
+    .optional()
+    .unwrap()
+    .map(|progress| progress.and_then(|p| Progress::try_from(p).ok()))
+}
+
+// 校验并修改主机安装进度并记录装机历史，返回进度是否发生变化；不允许的转换记录日志后返回原因
+pub fn set_install_progress(
+    conn: &Connection,
+    serial: &str,
+    to: Progress,
+    source: TransitionSource,
+) -> Result<bool, String> {
+    let Some(from) = current_progress(conn, serial) else {
+        return Err(format!("host {serial} not found"));
+    };
+    if let Some(from) = from {
+        if let Err(reason) = from.check_transition(to) {
+            println!(
+                "[WARN] Rejected install progress of {serial} from {from} to {to} ({source}): {reason}"
+            );
+            return Err(reason);
+        }
+        if from == to {
+            return Ok(false);
+        }
+    }
+    let current_time = Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    conn.execute(
+        "UPDATE hosts SET install_progress = ?1, last_updated = ?2, progress_updated = ?2 WHERE serial = ?3",
+        params![to as i32, current_time, serial],
+    )
+    .unwrap();
+    // 开始安装时确定本次安装使用的 iPXE 脚本版本，之后修改启用的版本不影响正在安装的主机
+    match to {
+        Progress::RebootingToKickstart => {
+            conn.execute(
+                "UPDATE hosts SET install_ipxe_version = COALESCE(ipxe_version, (SELECT active_version FROM ipxe WHERE ipxe.os = hosts.os)) WHERE serial = ?1",
+                params![serial],
+            )
+            .unwrap();
+        }
+        Progress::NotConfigured => {
+            conn.execute(
+                "UPDATE hosts SET install_ipxe_version = NULL WHERE serial = ?1",
+                params![serial],
+            )
+            .unwrap();
+        }
+        _ => {}
+    }
+    install_events::record_event(conn, serial, from, to, source, &current_time);
+    println!(
+        "[INFO] Install progress of {serial} changed from {} to {to} ({source})",
+        from.map(|p| p.to_string()).unwrap_or("unknown".to_string())
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    #[test]
+    fn progress_from_i32() {
+        assert_eq!(Progress::try_from(85), Ok(Progress::RebootedToSystem));
+        assert_eq!(Progress::try_from(-2), Ok(Progress::TimedOut));
+        assert_eq!(Progress::try_from(42), Err(42));
+    }
+
+    #[test]
+    fn allowed_transitions() {
+        use Progress::*;
+        for (from, to) in [
+            (NotConfigured, RebootingToKickstart),
+            (RebootingToKickstart, KickstartLoaded),
+            (KickstartLoaded, PostInstallFinished),
+            (InstallFinished, RebootedToSystem),
+            (RebootedToSystem, Installed),
+            (PreInstallFinished, Failed),
+            (RebootingToKickstart, TimedOut),
+            (KickstartLoaded, Cancelled),
+            (Installed, NotConfigured),
+            (Failed, NotConfigured),
+            (KickstartLoaded, KickstartLoaded),
+        ] {
+            assert!(from.check_transition(to).is_ok(), "{from} -> {to}");
+        }
+    }
+
+    #[test]
+    fn rejected_transitions() {
+        use Progress::*;
+        for (from, to) in [
+            (KickstartLoaded, NotConfigured),
+            (PostInstallFinished, PreInstallFinished),
+            (NotConfigured, KickstartLoaded),
+            (KickstartLoaded, Installed),
+            (Installed, RebootingToKickstart),
+            (Installed, Failed),
+            (Cancelled, KickstartLoaded),
+            (TimedOut, Failed),
+        ] {
+            assert!(from.check_transition(to).is_err(), "{from} -> {to}");
+        }
+    }
+
+    #[test]
+    fn rejected_transition_is_not_stored() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, install_progress) VALUES ('S1', 10)",
+            [],
+        )
+        .unwrap();
+        assert!(
+            set_install_progress(
+                &conn,
+                "S1",
+                Progress::NotConfigured,
+                TransitionSource::Discovery
+            )
+            .is_err()
+        );
+        assert_eq!(
+            current_progress(&conn, "S1"),
+            Some(Some(Progress::KickstartLoaded))
+        );
+        assert_eq!(
+            set_install_progress(&conn, "S1", Progress::Failed, TransitionSource::Installer),
+            Ok(true)
+        );
+        assert_eq!(current_progress(&conn, "S1"), Some(Some(Progress::Failed)));
+    }
+}
@@ -0,0 +1,512 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 安装程序类型：不同发行版的安装程序使用不同格式的应答文件和进度上报方式。操作系统登记所用的安装程序，
+// 服务端用主机字段渲染该操作系统的应答文件模板：anaconda 为 kickstart ，debian-installer 为 preseed ，
+// subiquity 为 autoinstall 的 user-data 和 meta-data ，autoyast 为 AutoYaST XML
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::config::InstallerConfig;
+use crate::install_state::Progress;
+use crate::ipxe_catalog::answer_template_for_host;
+use crate::kickstart;
+use crate::network_profile;
+use crate::os_profile::{self, os_settings};
+use crate::template::{self, TemplateError, Vars};
+
+static INSTALLER_CONFIG: OnceLock<InstallerConfig> = OnceLock::new();
+
+// 业务网络固定为 /24
+pub const NETMASK: &str = "255.255.255.0";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallerFamily {
+    // RHEL 系（麒麟等）的 anaconda ，使用 kickstart
+    #[default]
+    Anaconda,
+    // Debian 的 debian-installer ，使用 preseed
+    DebianInstaller,
+    // Ubuntu Server 的 subiquity ，使用 autoinstall
+    Subiquity,
+    // SLES 的 YaST ，使用 AutoYaST
+    Autoyast,
+}
+
+// 安装程序中可用的进度上报方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressHook {
+    // 安装程序运行 sshd（inst.sshd），服务端通过 SSH 读取 /tmp/install-progress
+    InstallProgressFile,
+    // kickstart %pre/%post 中调用 {{ progress_hook }} 定义的 report_progress
+    KickstartScripts,
+    // preseed/early_command 和 preseed/late_command ，装好的系统首次启动时由 systemd 服务上报
+    PreseedCommands,
+    // autoinstall 的 early-commands 和 late-commands ，装好的系统首次启动时由 systemd 服务上报
+    AutoinstallCommands,
+    // AutoYaST 的 pre-scripts 、postpartitioning-scripts 、chroot-scripts 和 init-scripts
+    AutoyastScripts,
+}
+
+impl InstallerFamily {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InstallerFamily::Anaconda => "anaconda",
+            InstallerFamily::DebianInstaller => "debian-installer",
+            InstallerFamily::Subiquity => "subiquity",
+            InstallerFamily::Autoyast => "autoyast",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "anaconda" => Some(InstallerFamily::Anaconda),
+            "debian-installer" => Some(InstallerFamily::DebianInstaller),
+            "subiquity" => Some(InstallerFamily::Subiquity),
+            "autoyast" => Some(InstallerFamily::Autoyast),
+            _ => None,
+        }
+    }
+
+    // 应答文件的名称，用于日志和错误信息
+    fn answer_name(&self) -> &'static str {
+        match self {
+            InstallerFamily::Anaconda => "kickstart",
+            InstallerFamily::DebianInstaller => "preseed",
+            InstallerFamily::Subiquity => "autoinstall user-data",
+            InstallerFamily::Autoyast => "AutoYaST profile",
+        }
+    }
+
+    // 安装程序的进度上报方式，只有 anaconda 的装机环境运行 sshd
+    pub fn progress_hooks(&self) -> &'static [ProgressHook] {
+        match self {
+            InstallerFamily::Anaconda => &[
+                ProgressHook::InstallProgressFile,
+                ProgressHook::KickstartScripts,
+            ],
+            InstallerFamily::DebianInstaller => &[ProgressHook::PreseedCommands],
+            InstallerFamily::Subiquity => &[ProgressHook::AutoinstallCommands],
+            InstallerFamily::Autoyast => &[ProgressHook::AutoyastScripts],
+        }
+    }
+
+    // 让安装程序获取应答文件的内核参数模板
+    pub fn kernel_args(&self) -> &'static str {
+        match self {
+            InstallerFamily::Anaconda => "inst.ks={{ answer_url }}",
+            InstallerFamily::DebianInstaller => "auto=true priority=critical url={{ answer_url }}",
+            InstallerFamily::Subiquity => "autoinstall ds=nocloud-net;s={{ answer_url }}",
+            InstallerFamily::Autoyast => "autoyast={{ answer_url }}",
+        }
+    }
+
+    // 主机获取应答文件的地址，iPXE 模板中为 {{ answer_url }} 。
+    // subiquity 的 nocloud-net 数据源在该地址后拼接 user-data 和 meta-data
+    pub fn answer_url(&self, server_url: &str, serial: &str) -> String {
+        match self {
+            InstallerFamily::Anaconda => format!("{server_url}/api/kickstart/{serial}"),
+            InstallerFamily::DebianInstaller => format!("{server_url}/api/preseed/{serial}"),
+            InstallerFamily::Subiquity => format!("{server_url}/api/autoinstall/{serial}/"),
+            InstallerFamily::Autoyast => format!("{server_url}/api/autoyast/{serial}"),
+        }
+    }
+
+    // 导入时检查应答文件模板，autoinstall 的 user-data 必须以 #cloud-config 开头，AutoYaST 必须有 <profile> 元素
+    pub fn validate_answer_template(&self, name: &str, template: &str) -> Result<(), String> {
+        match self {
+            InstallerFamily::Subiquity if !template.trim_start().starts_with("#cloud-config") => {
+                Err(format!(
+                    "{name} is not an autoinstall user-data (missing #cloud-config)"
+                ))
+            }
+            InstallerFamily::Autoyast if !template.contains("<profile") => Err(format!(
+                "{name} is not an AutoYaST profile (missing <profile>)"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for InstallerFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 启动时设置 root 密码哈希
+pub fn init(config: &InstallerConfig) {
+    INSTALLER_CONFIG.set(config.clone()).ok();
+}
+
+// 业务网络的网关为所在 /24 网段的 .1
+pub fn default_gateway(public_ip_addr: &str) -> String {
+    public_ip_addr
+        .split('.')
+        .take(3)
+        .collect::<Vec<&str>>()
+        .join(".")
+        + ".1"
+}
+
+// 依次上报多个进度的 shell 命令，同时写入 /tmp/install-progress 。debian-installer 中只有 busybox wget
+fn report_command(family: InstallerFamily, progress_url: &str, stages: &[Progress]) -> String {
+    let post = match family {
+        InstallerFamily::DebianInstaller => "wget -q -T 3 -O /dev/null --post-data \"progress=$p\"",
+        _ => "curl -m 3 -s -o /dev/null -d \"progress=$p\"",
+    };
+    let stages: Vec<String> = stages.iter().map(|p| (*p as i32).to_string()).collect();
+    format!(
+        "for p in {}; do echo $p >/tmp/install-progress; {post} \"{progress_url}\" || true; done",
+        stages.join(" ")
+    )
+}
+
+// 分区完成后上报 20 ：debian-installer 和 subiquity 没有分区之后的钩子，在后台等待分区挂载到 /target
+fn partitioned_watch_command(family: InstallerFamily, progress_url: &str) -> String {
+    format!(
+        "(while ! grep -qs ' /target ' /proc/mounts; do sleep 5; done; {}) </dev/null >/dev/null 2>&1 &",
+        report_command(family, progress_url, &[Progress::PreInstallFinished])
+    )
+}
+
+// 装好的系统首次启动后上报 85 ，网络可能尚未就绪，多次重试；系统中没有 curl 时使用 wget
+fn firstboot_report(progress_url: &str) -> String {
+    let progress = Progress::RebootedToSystem as i32;
+    format!(
+        "curl -m 3 -s --retry 10 --retry-all-errors -d progress={progress} -o /dev/null {progress_url} \
+         || wget -q -T 3 -t 10 -O /dev/null --post-data progress={progress} {progress_url}"
+    )
+}
+
+// 在 /target 中安装首次启动时上报 85 的 systemd 服务，与 samples/ks-install.cfg 中的服务相同，执行后自行禁用
+fn firstboot_unit_command(progress_url: &str) -> String {
+    let unit = [
+        "[Unit]",
+        "Description=Report install progress to CloudBoot LCE",
+        "Wants=network-online.target",
+        "After=network-online.target",
+        "[Service]",
+        "Type=oneshot",
+        &format!(
+            "ExecStart=/bin/sh -c \"{}\"",
+            firstboot_report(progress_url)
+        ),
+        "ExecStartPost=/bin/systemctl disable cloudboot-firstboot.service",
+        "[Install]",
+        "WantedBy=multi-user.target",
+    ];
+    let lines: Vec<String> = unit.iter().map(|line| format!("'{line}'")).collect();
+    format!(
+        "mkdir -p /target/etc/systemd/system/multi-user.target.wants; \
+         printf '%s\\n' {} >/target/etc/systemd/system/cloudboot-firstboot.service; \
+         ln -sf ../cloudboot-firstboot.service /target/etc/systemd/system/multi-user.target.wants/cloudboot-firstboot.service",
+        lines.join(" ")
+    )
+}
+
+// XML 文本中的特殊字符转义
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// 应答文件模板变量：iPXE 模板的所有变量和操作系统的 repo_url ，加上 root 密码哈希、进度上报地址和业务网络参数，
+// 以及各安装程序的进度上报钩子；主机不存在时返回 None
+pub fn answer_vars(conn: &Connection, serial: &str, family: InstallerFamily) -> Option<Vars> {
+    let mut vars = template::host_vars(conn, serial)?;
+    if let Some(os) = vars.get("os").cloned() {
+        os_profile::insert_os_vars(conn, &os, &mut vars);
+    }
+    let progress_url = format!("{}/api/progress/{serial}", vars["server_url"]);
+    vars.insert("progress_url".to_string(), progress_url.clone());
+    if let Some(hash) = INSTALLER_CONFIG
+        .get()
+        .and_then(|config| config.root_password_hash.clone())
+    {
+        vars.insert("root_password_hash".to_string(), hash);
+    }
+    // 网关和掩码取自网络配置中带默认网关的接口，没有网络配置时按 public_ip_addr 推算
+    match network_profile::host_profile(conn, serial) {
+        Some(profile) => {
+            let primary = profile.primary();
+            if let Some(gateway) = primary.gateway {
+                vars.insert("gateway".to_string(), gateway.to_string());
+            }
+            if let Some(cidr) = primary.ipv4_addresses().next() {
+                vars.insert("netmask".to_string(), cidr.netmask().to_string());
+            }
+        }
+        None => {
+            if let Some(public_ip_addr) = vars.get("public_ip_addr") {
+                let gateway = default_gateway(public_ip_addr);
+                vars.insert("gateway".to_string(), gateway);
+                vars.insert("netmask".to_string(), NETMASK.to_string());
+            }
+        }
+    }
+    // 安装开始前上报 10 ，分区完成后上报 20 ，写入磁盘后上报 60 和 80 ，装好的系统首次启动后上报 85
+    let early = report_command(family, &progress_url, &[Progress::KickstartLoaded]);
+    let late = report_command(
+        family,
+        &progress_url,
+        &[Progress::PostInstallFinished, Progress::InstallFinished],
+    );
+    match family {
+        InstallerFamily::Anaconda => kickstart::anaconda_vars(conn, serial, &mut vars),
+        // preseed/early_command 和 preseed/late_command 的值为一行 shell 命令
+        InstallerFamily::DebianInstaller => {
+            let early = format!(
+                "{early}; {}",
+                partitioned_watch_command(family, &progress_url)
+            );
+            vars.insert("early_command".to_string(), early);
+            vars.insert("late_command".to_string(), late);
+            vars.insert(
+                "firstboot_command".to_string(),
+                firstboot_unit_command(&progress_url),
+            );
+        }
+        // early-commands 和 late-commands 的列表项，渲染为带引号的 YAML 字符串
+        InstallerFamily::Subiquity => {
+            let early = format!(
+                "{early}; {}",
+                partitioned_watch_command(family, &progress_url)
+            );
+            vars.insert("early_command".to_string(), json!(early).to_string());
+            vars.insert("late_command".to_string(), json!(late).to_string());
+            vars.insert(
+                "firstboot_command".to_string(),
+                json!(firstboot_unit_command(&progress_url)).to_string(),
+            );
+        }
+        // pre-scripts 、postpartitioning-scripts 、chroot-scripts 和 init-scripts 的 <source> 内容
+        InstallerFamily::Autoyast => {
+            let partitioned =
+                report_command(family, &progress_url, &[Progress::PreInstallFinished]);
+            vars.insert("early_command".to_string(), xml_escape(&early));
+            vars.insert("partitioned_command".to_string(), xml_escape(&partitioned));
+            vars.insert("late_command".to_string(), xml_escape(&late));
+            vars.insert(
+                "firstboot_command".to_string(),
+                xml_escape(&firstboot_report(&progress_url)),
+            );
+        }
+    }
+    Some(vars)
+}
+
+// 渲染主机的应答文件，使用本次安装确定的版本（尚未开始安装时为主机固定的版本或操作系统启用的版本）中的模板，版本中没有模板时使用配置档内置的模板
+pub fn render_answer(conn: &Connection, serial: &str, family: InstallerFamily) -> HttpResponse {
+    let name = family.answer_name();
+    let host: Option<(Option<String>, Option<i64>)> = conn
+        .query_row(
+            "SELECT os, COALESCE(install_ipxe_version, ipxe_version) FROM hosts WHERE serial = ?1",
+            params![serial],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .unwrap();
+    let Some((os, host_version)) = host else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    };
+    let Some(os) = os else {
+        return HttpResponse::Conflict()
+            .json(json!({ "error": format!("host {serial} has no os") }));
+    };
+    let settings = os_settings(conn, &os);
+    if let Some(settings) = &settings
+        && settings.installer != family
+    {
+        return HttpResponse::Conflict().json(json!({
+            "error": format!("os {os} uses installer {}, not {family}", settings.installer)
+        }));
+    }
+    let builtin = settings
+        .and_then(|settings| settings.profile)
+        .and_then(|profile| profile.builtin_answer_template());
+    let (version, answer_template) = match answer_template_for_host(conn, &os, host_version) {
+        Ok((version, answer_template)) => {
+            match answer_template.or_else(|| builtin.map(String::from)) {
+                Some(answer_template) => (version, answer_template),
+                None => {
+                    let e = format!("version {version} of {os} has no answer template");
+                    println!("[ERROR] No {name} for {serial}: {e}");
+                    return HttpResponse::NotFound().json(json!({ "error": e }));
+                }
+            }
+        }
+        Err(e) => {
+            println!("[ERROR] No {name} for {serial}: {e}");
+            return HttpResponse::NotFound().json(json!({ "error": e }));
+        }
+    };
+    let vars = answer_vars(conn, serial, family).unwrap_or_default();
+    match template::render(&answer_template, &vars) {
+        Ok(answer) => {
+            println!("[INFO] Offering {name} of {os} version {version} for {serial}");
+            HttpResponse::Ok().body(answer)
+        }
+        Err(e) => {
+            println!("[ERROR] Cannot render {name} of {os} version {version} for {serial}: {e}");
+            let missing = match &e {
+                TemplateError::Missing(names) => names.clone(),
+                _ => Vec::new(),
+            };
+            HttpResponse::UnprocessableEntity().json(json!({
+                "error": format!("cannot render {name} of {os} version {version}: {e}"),
+                "missing": missing,
+            }))
+        }
+    }
+}
+
+// 处理 GET /api/preseed/{serial}
+pub async fn get_preseed(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    render_answer(&conn, &serial, InstallerFamily::DebianInstaller)
+}
+
+// 处理 GET /api/autoyast/{serial}
+pub async fn get_autoyast(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    render_answer(&conn, &serial, InstallerFamily::Autoyast)
+}
+
+// 处理 GET /api/autoinstall/{serial}/user-data
+pub async fn get_autoinstall_user_data(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    render_answer(&conn, &serial, InstallerFamily::Subiquity)
+}
+
+// 处理 GET /api/autoinstall/{serial}/meta-data ，nocloud-net 数据源要求存在，instance-id 使用序列号
+pub async fn get_autoinstall_meta_data(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner();
+    let conn = db_pool.get().unwrap();
+    let hostname: Option<Option<String>> = conn
+        .query_row(
+            "SELECT hostname FROM hosts WHERE serial = ?1",
+            params![serial],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap();
+    let Some(hostname) = hostname else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    };
+    let hostname = hostname.unwrap_or_else(|| serial.clone());
+    HttpResponse::Ok().body(format!(
+        "instance-id: {serial}\nlocal-hostname: {hostname}\n"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    #[test]
+    fn hooks_follow_installer_family() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute("INSERT INTO hosts (serial) VALUES ('S1')", [])
+            .unwrap();
+        let vars = answer_vars(&conn, "S1", InstallerFamily::DebianInstaller).unwrap();
+        // 安装开始时只上报 10 ，分区挂载到 /target 后才上报 20
+        assert_eq!(
+            vars["early_command"],
+            "for p in 10; do echo $p >/tmp/install-progress; \
+             wget -q -T 3 -O /dev/null --post-data \"progress=$p\" \
+             \"http://osinstall.pxe/api/progress/S1\" || true; done; \
+             (while ! grep -qs ' /target ' /proc/mounts; do sleep 5; done; \
+             for p in 20; do echo $p >/tmp/install-progress; \
+             wget -q -T 3 -O /dev/null --post-data \"progress=$p\" \
+             \"http://osinstall.pxe/api/progress/S1\" || true; done) </dev/null >/dev/null 2>&1 &"
+        );
+        // 首次启动时上报 85 的服务安装到 /target 中
+        let firstboot = &vars["firstboot_command"];
+        assert!(firstboot.contains(">/target/etc/systemd/system/cloudboot-firstboot.service;"));
+        assert!(
+            firstboot.contains("-d progress=85 -o /dev/null http://osinstall.pxe/api/progress/S1")
+        );
+        assert!(!vars.contains_key("progress_hook"));
+
+        // autoinstall 的命令渲染为 YAML 字符串
+        let vars = answer_vars(&conn, "S1", InstallerFamily::Subiquity).unwrap();
+        let late: String = serde_json::from_str(&vars["late_command"]).unwrap();
+        assert!(late.starts_with("for p in 60 80; do"));
+        assert!(late.contains("curl -m 3"));
+        let firstboot: String = serde_json::from_str(&vars["firstboot_command"]).unwrap();
+        assert!(
+            firstboot.starts_with("mkdir -p /target/etc/systemd/system/multi-user.target.wants;")
+        );
+
+        // AutoYaST 在 postpartitioning-scripts 中上报 20 ，在 init-scripts 中上报 85
+        let vars = answer_vars(&conn, "S1", InstallerFamily::Autoyast).unwrap();
+        assert!(vars["early_command"].starts_with("for p in 10; do"));
+        assert!(vars["partitioned_command"].starts_with("for p in 20; do"));
+        assert!(vars["firstboot_command"].starts_with("curl -m 3 -s --retry 10"));
+
+        let vars = answer_vars(&conn, "S1", InstallerFamily::Anaconda).unwrap();
+        assert!(vars.contains_key("progress_hook"));
+        assert!(!vars.contains_key("early_command"));
+    }
+
+    #[test]
+    fn answer_url_and_validation() {
+        assert_eq!(
+            InstallerFamily::Subiquity.answer_url("http://osinstall.pxe", "S1"),
+            "http://osinstall.pxe/api/autoinstall/S1/"
+        );
+        assert!(
+            InstallerFamily::Subiquity
+                .validate_answer_template("user-data", "autoinstall:\n")
+                .is_err()
+        );
+        assert!(
+            InstallerFamily::Subiquity
+                .validate_answer_template("user-data", "#cloud-config\nautoinstall:\n")
+                .is_ok()
+        );
+        assert_eq!(
+            InstallerFamily::parse("debian-installer"),
+            Some(InstallerFamily::DebianInstaller)
+        );
+    }
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 装机程序调用的 API：kickstart 脚本通过这些接口向服务端报告状态
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use chrono::Local;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{OptionalExtension, params};
+use serde::Deserialize;
+
+use crate::install_state::{Progress, TransitionSource, set_install_progress};
+
+// hosts.installer_action 取值：让装机程序中止安装（重启）
+pub const INSTALLER_ACTION_ABORT: &str = "abort";
+
+#[derive(Deserialize)]
+pub struct PingQuery {
+    serial: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ProgressReport {
+    progress: i32,
+    message: Option<String>,
+}
+
+// 获取客户端 IP，经 nginx 转发时取 X-Forwarded-For 中的第一个地址
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    req.connection_info()
+        .realip_remote_addr()
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty())
+}
+
+// 校验主机上报的序列号：只允许字母、数字和 ._- ，避免写入数据库或拼接进脚本的值带有换行等字符
+pub fn validate_serial(serial: &str) -> Result<(), String> {
+    if serial.is_empty() || serial.len() > 64 {
+        return Err(format!("invalid serial length: {}", serial.len()));
+    }
+    if !serial
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+    {
+        return Err(format!("invalid serial: {serial:?}"));
+    }
+    Ok(())
+}
+
+// 处理 /api/ping ，记录主机最后在线时间，并告诉装机程序继续还是中止
+pub async fn ping(
+    req: HttpRequest,
+    query: web::Query<PingQuery>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let ip_address = client_ip(&req);
+    let serial = query
+        .into_inner()
+        .serial
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    if let Some(serial) = &serial
+        && let Err(e) = validate_serial(serial)
+    {
+        println!("[INFO] Ping with {e}");
+        return HttpResponse::BadRequest().body(format!("{e}\n"));
+    }
+    let current_time = Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    let conn = db_pool.get().unwrap();
+    // 优先按序列号查找主机，没有序列号时按 IP 地址查找
+    let host: Option<(String, Option<String>)> = match (&serial, &ip_address) {
+        (Some(serial), _) => conn
+            .query_row(
+                "SELECT serial, installer_action FROM hosts WHERE serial = ?1",
+                params![serial],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap(),
+        (None, Some(ip)) => conn
+            .query_row(
+                "SELECT serial, installer_action FROM hosts WHERE ip_address = ?1 AND serial IS NOT NULL",
+                params![ip],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .unwrap(),
+        (None, None) => None,
+    };
+    let action = match host {
+        Some((serial, action)) => {
+            conn.execute(
+                "UPDATE hosts SET last_seen = ?1, ip_address = COALESCE(?2, ip_address) WHERE serial = ?3",
+                params![current_time, ip_address, serial],
+            )
+            .unwrap();
+            // 中止指令只下发一次，避免主机重启后再次被中止
+            if action.as_deref() == Some(INSTALLER_ACTION_ABORT) {
+                conn.execute(
+                    "UPDATE hosts SET installer_action = NULL WHERE serial = ?1",
+                    params![serial],
+                )
+                .unwrap();
+                println!("[INFO] Instructing installer of {serial} to abort");
+                set_install_progress(
+                    &conn,
+                    &serial,
+                    Progress::Cancelled,
+                    TransitionSource::Installer,
+                )
+                .ok();
+            }
+            action
+        }
+        None => {
+            match &serial {
+                // 未纳管的主机先记录序列号，其余信息由主机发现补全
+                Some(serial) => {
+                    conn.execute(
+                        "INSERT INTO hosts (serial, ip_address, last_seen) VALUES (?1, ?2, ?3)",
+                        params![serial, ip_address, current_time],
+                    )
+                    .unwrap();
+                    println!("[INFO] New host {serial} registered by ping");
+                }
+                None => println!(
+                    "[INFO] Ping from unknown host: {}",
+                    ip_address.as_deref().unwrap_or("unknown")
+                ),
+            }
+            None
+        }
+    };
+    if action.as_deref() == Some(INSTALLER_ACTION_ABORT) {
+        // 非 2xx 状态码使 curl -f 失败，装机程序随即重启
+        return HttpResponse::Gone().body("abort\n");
+    }
+    HttpResponse::Ok().body("continue\n")
+}
+
+// 处理 POST /api/progress/{serial} ，由 kickstart 脚本主动上报装机进度，返回服务端确认的进度
+pub async fn report_progress(
+    req: HttpRequest,
+    serial: web::Path<String>,
+    report: web::Form<ProgressReport>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let serial = serial.into_inner().trim().to_string();
+    if let Err(e) = validate_serial(&serial) {
+        println!("[INFO] Install progress reported with {e}");
+        return HttpResponse::BadRequest().body(format!("{e}\n"));
+    }
+    let report = report.into_inner();
+    let progress = match Progress::try_from(report.progress) {
+        Ok(progress) => progress,
+        Err(value) => {
+            println!("[INFO] Invalid install progress reported by {serial}: {value}");
+            return HttpResponse::BadRequest().body(format!("invalid progress: {value}\n"));
+        }
+    };
+    let message = report
+        .message
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty());
+    let ip_address = client_ip(&req);
+    let current_time = Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    let conn = db_pool.get().unwrap();
+    let updated = conn
+        .execute(
+            "UPDATE hosts SET last_seen = ?1, ip_address = COALESCE(?2, ip_address) WHERE serial = ?3",
+            params![current_time, ip_address, serial],
+        )
+        .unwrap();
+    // 未纳管的主机先以 NotConfigured 登记，上报的进度同样经过状态机校验并记录事件
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO hosts (serial, ip_address, install_progress, last_updated, last_seen, progress_updated) VALUES (?1, ?2, ?3, ?4, ?4, ?4)",
+            params![
+                serial,
+                ip_address,
+                Progress::NotConfigured as i32,
+                current_time
+            ],
+        )
+        .unwrap();
+        println!("[INFO] New host {serial} registered by progress report");
+    }
+    if let Err(reason) = set_install_progress(&conn, &serial, progress, TransitionSource::Installer)
+    {
+        return HttpResponse::Conflict().body(format!("{reason}\n"));
+    }
+    conn.execute(
+        "UPDATE hosts SET progress_message = ?1 WHERE serial = ?2",
+        params![message, serial],
+    )
+    .unwrap();
+    println!(
+        "[INFO] Install progress reported by {serial}: {}{}",
+        progress as i32,
+        message.map(|m| format!(" ({m})")).unwrap_or_default()
+    );
+    HttpResponse::Ok().body(format!("{}\n", progress as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+    use actix_web::{App, http::StatusCode, test};
+
+    #[actix_web::test]
+    async fn unknown_hosts_go_through_the_state_machine() {
+        let db_pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        init_db(&db_pool.get().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_pool.clone()))
+                .route("/api/ping", web::get().to(ping))
+                .route("/api/progress/{serial}", web::post().to(report_progress)),
+        )
+        .await;
+        let report = |serial: &str, progress: i32| {
+            test::TestRequest::post()
+                .uri(&format!("/api/progress/{serial}"))
+                .set_form([("progress", progress.to_string())])
+                .to_request()
+        };
+
+        // 未纳管的主机不能直接上报安装中的进度，登记为 NotConfigured
+        let response = test::call_service(&app, report("S1", 10)).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let progress: i32 = db_pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT install_progress FROM hosts WHERE serial = 'S1'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(progress, 0);
+        let response = test::call_service(&app, report("S1", 0)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // 序列号带有换行等字符时拒绝
+        let response = test::call_service(&app, report("S2%0Areboot", 0)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/api/ping?serial=S2%0Areboot")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let hosts: i64 = db_pool
+            .get()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM hosts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(hosts, 1);
+    }
+}
@@ -0,0 +1,912 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 操作系统目录 API：管理操作系统使用的安装程序，以及 iPXE 脚本、应答文件模板的对应关系。脚本内容按版本保存在 ipxe_versions 表中，
+// 从文件导入后生成新版本并启用，可以回滚到旧版本，也可以将主机固定在某一版本
+use actix_web::{HttpResponse, Responder, web};
+use chrono::Local;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+use crate::boot_mode::BootMode;
+use crate::hosts_api::nullable;
+use crate::installer::{InstallerFamily, ProgressHook};
+use crate::network_profile::NetworkFormat;
+use crate::os_profile::{OsProfile, check_profile, os_settings};
+use crate::template;
+
+#[derive(Serialize)]
+pub struct OsEntry {
+    os: String,
+    // 安装程序类型，决定应答文件的格式和获取地址
+    installer: InstallerFamily,
+    // 配置档，决定追加的内核参数和内置的应答文件模板
+    profile: Option<OsProfile>,
+    repo_url: Option<String>,
+    // 装机后网络配置文件的格式
+    network_format: NetworkFormat,
+    // 安装程序中可用的进度上报方式
+    progress_hooks: &'static [ProgressHook],
+    // 未固定版本的主机使用的版本
+    active_version: Option<i64>,
+    versions: Vec<OsVersion>,
+    // 引用该操作系统的主机序列号
+    hosts: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct OsVersion {
+    version: i64,
+    // 导入时的文件路径
+    script_file: Option<String>,
+    // 传统 BIOS 启动的主机使用的脚本，没有时同样使用 script
+    bios_script_file: Option<String>,
+    has_bios_script: bool,
+    // 安装程序的应答文件模板（kickstart 、preseed 或 autoinstall user-data）
+    answer_template_file: Option<String>,
+    has_answer_template: bool,
+    created_at: String,
+    // 固定在该版本的主机
+    pinned_hosts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewOsEntry {
+    os: String,
+    script: String,
+    bios_script: Option<String>,
+    answer_template: Option<String>,
+    // 未指定时使用配置档的安装程序，都未指定时为 anaconda
+    installer: Option<InstallerFamily>,
+    profile: Option<OsProfile>,
+    repo_url: Option<String>,
+    // 未指定时为 keyfile
+    network_format: Option<NetworkFormat>,
+}
+
+// PATCH 请求中 script 、bios_script 或 answer_template 从文件导入新版本并启用，未出现的脚本沿用当前版本，
+// bios_script 或 answer_template 传 null 时去掉；active_version 切换到已有版本，用于回滚；
+// installer 或 profile 更换安装程序，当前版本有应答文件模板时需要同时导入新格式的模板；profile 和 repo_url 传 null 时去掉；
+// network_format 更换装机后网络配置文件的格式
+#[derive(Deserialize)]
+pub struct OsEntryPatch {
+    installer: Option<InstallerFamily>,
+    #[serde(default, deserialize_with = "nullable")]
+    profile: Option<Option<OsProfile>>,
+    #[serde(default, deserialize_with = "nullable")]
+    repo_url: Option<Option<String>>,
+    network_format: Option<NetworkFormat>,
+    script: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    bios_script: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    answer_template: Option<Option<String>>,
+    active_version: Option<i64>,
+}
+
+// 导出的内容：UEFI 脚本（默认）、BIOS 脚本或应答文件模板
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ExportVariant {
+    #[default]
+    Uefi,
+    Bios,
+    AnswerTemplate,
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    variant: ExportVariant,
+}
+
+// 一个版本的脚本内容
+struct ScriptContent {
+    script: String,
+    bios_script: Option<String>,
+    answer_template: Option<String>,
+    script_file: Option<String>,
+    bios_script_file: Option<String>,
+    answer_template_file: Option<String>,
+}
+
+// 检查脚本以 #!ipxe 开头且模板语法正确
+fn validate_ipxe_script(name: &str, script: &str) -> Result<(), String> {
+    if !script.trim_start().starts_with("#!ipxe") {
+        return Err(format!("{name} is not an iPXE script (missing #!ipxe)"));
+    }
+    template::variables(script)
+        .map(|_| ())
+        .map_err(|e| format!("{name}: {e}"))
+}
+
+// 读取导入目录中的文件。路径解析符号链接和 .. 之后再检查，避免通过接口读取服务端的其他文件（例如密钥文件）
+fn read_import_file(import_dir: &Path, path: &str) -> Result<String, String> {
+    let import_dir = fs::canonicalize(import_dir)
+        .map_err(|e| format!("cannot read import directory {}: {e}", import_dir.display()))?;
+    let file = fs::canonicalize(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    if !file.starts_with(&import_dir) {
+        return Err(format!(
+            "{path} is outside the import directory {}",
+            import_dir.display()
+        ));
+    }
+    fs::read_to_string(&file).map_err(|e| format!("cannot read {path}: {e}"))
+}
+
+// 从导入目录读取并检查脚本
+fn read_ipxe_script(path: &str) -> Result<String, String> {
+    let script = read_import_file(Path::new(&template::import_dir()), path)?;
+    validate_ipxe_script(path, &script)?;
+    Ok(script)
+}
+
+// 旧版本数据库中登记的脚本路径，升级前由管理员登记，启动时导入，不受导入目录限制
+fn read_legacy_script(path: &str) -> Result<String, String> {
+    let script = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    validate_ipxe_script(path, &script)?;
+    Ok(script)
+}
+
+// 从导入目录读取应答文件模板，检查模板语法和安装程序要求的格式
+fn read_answer_template(path: &str, installer: InstallerFamily) -> Result<String, String> {
+    let answer_template = read_import_file(Path::new(&template::import_dir()), path)?;
+    template::variables(&answer_template).map_err(|e| format!("{path}: {e}"))?;
+    installer.validate_answer_template(path, &answer_template)?;
+    Ok(answer_template)
+}
+
+fn hosts_using_os(conn: &Connection, os: &str) -> Vec<String> {
+    let mut stmt = conn
+        .prepare("SELECT serial FROM hosts WHERE os = ?1 AND serial IS NOT NULL ORDER BY serial")
+        .unwrap();
+    stmt.query_map(params![os], |row| row.get(0))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+fn hosts_pinned_to(conn: &Connection, os: &str, version: i64) -> Vec<String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT serial FROM hosts WHERE os = ?1 AND ipxe_version = ?2 AND serial IS NOT NULL ORDER BY serial",
+        )
+        .unwrap();
+    stmt.query_map(params![os, version], |row| row.get(0))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+fn list_versions(conn: &Connection, os: &str) -> Vec<OsVersion> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT version, script_file, bios_script_file, bios_script IS NOT NULL, answer_template_file,
+                answer_template IS NOT NULL, created_at
+             FROM ipxe_versions WHERE os = ?1 ORDER BY version",
+        )
+        .unwrap();
+    let versions: Vec<OsVersion> = stmt
+        .query_map(params![os], |row| {
+            Ok(OsVersion {
+                version: row.get(0)?,
+                script_file: row.get(1)?,
+                bios_script_file: row.get(2)?,
+                has_bios_script: row.get(3)?,
+                answer_template_file: row.get(4)?,
+                has_answer_template: row.get(5)?,
+                created_at: row.get(6)?,
+                pinned_hosts: Vec::new(),
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    versions
+        .into_iter()
+        .map(|version| OsVersion {
+            pinned_hosts: hosts_pinned_to(conn, os, version.version),
+            ..version
+        })
+        .collect()
+}
+
+fn get_os_entry(conn: &Connection, os: &str) -> Option<OsEntry> {
+    let settings = os_settings(conn, os)?;
+    let active_version = conn
+        .query_row(
+            "SELECT active_version FROM ipxe WHERE os = ?1",
+            params![os],
+            |row| row.get(0),
+        )
+        .unwrap();
+    Some(OsEntry {
+        os: os.to_string(),
+        installer: settings.installer,
+        profile: settings.profile,
+        repo_url: settings.repo_url,
+        network_format: settings.network_format,
+        progress_hooks: settings.installer.progress_hooks(),
+        active_version,
+        versions: list_versions(conn, os),
+        hosts: hosts_using_os(conn, os),
+    })
+}
+
+// 去掉空白的 repo_url
+fn normalize_repo_url(repo_url: Option<String>) -> Option<String> {
+    repo_url
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+}
+
+fn load_version(conn: &Connection, os: &str, version: i64) -> Option<ScriptContent> {
+    conn.query_row(
+        "SELECT script, bios_script, answer_template, script_file, bios_script_file, answer_template_file
+         FROM ipxe_versions WHERE os = ?1 AND version = ?2",
+        params![os, version],
+        |row| {
+            Ok(ScriptContent {
+                script: row.get(0)?,
+                bios_script: row.get(1)?,
+                answer_template: row.get(2)?,
+                script_file: row.get(3)?,
+                bios_script_file: row.get(4)?,
+                answer_template_file: row.get(5)?,
+            })
+        },
+    )
+    .optional()
+    .unwrap()
+}
+
+pub fn version_exists(conn: &Connection, os: &str, version: i64) -> bool {
+    load_version(conn, os, version).is_some()
+}
+
+// 保存为新版本并启用，返回版本号
+fn create_version(conn: &Connection, os: &str, content: &ScriptContent) -> i64 {
+    let version: i64 = conn
+        .query_row(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM ipxe_versions WHERE os = ?1",
+            params![os],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let created_at = Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    conn.execute(
+        "INSERT INTO ipxe_versions (os, version, script, bios_script, answer_template, script_file,
+            bios_script_file, answer_template_file, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            os,
+            version,
+            content.script,
+            content.bios_script,
+            content.answer_template,
+            content.script_file,
+            content.bios_script_file,
+            content.answer_template_file,
+            created_at
+        ],
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE ipxe SET active_version = ?1 WHERE os = ?2",
+        params![version, os],
+    )
+    .unwrap();
+    version
+}
+
+// 主机使用的版本：本次安装确定的版本或固定的版本优先，否则使用启用的版本
+fn version_for_host(
+    conn: &Connection,
+    os: &str,
+    pinned_version: Option<i64>,
+) -> Result<(i64, ScriptContent), String> {
+    let version = match pinned_version {
+        Some(version) => version,
+        None => conn
+            .query_row(
+                "SELECT active_version FROM ipxe WHERE os = ?1",
+                params![os],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .optional()
+            .unwrap()
+            .flatten()
+            .ok_or_else(|| format!("os {os} has no iPXE script"))?,
+    };
+    let content = load_version(conn, os, version)
+        .ok_or_else(|| format!("os {os} has no iPXE script version {version}"))?;
+    Ok((version, content))
+}
+
+// 选择主机使用的脚本，BIOS 主机优先使用 bios_script 。返回版本号和脚本内容
+pub fn script_for_host(
+    conn: &Connection,
+    os: &str,
+    pinned_version: Option<i64>,
+    boot_mode: BootMode,
+) -> Result<(i64, String), String> {
+    let (version, content) = version_for_host(conn, os, pinned_version)?;
+    let script = match boot_mode {
+        BootMode::Bios => content.bios_script.unwrap_or(content.script),
+        BootMode::Uefi => content.script,
+    };
+    Ok((version, script))
+}
+
+// 选择主机使用的应答文件模板，与 iPXE 脚本使用同一版本。返回版本号和模板内容，版本中没有模板时为 None
+pub fn answer_template_for_host(
+    conn: &Connection,
+    os: &str,
+    pinned_version: Option<i64>,
+) -> Result<(i64, Option<String>), String> {
+    let (version, content) = version_for_host(conn, os, pinned_version)?;
+    Ok((version, content.answer_template))
+}
+
+// 旧版本数据库的 ipxe 表只保存脚本路径，启动时将仍然可读的脚本导入为第 1 版
+pub fn import_legacy_scripts(conn: &Connection) {
+    let mut stmt = conn
+        .prepare("SELECT os, script, bios_script FROM ipxe WHERE active_version IS NULL AND script IS NOT NULL")
+        .unwrap();
+    let legacy: Vec<(String, String, Option<String>)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    for (os, script_file, bios_script_file) in legacy {
+        let bios_script = match bios_script_file.as_deref().map(read_legacy_script) {
+            Some(Err(e)) => {
+                println!("[ERROR] Cannot import BIOS iPXE script of {os}: {e}");
+                continue;
+            }
+            other => other.and_then(Result::ok),
+        };
+        let script = match read_legacy_script(&script_file) {
+            Ok(script) => script,
+            Err(e) => {
+                println!("[ERROR] Cannot import iPXE script of {os}: {e}");
+                continue;
+            }
+        };
+        let version = create_version(
+            conn,
+            &os,
+            &ScriptContent {
+                script,
+                bios_script,
+                answer_template: None,
+                script_file: Some(script_file.clone()),
+                bios_script_file,
+                answer_template_file: None,
+            },
+        );
+        println!("[INFO] iPXE script {script_file} of {os} imported as version {version}");
+    }
+}
+
+// 处理 GET /api/os
+pub async fn list_os(db_pool: web::Data<Pool<SqliteConnectionManager>>) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn.prepare("SELECT os FROM ipxe ORDER BY os").unwrap();
+    let names: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    let entries: Vec<OsEntry> = names
+        .iter()
+        .filter_map(|os| get_os_entry(&conn, os))
+        .collect();
+    HttpResponse::Ok().json(entries)
+}
+
+// 处理 GET /api/os/{os}
+pub async fn get_os(
+    os: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    match get_os_entry(&conn, &os) {
+        Some(entry) => HttpResponse::Ok().json(entry),
+        None => HttpResponse::NotFound().json(json!({ "error": format!("os {os} not found") })),
+    }
+}
+
+// 处理 POST /api/os ，注册操作系统并从文件导入第 1 版 iPXE 脚本
+pub async fn create_os(
+    entry: web::Json<NewOsEntry>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let entry = entry.into_inner();
+    let os = entry.os.trim().to_string();
+    if os.is_empty() {
+        return HttpResponse::BadRequest().json(json!({ "error": "os must not be empty" }));
+    }
+    let installer = entry
+        .installer
+        .or(entry.profile.map(|profile| profile.installer()))
+        .unwrap_or_default();
+    if let Err(message) = check_profile(installer, entry.profile) {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+    let script = match read_ipxe_script(&entry.script) {
+        Ok(script) => script,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+    let bios_script = match entry
+        .bios_script
+        .as_deref()
+        .map(read_ipxe_script)
+        .transpose()
+    {
+        Ok(bios_script) => bios_script,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+    let answer_template = match entry
+        .answer_template
+        .as_deref()
+        .map(|path| read_answer_template(path, installer))
+        .transpose()
+    {
+        Ok(answer_template) => answer_template,
+        Err(message) => return HttpResponse::BadRequest().json(json!({ "error": message })),
+    };
+    let conn = db_pool.get().unwrap();
+    if get_os_entry(&conn, &os).is_some() {
+        return HttpResponse::Conflict()
+            .json(json!({ "error": format!("os {os} already exists") }));
+    }
+    conn.execute(
+        "INSERT INTO ipxe (os, installer, profile, repo_url, network_format) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            os,
+            installer.as_str(),
+            entry.profile.map(|profile| profile.as_str()),
+            normalize_repo_url(entry.repo_url),
+            entry.network_format.unwrap_or_default().as_str()
+        ],
+    )
+    .unwrap();
+    let version = create_version(
+        &conn,
+        &os,
+        &ScriptContent {
+            script,
+            bios_script,
+            answer_template,
+            script_file: Some(entry.script.clone()),
+            bios_script_file: entry.bios_script,
+            answer_template_file: entry.answer_template,
+        },
+    );
+    println!(
+        "[INFO] OS {os} registered with installer {installer} and iPXE script {} (version {version})",
+        entry.script
+    );
+    HttpResponse::Created().json(get_os_entry(&conn, &os))
+}
+
+// 处理 PATCH /api/os/{os} ，从文件导入新版本，切换启用的版本，或更换安装程序
+pub async fn update_os(
+    os: web::Path<String>,
+    patch: web::Json<OsEntryPatch>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let os = os.into_inner();
+    let patch = patch.into_inner();
+    let imports =
+        patch.script.is_some() || patch.bios_script.is_some() || patch.answer_template.is_some();
+    if imports && patch.active_version.is_some() {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "active_version cannot be combined with script, bios_script or answer_template"
+        }));
+    }
+    let conn = db_pool.get().unwrap();
+    let Some(entry) = get_os_entry(&conn, &os) else {
+        return HttpResponse::NotFound().json(json!({ "error": format!("os {os} not found") }));
+    };
+    let profile = patch.profile.unwrap_or(entry.profile);
+    let installer = patch
+        .installer
+        .or(patch.profile.flatten().map(|profile| profile.installer()))
+        .unwrap_or(entry.installer);
+    if let Err(message) = check_profile(installer, profile) {
+        return HttpResponse::BadRequest().json(json!({ "error": message }));
+    }
+    if installer != entry.installer {
+        let has_answer_template = entry
+            .active_version
+            .and_then(|version| load_version(&conn, &os, version))
+            .is_some_and(|current| current.answer_template.is_some());
+        if has_answer_template && patch.answer_template.is_none() {
+            return HttpResponse::BadRequest().json(json!({
+                "error": format!("answer_template must be imported again for installer {installer}")
+            }));
+        }
+    }
+    if let Some(version) = patch.active_version {
+        if !version_exists(&conn, &os, version) {
+            return HttpResponse::BadRequest()
+                .json(json!({ "error": format!("os {os} has no version {version}") }));
+        }
+        conn.execute(
+            "UPDATE ipxe SET active_version = ?1 WHERE os = ?2",
+            params![version, os],
+        )
+        .unwrap();
+        println!("[INFO] OS {os} switched to iPXE script version {version}");
+    }
+    if imports {
+        let current = entry
+            .active_version
+            .and_then(|version| load_version(&conn, &os, version));
+        let (script, script_file) = match (&patch.script, current.as_ref()) {
+            (Some(path), _) => match read_ipxe_script(path) {
+                Ok(script) => (script, Some(path.clone())),
+                Err(message) => {
+                    return HttpResponse::BadRequest().json(json!({ "error": message }));
+                }
+            },
+            (None, Some(current)) => (current.script.clone(), current.script_file.clone()),
+            (None, None) => {
+                return HttpResponse::BadRequest()
+                    .json(json!({ "error": format!("os {os} has no script to keep") }));
+            }
+        };
+        let (bios_script, bios_script_file) = match &patch.bios_script {
+            Some(Some(path)) => match read_ipxe_script(path) {
+                Ok(script) => (Some(script), Some(path.clone())),
+                Err(message) => {
+                    return HttpResponse::BadRequest().json(json!({ "error": message }));
+                }
+            },
+            Some(None) => (None, None),
+            None => current
+                .as_ref()
+                .map(|current| {
+                    (
+                        current.bios_script.clone(),
+                        current.bios_script_file.clone(),
+                    )
+                })
+                .unwrap_or_default(),
+        };
+        let (answer_template, answer_template_file) = match &patch.answer_template {
+            Some(Some(path)) => match read_answer_template(path, installer) {
+                Ok(answer_template) => (Some(answer_template), Some(path.clone())),
+                Err(message) => {
+                    return HttpResponse::BadRequest().json(json!({ "error": message }));
+                }
+            },
+            Some(None) => (None, None),
+            None => current
+                .map(|current| (current.answer_template, current.answer_template_file))
+                .unwrap_or_default(),
+        };
+        let version = create_version(
+            &conn,
+            &os,
+            &ScriptContent {
+                script,
+                bios_script,
+                answer_template,
+                script_file,
+                bios_script_file,
+                answer_template_file,
+            },
+        );
+        println!("[INFO] OS {os} updated with iPXE script version {version}");
+    }
+    // 模板导入成功后再更换安装程序和配置档
+    if installer != entry.installer || patch.profile.is_some() {
+        conn.execute(
+            "UPDATE ipxe SET installer = ?1, profile = ?2 WHERE os = ?3",
+            params![
+                installer.as_str(),
+                profile.map(|profile| profile.as_str()),
+                os
+            ],
+        )
+        .unwrap();
+        println!(
+            "[INFO] OS {os} uses installer {installer}, profile {}",
+            profile.map_or("none", |profile| profile.as_str())
+        );
+    }
+    if let Some(repo_url) = patch.repo_url {
+        conn.execute(
+            "UPDATE ipxe SET repo_url = ?1 WHERE os = ?2",
+            params![normalize_repo_url(repo_url), os],
+        )
+        .unwrap();
+    }
+    if let Some(network_format) = patch.network_format {
+        conn.execute(
+            "UPDATE ipxe SET network_format = ?1 WHERE os = ?2",
+            params![network_format.as_str(), os],
+        )
+        .unwrap();
+        println!(
+            "[INFO] OS {os} uses {} network configuration files",
+            network_format.as_str()
+        );
+    }
+    HttpResponse::Ok().json(get_os_entry(&conn, &os))
+}
+
+// 处理 GET /api/os/{os}/versions/{version} ，导出脚本原文，?variant=bios 导出 BIOS 脚本，?variant=answer_template 导出应答文件模板
+pub async fn export_os_version(
+    path: web::Path<(String, i64)>,
+    query: web::Query<ExportQuery>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let (os, version) = path.into_inner();
+    let conn = db_pool.get().unwrap();
+    let Some(content) = load_version(&conn, &os, version) else {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("os {os} has no version {version}") }));
+    };
+    let (script, name) = match query.variant {
+        ExportVariant::Uefi => (Some(content.script), "iPXE script"),
+        ExportVariant::Bios => (content.bios_script, "BIOS script"),
+        ExportVariant::AnswerTemplate => (content.answer_template, "answer template"),
+    };
+    match script {
+        Some(script) => HttpResponse::Ok().body(script),
+        None => HttpResponse::NotFound()
+            .json(json!({ "error": format!("version {version} of {os} has no {name}") })),
+    }
+}
+
+// 处理 DELETE /api/os/{os} ，仍有主机引用时拒绝删除
+pub async fn delete_os(
+    os: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let os = os.into_inner();
+    let conn = db_pool.get().unwrap();
+    let Some(entry) = get_os_entry(&conn, &os) else {
+        return HttpResponse::NotFound().json(json!({ "error": format!("os {os} not found") }));
+    };
+    if !entry.hosts.is_empty() {
+        return HttpResponse::Conflict().json(json!({
+            "error": format!("os {os} is still referenced by {} host(s)", entry.hosts.len()),
+            "hosts": entry.hosts,
+        }));
+    }
+    conn.execute("DELETE FROM ipxe_versions WHERE os = ?1", params![os])
+        .unwrap();
+    conn.execute("DELETE FROM ipxe WHERE os = ?1", params![os])
+        .unwrap();
+    println!("[INFO] OS {os} deleted");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+    use crate::install_events::record_event;
+    use crate::install_state::{Progress, TransitionSource, set_install_progress};
+
+    fn content(script: &str, bios_script: Option<&str>) -> ScriptContent {
+        ScriptContent {
+            script: script.to_string(),
+            bios_script: bios_script.map(String::from),
+            answer_template: None,
+            script_file: None,
+            bios_script_file: None,
+            answer_template_file: None,
+        }
+    }
+
+    #[test]
+    fn pinned_version_beats_active_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute("INSERT INTO ipxe (os) VALUES ('Kylin')", [])
+            .unwrap();
+        conn.execute("INSERT INTO hosts (serial, os) VALUES ('S1', 'Kylin')", [])
+            .unwrap();
+        assert_eq!(
+            create_version(&conn, "Kylin", &content("#!ipxe\nv1", None)),
+            1
+        );
+        assert_eq!(
+            create_version(
+                &conn,
+                "Kylin",
+                &content("#!ipxe\nv2", Some("#!ipxe\nv2 bios"))
+            ),
+            2
+        );
+        assert_eq!(
+            script_for_host(&conn, "Kylin", None, BootMode::Uefi).unwrap(),
+            (2, "#!ipxe\nv2".to_string())
+        );
+        assert_eq!(
+            script_for_host(&conn, "Kylin", None, BootMode::Bios)
+                .unwrap()
+                .1,
+            "#!ipxe\nv2 bios"
+        );
+        // 第 1 版没有 BIOS 脚本，BIOS 主机同样使用 script
+        assert_eq!(
+            script_for_host(&conn, "Kylin", Some(1), BootMode::Bios).unwrap(),
+            (1, "#!ipxe\nv1".to_string())
+        );
+        assert!(script_for_host(&conn, "Kylin", Some(3), BootMode::Uefi).is_err());
+
+        // 装机历史记录主机当时使用的版本
+        record_event(
+            &conn,
+            "S1",
+            None,
+            Progress::NotConfigured,
+            TransitionSource::Server,
+            "2025-01-01 00:00:00",
+        );
+        conn.execute("UPDATE hosts SET ipxe_version = 1", [])
+            .unwrap();
+        record_event(
+            &conn,
+            "S1",
+            Some(Progress::NotConfigured),
+            Progress::RebootingToKickstart,
+            TransitionSource::Server,
+            "2025-01-01 00:01:00",
+        );
+        let mut stmt = conn
+            .prepare("SELECT ipxe_version FROM install_events ORDER BY id")
+            .unwrap();
+        let versions: Vec<Option<i64>> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(versions, [Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn install_keeps_version_resolved_at_start() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute("INSERT INTO ipxe (os) VALUES ('Kylin')", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO hosts (serial, os, install_progress) VALUES ('S1', 'Kylin', 0)",
+            [],
+        )
+        .unwrap();
+        create_version(&conn, "Kylin", &content("#!ipxe\nv1", None));
+        let host_version = |conn: &Connection| -> Option<i64> {
+            conn.query_row(
+                "SELECT COALESCE(install_ipxe_version, ipxe_version) FROM hosts",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(host_version(&conn), None);
+
+        // 开始安装后启用新版本，正在安装的主机仍然使用开始安装时的版本
+        set_install_progress(
+            &conn,
+            "S1",
+            Progress::RebootingToKickstart,
+            TransitionSource::Server,
+        )
+        .unwrap();
+        create_version(&conn, "Kylin", &content("#!ipxe\nv2", None));
+        assert_eq!(host_version(&conn), Some(1));
+        assert_eq!(
+            script_for_host(&conn, "Kylin", host_version(&conn), BootMode::Uefi).unwrap(),
+            (1, "#!ipxe\nv1".to_string())
+        );
+        set_install_progress(
+            &conn,
+            "S1",
+            Progress::KickstartLoaded,
+            TransitionSource::Installer,
+        )
+        .unwrap();
+
+        // 安装结束后回到装机环境，下次安装使用新版本
+        set_install_progress(&conn, "S1", Progress::Failed, TransitionSource::Installer).unwrap();
+        set_install_progress(
+            &conn,
+            "S1",
+            Progress::NotConfigured,
+            TransitionSource::Discovery,
+        )
+        .unwrap();
+        assert_eq!(host_version(&conn), None);
+        let mut stmt = conn
+            .prepare("SELECT ipxe_version FROM install_events ORDER BY id")
+            .unwrap();
+        let versions: Vec<Option<i64>> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(versions, [Some(1), Some(1), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn imports_are_confined_to_import_dir() {
+        let base =
+            std::env::temp_dir().join(format!("cloudboot-lce-import-{}", std::process::id()));
+        let import_dir = base.join("assets");
+        fs::create_dir_all(&import_dir).unwrap();
+        fs::write(import_dir.join("os.ipxe"), "#!ipxe\nboot\n").unwrap();
+        fs::write(base.join("secret.key"), "secret").unwrap();
+        let inside = import_dir.join("os.ipxe");
+        assert_eq!(
+            read_import_file(&import_dir, &inside.to_string_lossy()).unwrap(),
+            "#!ipxe\nboot\n"
+        );
+        // 目录外的文件，以及通过 .. 或符号链接指向目录外的路径都被拒绝
+        for path in [
+            base.join("secret.key"),
+            import_dir.join("../secret.key"),
+            import_dir.join("link.key"),
+        ] {
+            if path.ends_with("link.key") {
+                std::os::unix::fs::symlink(base.join("secret.key"), &path).unwrap();
+            }
+            let e = read_import_file(&import_dir, &path.to_string_lossy()).unwrap_err();
+            assert!(e.contains("outside the import directory"), "{e}");
+        }
+        fs::remove_dir_all(&base).ok();
+    }
+
+    #[test]
+    fn legacy_script_paths_are_imported() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let path =
+            std::env::temp_dir().join(format!("cloudboot-lce-legacy-{}.ipxe", std::process::id()));
+        fs::write(&path, "#!ipxe\nboot\n").unwrap();
+        conn.execute(
+            "INSERT INTO ipxe (os, script) VALUES ('Kylin', ?1), ('Missing', '/nonexistent.ipxe')",
+            params![path.to_string_lossy()],
+        )
+        .unwrap();
+        import_legacy_scripts(&conn);
+        fs::remove_file(&path).ok();
+        let entry = get_os_entry(&conn, "Kylin").unwrap();
+        assert_eq!(entry.active_version, Some(1));
+        assert_eq!(entry.versions.len(), 1);
+        assert_eq!(
+            load_version(&conn, "Kylin", 1).unwrap().script,
+            "#!ipxe\nboot\n"
+        );
+        assert_eq!(get_os_entry(&conn, "Missing").unwrap().active_version, None);
+    }
+}
@@ -14,51 +14,360 @@
  * limitations under the License.
 */
 
-// iPXE 脚本生成代码
+// iPXE 脚本生成代码：根据主机状态返回装机脚本、进入装机环境 BootOS 的脚本或从本地磁盘启动的脚本。
+// 装机脚本从数据库中取出主机使用的版本，所有脚本都作为模板用主机字段和全局变量渲染
 use actix_web::{HttpResponse, Responder, web};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::params;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Deserialize;
+use serde_json::json;
 use std::fs;
+use std::sync::OnceLock;
 
-use crate::progress_control::Progress;
+use crate::boot_mode::{BootMode, host_boot_mode};
+use crate::config::IpxeConfig;
+use crate::install_state::Progress;
+use crate::installer_api::validate_serial;
+use crate::ipxe_catalog::script_for_host;
+use crate::os_profile::{self, os_settings};
+use crate::template::{self, TemplateError, Vars};
 
-// 处理 /api/ipxe/{serial}
+static IPXE_CONFIG: OnceLock<IpxeConfig> = OnceLock::new();
+
+// 已装好的主机从本地磁盘启动的方式
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocalBoot {
+    // UEFI 主机使用 exit ，BIOS 主机使用 sanboot
+    #[default]
+    Auto,
+    // 退出 iPXE ，由固件按启动顺序尝试下一个启动项
+    Exit,
+    // 从第一块本地磁盘启动
+    Sanboot,
+}
+
+#[derive(Deserialize)]
+pub struct IpxeQuery {
+    // iPXE 的 ${buildarch}
+    arch: Option<String>,
+    // 序列号未登记时（例如读不出序列号的主机）按 MAC 地址查找主机
+    mac: Option<String>,
+}
+
+// 主机此次 PXE 启动应进入的环境
+#[derive(Debug, PartialEq, Eq)]
+enum BootChoice {
+    Install,
+    LocalDisk,
+    BootOs,
+}
+
+struct IpxeHost {
+    serial: String,
+    os: Option<String>,
+    progress: Option<Progress>,
+    ipxe_version: Option<i64>,
+    // 在装机队列中，即操作员要求重装
+    queued: bool,
+}
+
+// 启动时设置 BootOS 脚本和本地启动方式
+pub fn init(config: &IpxeConfig) {
+    IPXE_CONFIG.set(config.clone()).ok();
+}
+
+// 服务端已触发安装时进入 kickstart ，系统已写入磁盘时从本地启动，其他情况（包括未纳管的主机）进入 BootOS 。
+// 加入装机队列的主机不再从本地启动，进入 BootOS 后重新安装
+fn choose(progress: Option<Progress>, has_os: bool, queued: bool) -> BootChoice {
+    match progress {
+        Some(Progress::RebootingToKickstart) if has_os => BootChoice::Install,
+        Some(progress) if progress.boots_from_disk() && !queued => BootChoice::LocalDisk,
+        _ => BootChoice::BootOs,
+    }
+}
+
+fn local_boot_script(local_boot: LocalBoot, boot_mode: Option<BootMode>) -> &'static str {
+    let sanboot = match local_boot {
+        LocalBoot::Auto => boot_mode == Some(BootMode::Bios),
+        LocalBoot::Exit => false,
+        LocalBoot::Sanboot => true,
+    };
+    if sanboot {
+        "#!ipxe\necho Booting from local disk\nsanboot --no-describe --drive 0x80 || exit\n"
+    } else {
+        "#!ipxe\necho Booting from local disk\nexit\n"
+    }
+}
+
+// 按序列号查找主机，找不到时按 MAC 地址查找
+fn find_host(conn: &Connection, serial: &str, mac: Option<&str>) -> Option<IpxeHost> {
+    let query = |column: &str, value: &str| {
+        conn.query_row(
+            &format!(
+                "SELECT serial, os, install_progress, COALESCE(install_ipxe_version, ipxe_version), EXISTS (SELECT 1 FROM install_queue iq WHERE iq.ipmi_address = hosts.ipmi_address) FROM hosts WHERE {column} = ?1 AND serial IS NOT NULL"
+            ),
+            params![value],
+            |row| {
+                Ok(IpxeHost {
+                    serial: row.get(0)?,
+                    os: row.get(1)?,
+                    progress: row
+                        .get::<_, Option<i32>>(2)?
+                        .and_then(|p| Progress::try_from(p).ok()),
+                    ipxe_version: row.get(3)?,
+                    queued: row.get(4)?,
+                })
+            },
+        )
+        .optional()
+        .unwrap()
+    };
+    query("serial", serial).or_else(|| {
+        mac.map(str::to_lowercase)
+            .and_then(|mac| query("mac_address", &mac))
+    })
+}
+
+// 渲染模板，变量缺失时拒绝下发，iPXE 在报错后停止，而不是用残缺的参数启动
+fn render_response(name: &str, script: &str, vars: &Vars, serial: &str) -> HttpResponse {
+    match template::render(script, vars) {
+        Ok(script) => {
+            println!("[INFO] iPXE script for {serial}: {name}");
+            HttpResponse::Ok().body(script)
+        }
+        Err(e) => render_error(name, e, serial),
+    }
+}
+
+fn render_error(name: &str, e: TemplateError, serial: &str) -> HttpResponse {
+    println!("[ERROR] Cannot render iPXE script {name} for {serial}: {e}");
+    let missing = match &e {
+        TemplateError::Missing(names) => names.clone(),
+        _ => Vec::new(),
+    };
+    HttpResponse::UnprocessableEntity().json(json!({
+        "error": format!("cannot render iPXE script {name}: {e}"),
+        "missing": missing,
+    }))
+}
+
+fn install_script(conn: &Connection, host: &IpxeHost, os: &str) -> HttpResponse {
+    // 传统 BIOS 启动的主机优先使用 bios_script ，未检测到启动模式时按 UEFI 处理
+    let boot_mode = host_boot_mode(conn, &host.serial).unwrap_or(BootMode::Uefi);
+    let (version, script) = match script_for_host(conn, os, host.ipxe_version, boot_mode) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("[ERROR] No iPXE script for {}: {e}", host.serial);
+            return HttpResponse::NotFound().json(json!({ "error": e }));
+        }
+    };
+    let mut vars = template::host_vars(conn, &host.serial).unwrap_or_default();
+    os_profile::insert_os_vars(conn, os, &mut vars);
+    // 安装程序获取应答文件的地址，例如 inst.ks={{ answer_url }}
+    let (installer, profile) = os_settings(conn, os)
+        .map(|settings| (settings.installer, settings.profile))
+        .unwrap_or_default();
+    let answer_url = installer.answer_url(&vars["server_url"], &host.serial);
+    vars.insert("answer_url".to_string(), answer_url);
+    // 安装程序和配置档需要的全部内核参数，引用的 repo_url 等变量缺失时同样拒绝下发
+    let uses_args = template::variables(&script)
+        .is_ok_and(|names| names.iter().any(|name| name == "installer_args"));
+    if uses_args {
+        match template::render(&os_profile::kernel_args(installer, profile), &vars) {
+            Ok(args) => {
+                vars.insert("installer_args".to_string(), args);
+            }
+            Err(e) => return render_error("installer_args", e, &host.serial),
+        }
+    }
+    render_response(
+        &format!("{os} version {version} ({boot_mode})"),
+        &script,
+        &vars,
+        &host.serial,
+    )
+}
+
+fn bootos_script(mut vars: Vars, arch: Option<&str>, serial: &str) -> HttpResponse {
+    let Some(arch) = arch else {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "arch is required to choose a BootOS script" }));
+    };
+    let config = IPXE_CONFIG.get().cloned().unwrap_or_default();
+    let Some(path) = config.bootos.get(arch) else {
+        println!("[ERROR] No BootOS script for arch {arch} ({serial})");
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("no BootOS script for arch {arch}") }));
+    };
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("[ERROR] Error reading BootOS script {path}: {e}");
+            return HttpResponse::InternalServerError()
+                .json(json!({ "error": format!("cannot read {path}") }));
+        }
+    };
+    vars.insert("arch".to_string(), arch.to_string());
+    render_response(&format!("BootOS {path}"), &script, &vars, serial)
+}
+
+// MAC 地址只允许六组冒号分隔的十六进制数
+fn is_mac_address(mac: &str) -> bool {
+    let parts: Vec<&str> = mac.split(':').collect();
+    parts.len() == 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// 处理 GET /api/ipxe/{serial}?arch=&mac=
 pub async fn get_ipxe_script(
     serial: web::Path<String>,
+    query: web::Query<IpxeQuery>,
     db_pool: web::Data<Pool<SqliteConnectionManager>>,
 ) -> impl Responder {
-    println!("[INFO] Offering iPXE script for {serial}");
+    println!("[INFO] Offering iPXE script for {serial:?}");
     let conn = db_pool.get().unwrap();
     let serial = serial.into_inner();
-    let os: Option<String> = conn
-        .query_row(
-            "SELECT os FROM hosts WHERE serial = ?1 and install_progress = ?2",
-            params![serial, Progress::RebootingToKickstart as i32],
-            |row| row.get(0),
-        )
-        .ok();
-    if let Some(os) = os {
-        let script_path: Option<String> = conn
-            .query_row(
-                "SELECT script FROM ipxe WHERE os = ?1",
-                params![os],
-                |row| row.get(0),
-            )
-            .ok();
-        if let Some(path) = script_path {
-            match fs::read_to_string(&path) {
-                Ok(script) => {
-                    println!("[INFO] iPXE script for {serial}: {path}");
-                    return HttpResponse::Ok().body(script);
+    let host = find_host(&conn, &serial, query.mac.as_deref());
+    let choice = host.as_ref().map_or(BootChoice::BootOs, |host| {
+        choose(host.progress, host.os.is_some(), host.queued)
+    });
+    match (choice, &host) {
+        (BootChoice::Install, Some(host)) => {
+            install_script(&conn, host, host.os.as_deref().unwrap_or_default())
+        }
+        (BootChoice::LocalDisk, Some(host)) => {
+            println!(
+                "[INFO] {} is installed, booting from local disk",
+                host.serial
+            );
+            let local_boot = IPXE_CONFIG.get().cloned().unwrap_or_default().local_boot;
+            HttpResponse::Ok().body(local_boot_script(
+                local_boot,
+                host_boot_mode(&conn, &host.serial),
+            ))
+        }
+        _ => {
+            let mut vars = host
+                .as_ref()
+                .and_then(|host| template::host_vars(&conn, &host.serial))
+                .unwrap_or_else(template::global_vars);
+            // 请求中的序列号和 MAC 地址原样渲染进脚本，含有换行等字符时不作为变量，避免注入 iPXE 命令
+            match validate_serial(&serial) {
+                Ok(()) => {
+                    vars.entry("serial".to_string()).or_insert(serial.clone());
                 }
-                Err(_) => {
-                    println!("[ERROR] Error reading script file: {path}");
-                    return HttpResponse::InternalServerError().body("");
+                Err(e) => println!("[WARN] Not rendering iPXE script with {e}"),
+            }
+            match &query.mac {
+                Some(mac) if is_mac_address(mac) => {
+                    vars.entry("mac".to_string()).or_insert(mac.to_lowercase());
                 }
+                Some(mac) => println!("[WARN] Not rendering iPXE script with invalid mac: {mac:?}"),
+                None => {}
             }
+            bootos_script(vars, query.arch.as_deref(), &serial)
         }
     }
-    println!("[INFO] No iPXE script found for serial {serial}");
-    HttpResponse::NotFound().body("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_choice_follows_progress() {
+        assert_eq!(
+            choose(Some(Progress::RebootingToKickstart), true, false),
+            BootChoice::Install
+        );
+        assert_eq!(
+            choose(Some(Progress::RebootingToKickstart), false, false),
+            BootChoice::BootOs
+        );
+        assert_eq!(
+            choose(Some(Progress::Installed), true, false),
+            BootChoice::LocalDisk
+        );
+        assert_eq!(
+            choose(Some(Progress::InstallFinished), true, false),
+            BootChoice::LocalDisk
+        );
+        assert_eq!(
+            choose(Some(Progress::TimedOut), true, false),
+            BootChoice::BootOs
+        );
+        assert_eq!(choose(None, false, false), BootChoice::BootOs);
+        // 已安装的主机加入装机队列后重新安装
+        assert_eq!(
+            choose(Some(Progress::Installed), true, true),
+            BootChoice::BootOs
+        );
+        assert_eq!(
+            choose(Some(Progress::RebootingToKickstart), true, true),
+            BootChoice::Install
+        );
+    }
+
+    #[test]
+    fn local_boot_follows_boot_mode() {
+        assert!(local_boot_script(LocalBoot::Auto, Some(BootMode::Bios)).contains("sanboot"));
+        assert!(!local_boot_script(LocalBoot::Auto, None).contains("sanboot"));
+        assert!(local_boot_script(LocalBoot::Sanboot, Some(BootMode::Uefi)).contains("sanboot"));
+    }
+
+    #[test]
+    fn mac_address_format() {
+        assert!(is_mac_address("52:54:00:AA:bb:cc"));
+        assert!(!is_mac_address("52:54:00:aa:bb"));
+        assert!(!is_mac_address("52:54:00:aa:bb:c\n"));
+        assert!(!is_mac_address("52:54:00:aa:bb:cc\nshell"));
+    }
+
+    #[actix_web::test]
+    async fn request_values_are_not_injected_into_bootos_script() {
+        use crate::database_init::init_db;
+        use actix_web::{App, test};
+
+        let path = std::env::temp_dir().join(format!("bootos-{}.ipxe", std::process::id()));
+        fs::write(
+            &path,
+            "#!ipxe\nchain {{ server_url }}/bootos?serial={{ serial }}\n",
+        )
+        .unwrap();
+        init(&IpxeConfig {
+            bootos: [("x86_64".to_string(), path.to_string_lossy().to_string())].into(),
+            ..IpxeConfig::default()
+        });
+        let db_pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        init_db(&db_pool.get().unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_pool))
+                .route("/api/ipxe/{serial}", web::get().to(get_ipxe_script)),
+        )
+        .await;
+        let body = |uri: &'static str| {
+            let app = &app;
+            async move {
+                let request = test::TestRequest::get().uri(uri).to_request();
+                String::from_utf8(test::call_and_read_body(app, request).await.to_vec()).unwrap()
+            }
+        };
+        assert!(
+            body("/api/ipxe/S1?arch=x86_64")
+                .await
+                .contains("serial=S1\n")
+        );
+        // 带换行的序列号不作为变量，脚本因缺少变量拒绝下发
+        let injected = body("/api/ipxe/S1%0Ashell?arch=x86_64").await;
+        assert!(!injected.contains("shell"));
+        fs::remove_file(path).ok();
+    }
 }
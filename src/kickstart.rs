@@ -0,0 +1,138 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// kickstart 生成代码：anaconda 专用的模板变量，安装程序据此直接配置主机名、业务网络和分区，
+// 并在 %pre/%post 中上报装机进度
+use actix_web::{Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use crate::boot_mode::{BootMode, host_boot_mode};
+use crate::installer::{InstallerFamily, render_answer};
+use crate::network_profile::{self, NetworkProfile};
+use crate::nic_rules;
+use crate::template::Vars;
+
+// 在 %pre 中按 MAC 地址找出网卡选择规则选中的网卡。装机环境与主机发现时的网卡名称可能不同，MAC 地址不变
+fn find_slaves(mac_addresses: &[String]) -> String {
+    format!(
+        "slaves=\n\
+         for mac in {}; do\n  \
+           for dev in /sys/class/net/*; do\n    \
+             [ -e \"$dev/device\" ] && [ \"$(cat \"$dev/address\")\" = \"$mac\" ] && slaves=${{slaves:+$slaves,}}${{dev##*/}}\n  \
+           done\n\
+         done\n",
+        mac_addresses.join(" ")
+    )
+}
+
+// 定义 report_progress 函数：写入 /tmp/install-progress 供服务端通过 SSH 读取，同时调用 POST /api/progress/{serial}
+fn progress_hook(progress_url: &str) -> String {
+    format!(
+        "report_progress() {{\n  \
+           echo \"$1\" >/tmp/install-progress\n  \
+           curl -m 3 -s -d \"progress=$1\" --data-urlencode \"message=$2\" -o /dev/null \"{progress_url}\" || true\n\
+         }}\n"
+    )
+}
+
+// 在 %pre 中生成 /tmp/network.ks ：选中的网卡按主机网络配置做绑定，每个接口一条 network 命令
+fn network_pre(hostname: &str, profile: &NetworkProfile, mac_addresses: &[String]) -> String {
+    format!(
+        "{}cat >/tmp/network.ks <<EOF\n{}\nEOF\n",
+        find_slaves(mac_addresses),
+        profile.kickstart_commands(hostname).join("\n")
+    )
+}
+
+// anaconda 的模板变量：分区方案、进度上报函数和网络配置。网络配置需要主机设置 hostname 和网络配置
+// （或 public_ip_addr 和 vlan_id），并且按主机发现时收集的网卡信息能选出绑定的网卡
+pub fn anaconda_vars(conn: &Connection, serial: &str, vars: &mut Vars) {
+    // 与重启和 iPXE 脚本一致，未检测到启动模式时按 UEFI 处理
+    let boot_mode = host_boot_mode(conn, serial).unwrap_or(BootMode::Uefi);
+    vars.insert(
+        "partitioning".to_string(),
+        boot_mode.kickstart_partitioning(),
+    );
+    let hook = progress_hook(&vars["progress_url"]);
+    vars.insert("progress_hook".to_string(), hook);
+    if let (Some(hostname), Some(profile)) = (
+        vars.get("hostname"),
+        network_profile::host_profile(conn, serial),
+    ) {
+        match nic_rules::select_for_host(conn, serial) {
+            Ok(nics) => {
+                let mac_addresses: Vec<String> =
+                    nics.into_iter().map(|nic| nic.mac_address).collect();
+                let network = network_pre(hostname, &profile, &mac_addresses);
+                vars.insert("network_pre".to_string(), network);
+            }
+            Err(e) => println!("[WARN] No network configuration in kickstart for {serial}: {e}"),
+        }
+    }
+}
+
+// 处理 GET /api/kickstart/{serial}
+pub async fn get_kickstart(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    render_answer(&conn, &serial, InstallerFamily::Anaconda)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+    use crate::installer::answer_vars;
+    use crate::template::{self, TemplateError};
+
+    #[test]
+    fn network_needs_hostname_and_vlan() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, hostname, public_ip_addr, vlan_id, boot_mode)
+             VALUES ('S1', 'node01', '10.1.2.30', 100, 'bios'), ('S2', NULL, '10.1.3.40', 200, NULL)",
+            [],
+        )
+        .unwrap();
+        let nics = nic_rules::parse_facts(
+            "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\t\t\n\
+             ens1f1\t52:54:00:00:00:02\tixgbe\t10000\t0000:3b:00.1\tFIBRE\t\t\n",
+        );
+        nic_rules::store_facts(&conn, "S1", &nics, "2025-01-01 00:00:00");
+        let vars = answer_vars(&conn, "S1", InstallerFamily::Anaconda).unwrap();
+        assert_eq!(vars["gateway"], "10.1.2.1");
+        assert_eq!(vars["progress_url"], "http://osinstall.pxe/api/progress/S1");
+        assert!(vars["partitioning"].contains("biosboot"));
+        let network = &vars["network_pre"];
+        assert!(network.contains("--vlanid=100 --bootproto=static --ip=10.1.2.30"));
+        assert!(network.contains("--gateway=10.1.2.1 --noipv6 --onboot=yes --hostname=node01"));
+        assert!(network.contains("for mac in 52:54:00:00:00:01 52:54:00:00:00:02; do"));
+
+        // 没有主机名或没有收集到网卡时不生成网络配置，模板引用时报告缺失
+        let vars = answer_vars(&conn, "S2", InstallerFamily::Anaconda).unwrap();
+        assert!(vars["partitioning"].contains("/boot/efi"));
+        assert_eq!(
+            template::render("{{ network_pre }}", &vars),
+            Err(TemplateError::Missing(vec!["network_pre".to_string()]))
+        );
+        assert!(answer_vars(&conn, "S3", InstallerFamily::Anaconda).is_none());
+    }
+}
@@ -0,0 +1,44 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+pub mod bmc;
+pub mod boot_mode;
+pub mod command_execute;
+pub mod config;
+pub mod credentials;
+pub mod database_init;
+pub mod executor;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fake_executor;
+pub mod host_keys;
+pub mod hosts_api;
+pub mod hosts_discovery;
+pub mod install_events;
+pub mod install_queue;
+pub mod install_state;
+pub mod installer;
+pub mod installer_api;
+pub mod ipxe_catalog;
+pub mod ipxe_script;
+pub mod kickstart;
+pub mod network_apply;
+pub mod network_profile;
+pub mod nic_rules;
+pub mod os_profile;
+pub mod progress_control;
+pub mod redfish;
+pub mod routes;
+pub mod template;
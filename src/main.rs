@@ -14,54 +14,88 @@
  * limitations under the License.
 */
 
-pub mod command_execute;
-pub mod database_init;
-pub mod hosts_discovery;
-pub mod ipxe_script;
-pub mod progress_control;
-
 use actix_web::{App, HttpServer, web};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use tokio::task;
 
-use crate::database_init::init_db;
-use crate::hosts_discovery::monitor_dhcp_leases;
-use crate::ipxe_script::get_ipxe_script;
-use crate::progress_control::progress_control;
-
-// 数据库地址
-const DB_PATH: &str = "./cloudboot-lce.db";
-const SQL_CONN_POOL_SIZE: u32 = 8;
+use cloudboot_lce::database_init::init_db;
+use cloudboot_lce::executor::SystemExecutor;
+use cloudboot_lce::hosts_discovery::monitor_dhcp_leases;
+use cloudboot_lce::ipxe_catalog::import_legacy_scripts;
+use cloudboot_lce::progress_control::progress_control;
+use cloudboot_lce::{
+    bmc, command_execute, config, credentials, installer, ipxe_script, routes, template,
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    // 加载配置
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("[ERROR] {e}");
+            std::process::exit(1);
+        }
+    };
+    if let Some(secret_key_file) = &config.ssh.secret_key_file
+        && let Err(e) = credentials::load_secret_key(secret_key_file)
+    {
+        eprintln!("[ERROR] {e}");
+        std::process::exit(1);
+    }
     // 初始化连接池 (新增)
-    let manager = SqliteConnectionManager::file(DB_PATH);
+    let manager = SqliteConnectionManager::file(&config.database.path);
     let db_pool = Pool::builder()
-        .max_size(SQL_CONN_POOL_SIZE) // 根据需求调整连接数
+        .max_size(config.database.pool_size)
         .build(manager)
         .unwrap();
     // 初始化数据库
     let conn = db_pool.get().unwrap();
     init_db(&conn);
+    import_legacy_scripts(&conn);
+    command_execute::init(&config.ssh, db_pool.clone());
+    bmc::init(&config.ipmi, &config.redfish, db_pool.clone());
+    template::init(&config.templates);
+    ipxe_script::init(&config.ipxe);
+    installer::init(&config.installer);
     // 监控 dhcp.leases
     let db_pool_clone = db_pool.clone();
+    let discovery = config.discovery.clone();
     tokio::spawn(async move {
-        monitor_dhcp_leases("/var/lib/dhcpd/dhcpd.leases", 10, db_pool_clone).await;
+        monitor_dhcp_leases(
+            SystemExecutor::new(),
+            &discovery.leases_file,
+            discovery.interval_secs,
+            discovery.concurrency,
+            db_pool_clone,
+        )
+        .await;
     });
     // 进行装机进度控制
     let db_pool_clone = db_pool.clone();
+    let progress = config.progress.clone();
+    let executor = SystemExecutor::new();
     task::spawn(async move {
-        progress_control(10, db_pool_clone).await;
+        progress_control(executor, progress, db_pool_clone).await;
     });
-    // 受理 HTTP 请求
-    HttpServer::new(move || {
+    // 受理装机网络中主机的请求
+    let installer_pool = db_pool.clone();
+    let installer_server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(installer_pool.clone()))
+            .configure(routes::installer)
+    })
+    .bind(&config.server.bind)?
+    .run();
+    // 受理管理请求
+    let admin_server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(db_pool.clone()))
-            .route("/api/ipxe/{serial}", web::get().to(get_ipxe_script))
+            .configure(routes::admin)
     })
-    .bind("127.0.0.1:8000")?
-    .run()
-    .await
+    .bind(&config.server.admin_bind)?
+    .run();
+    tokio::try_join!(installer_server, admin_server)?;
+    Ok(())
 }
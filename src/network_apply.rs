@@ -0,0 +1,288 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 装机后的网络配置下发：一次 SSH 写入全部配置文件和应用脚本，脚本在主机上替换连接配置、只重新加载一次
+// NetworkManager ，业务地址在限定时间内没有生效时恢复原来的配置，并将结果写入结果文件供服务端读取
+use crate::network_profile::{BOND, NetworkFormat, NetworkProfile};
+
+// 主机上保存待写入的配置文件、备份、应用脚本和日志的目录
+const WORK_DIR: &str = "/tmp/.install/network";
+// 应用结果：applying 、applied 或 rolled-back 加配置版本
+pub const RESULT_FILE: &str = "/tmp/.install/network/result";
+// 回滚时需要清理和恢复的两个目录
+const KEYFILE_DIR: &str = "/etc/NetworkManager/system-connections";
+const IFCFG_DIR: &str = "/etc/sysconfig/network-scripts";
+// 写入文件的 heredoc 结束标记
+const EOF_MARKER: &str = "CLOUDBOOT_EOF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyState {
+    Applying,
+    Applied,
+    RolledBack,
+}
+
+// 主机结果文件的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyResult {
+    pub state: ApplyState,
+    pub version: String,
+}
+
+// 下发一个版本网络配置的 SSH 命令
+pub struct NetworkPush {
+    // 配置内容的摘要，内容不变时版本不变
+    pub version: String,
+    pub command: String,
+}
+
+impl ApplyState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "applying" => Some(ApplyState::Applying),
+            "applied" => Some(ApplyState::Applied),
+            "rolled-back" => Some(ApplyState::RolledBack),
+            _ => None,
+        }
+    }
+}
+
+// 解析结果文件，格式不对时返回 None
+pub fn parse_result(output: &str) -> Option<ApplyResult> {
+    let (state, version) = output.trim().split_once(' ')?;
+    Some(ApplyResult {
+        state: ApplyState::parse(state)?,
+        version: version.trim().to_string(),
+    })
+}
+
+// FNV-1a 64 位摘要，服务端重启或升级后同样的配置得到同样的版本
+fn digest(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.bytes().chain([0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+// 主机上执行的应用脚本：先把新文件写成目标目录中的隐藏临时文件，全部写入后删除原有的连接配置并改名替换，
+// 重新加载一次后激活绑定和各接口；业务地址出现且默认网关可以 ping 通才算生效，否则恢复备份并重启 NetworkManager
+fn apply_script(
+    version: &str,
+    format: NetworkFormat,
+    hostname: &str,
+    address: &str,
+    profile: &NetworkProfile,
+    rollback_secs: u64,
+) -> String {
+    let target = format.directory();
+    let connections: Vec<String> = std::iter::once(BOND.to_string())
+        .chain(
+            profile
+                .interfaces
+                .iter()
+                .filter(|interface| interface.vlan_id.is_some())
+                .map(|interface| interface.name()),
+        )
+        .collect();
+    let gateway_check = match profile.primary().gateway {
+        Some(gateway) => format!(" && ping -c1 -W1 {gateway} >/dev/null"),
+        None => String::new(),
+    };
+    format!(
+        r#"#!/bin/bash
+work={WORK_DIR}
+target={target}
+log() {{ echo "[$(date '+%F %T')] $*"; }}
+remove_connections() {{
+  rm -f {KEYFILE_DIR}/*
+  find {IFCFG_DIR} -maxdepth 1 \( -name 'ifcfg-*' -o -name 'route-*' -o -name 'route6-*' \) ! -name ifcfg-lo -delete 2>/dev/null
+}}
+rollback() {{
+  log "$1, rolling back"
+  rm -f $target/.*.tmp
+  remove_connections
+  cp -a $work/backup/keyfile/. {KEYFILE_DIR}/
+  cp -a $work/backup/ifcfg/. {IFCFG_DIR}/ 2>/dev/null
+  [ -s $work/backup/hostname ] && hostnamectl set-hostname --static "$(cat $work/backup/hostname)"
+  systemctl restart NetworkManager
+  echo "rolled-back {version}" >$work/result
+  exit 1
+}}
+log "applying {version}"
+rm -rf $work/backup
+mkdir -p $work/backup/keyfile $work/backup/ifcfg $target
+cp -a {KEYFILE_DIR}/. $work/backup/keyfile/ 2>/dev/null
+cp -a {IFCFG_DIR}/ifcfg-* {IFCFG_DIR}/route-* {IFCFG_DIR}/route6-* $work/backup/ifcfg/ 2>/dev/null
+cp -a /etc/hostname $work/backup/hostname 2>/dev/null
+for file in $work/staged/*; do
+  install -m 600 "$file" "$target/.${{file##*/}}.tmp" || rollback "cannot write ${{file##*/}}"
+done
+remove_connections
+for file in $work/staged/*; do
+  name=${{file##*/}}
+  mv -f "$target/.$name.tmp" "$target/$name" || rollback "cannot install $name"
+done
+hostnamectl set-hostname --static {hostname}
+nmcli connection reload
+SECONDS=0
+for connection in {connections}; do
+  nmcli --wait 10 connection up $connection
+done
+while [ $SECONDS -lt {rollback_secs} ]; do
+  if ip -o addr show | grep -qF " {address}/"{gateway_check}; then
+    log "{address} is up"
+    nmcli connection show
+    cat /proc/net/bonding/{BOND}
+    echo "applied {version}" >$work/result
+    exit 0
+  fi
+  sleep 2
+done
+rollback "{address} is not up in {rollback_secs}s"
+"#,
+        connections = connections.join(" ")
+    )
+}
+
+// 生成下发网络配置的 SSH 命令：写入配置文件和应用脚本，标记为 applying 后在后台执行脚本。
+// 切换网络后 SSH 连接会断开，因此脚本用 nohup 执行，服务端之后读取结果文件或 ping 业务地址确认结果
+pub fn push(
+    format: NetworkFormat,
+    hostname: &str,
+    address: &str,
+    profile: &NetworkProfile,
+    slaves: &[String],
+    rollback_secs: u64,
+) -> NetworkPush {
+    let files = profile.files(format, slaves);
+    let rollback = rollback_secs.to_string();
+    let mut parts = vec![format.as_str(), hostname, address, rollback.as_str()];
+    for file in &files {
+        parts.push(&file.name);
+        parts.push(&file.content);
+    }
+    let version = digest(&parts);
+    let mut command = format!("rm -rf {WORK_DIR}/staged && mkdir -p {WORK_DIR}/staged\n");
+    for file in &files {
+        command.push_str(&format!(
+            "cat >{WORK_DIR}/staged/{} <<'{EOF_MARKER}'\n{}{EOF_MARKER}\n",
+            file.name, file.content
+        ));
+    }
+    command.push_str(&format!(
+        "cat >{WORK_DIR}/apply.sh <<'{EOF_MARKER}'\n{}{EOF_MARKER}\n\
+         chmod +x {WORK_DIR}/apply.sh\n\
+         echo \"applying {version}\" >{RESULT_FILE}\n\
+         nohup {WORK_DIR}/apply.sh </dev/null &>{WORK_DIR}/apply.log &\n",
+        apply_script(&version, format, hostname, address, profile, rollback_secs)
+    ));
+    NetworkPush { version, command }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_writes_files_and_waits_for_public_ip() {
+        let profile = NetworkProfile::from_host_fields("192.168.10.11", 100).unwrap();
+        let slaves = ["ens1f0".to_string(), "ens2f0".to_string()];
+        let keyfile = push(
+            NetworkFormat::Keyfile,
+            "node-01",
+            "192.168.10.11",
+            &profile,
+            &slaves,
+            120,
+        );
+        let command = &keyfile.command;
+        assert!(command.contains(
+            "cat >/tmp/.install/network/staged/bond0.100.nmconnection <<'CLOUDBOOT_EOF'\n[connection]\nid=bond0.100\n"
+        ));
+        assert!(command.contains("target=/etc/NetworkManager/system-connections\n"));
+        assert!(command.contains("for connection in bond0 bond0.100; do"));
+        assert!(command.contains(
+            "if ip -o addr show | grep -qF \" 192.168.10.11/\" && ping -c1 -W1 192.168.10.1 >/dev/null; then"
+        ));
+        assert!(command.contains("while [ $SECONDS -lt 120 ]; do"));
+        assert_eq!(command.matches("nmcli connection reload").count(), 1);
+        assert!(command.ends_with(&format!(
+            "echo \"applying {}\" >/tmp/.install/network/result\n\
+             nohup /tmp/.install/network/apply.sh </dev/null &>/tmp/.install/network/apply.log &\n",
+            keyfile.version
+        )));
+
+        // 内容不变时版本不变，格式或成员网卡变化时版本变化
+        let again = push(
+            NetworkFormat::Keyfile,
+            "node-01",
+            "192.168.10.11",
+            &profile,
+            &slaves,
+            120,
+        );
+        assert_eq!(again.version, keyfile.version);
+        let ifcfg = push(
+            NetworkFormat::Ifcfg,
+            "node-01",
+            "192.168.10.11",
+            &profile,
+            &slaves,
+            120,
+        );
+        assert_ne!(ifcfg.version, keyfile.version);
+        assert!(
+            ifcfg
+                .command
+                .contains("target=/etc/sysconfig/network-scripts\n")
+        );
+        assert!(
+            ifcfg
+                .command
+                .contains("/staged/ifcfg-bond0.100 <<'CLOUDBOOT_EOF'\n")
+        );
+        let one_slave = push(
+            NetworkFormat::Keyfile,
+            "node-01",
+            "192.168.10.11",
+            &profile,
+            &slaves[..1],
+            120,
+        );
+        assert_ne!(one_slave.version, keyfile.version);
+    }
+
+    #[test]
+    fn parses_apply_result() {
+        assert_eq!(
+            parse_result("rolled-back 0123456789abcdef\n"),
+            Some(ApplyResult {
+                state: ApplyState::RolledBack,
+                version: "0123456789abcdef".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_result("applied 0123456789abcdef").map(|result| result.state),
+            Some(ApplyState::Applied)
+        );
+        assert_eq!(parse_result("done"), None);
+        assert_eq!(parse_result("finished 0123456789abcdef"), None);
+    }
+}
@@ -0,0 +1,1125 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 主机网络配置：绑定模式、MTU 、多个 VLAN 接口、任意前缀长度的地址、网关、DNS 、静态路由和 IPv6 ，
+// 装机后写入主机的 keyfile 或 ifcfg 文件和 kickstart 的 network 命令据此生成
+use actix_web::{HttpResponse, Responder, web};
+use chrono::Local;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::installer::default_gateway;
+
+// 绑定接口名称
+pub const BOND: &str = "bond0";
+// 未设置时网卡的 MTU
+const DEFAULT_MTU: u32 = 1500;
+// 主机字段推算的业务网络前缀长度
+const LEGACY_PREFIX: u8 = 24;
+
+// 带前缀长度的地址，例如 192.168.10.11/24 或 2001:db8::11/64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    pub address: IpAddr,
+    pub prefix: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BondMode {
+    BalanceRr,
+    ActiveBackup,
+    BalanceXor,
+    Broadcast,
+    #[default]
+    #[serde(rename = "802.3ad")]
+    Lacp,
+    BalanceTlb,
+    BalanceAlb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LacpRate {
+    Slow,
+    Fast,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum XmitHashPolicy {
+    #[serde(rename = "layer2")]
+    Layer2,
+    #[serde(rename = "layer2+3")]
+    Layer23,
+    #[serde(rename = "layer3+4")]
+    Layer34,
+    #[serde(rename = "encap2+3")]
+    Encap23,
+    #[serde(rename = "encap3+4")]
+    Encap34,
+}
+
+// 绑定参数，默认为 802.3ad 、miimon=100
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BondConfig {
+    #[serde(default)]
+    pub mode: BondMode,
+    // 只用于 802.3ad
+    pub lacp_rate: Option<LacpRate>,
+    // 只用于 802.3ad 、balance-xor 和 balance-tlb
+    pub xmit_hash_policy: Option<XmitHashPolicy>,
+    #[serde(default = "default_miimon")]
+    pub miimon: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub destination: Cidr,
+    pub gateway: IpAddr,
+    pub metric: Option<u32>,
+}
+
+// 绑定上的一个接口：设置 vlan_id 时为 VLAN 子接口，否则地址直接配置在绑定上
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub vlan_id: Option<u32>,
+    pub mtu: Option<u32>,
+    pub addresses: Vec<Cidr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub ipv6_gateway: Option<Ipv6Addr>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    #[serde(default)]
+    pub bond: BondConfig,
+    // 绑定和成员网卡的 MTU
+    pub mtu: Option<u32>,
+    pub interfaces: Vec<NetworkInterface>,
+    // DNS 服务器，配置在带默认网关的接口上
+    #[serde(default)]
+    pub dns: Vec<IpAddr>,
+}
+
+// 装机后网络配置文件的格式：NetworkManager 的 keyfile ，或旧版本发行版使用的 ifcfg
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkFormat {
+    #[default]
+    Keyfile,
+    Ifcfg,
+}
+
+// 写入主机的一个网络配置文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkFile {
+    // 文件名，所在目录由格式决定
+    pub name: String,
+    pub content: String,
+}
+
+// GET /api/hosts/{serial}/network 的返回
+#[derive(Serialize)]
+pub struct HostNetwork {
+    // profile 为登记的网络配置，host_fields 为按 public_ip_addr 和 vlan_id 推算
+    source: &'static str,
+    profile: NetworkProfile,
+}
+
+fn default_miimon() -> u32 {
+    100
+}
+
+impl Default for BondConfig {
+    fn default() -> Self {
+        BondConfig {
+            mode: BondMode::default(),
+            lacp_rate: None,
+            xmit_hash_policy: None,
+            miimon: default_miimon(),
+        }
+    }
+}
+
+impl Cidr {
+    // 地址是否在该网段内
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+
+    // IPv4 前缀对应的子网掩码
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = value
+            .split_once('/')
+            .ok_or_else(|| format!("{value} has no prefix length"))?;
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("invalid address in {value}"))?;
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| *prefix <= max_prefix)
+            .ok_or_else(|| format!("invalid prefix length in {value}"))?;
+        Ok(Cidr { address, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+impl NetworkFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkFormat::Keyfile => "keyfile",
+            NetworkFormat::Ifcfg => "ifcfg",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keyfile" => Some(NetworkFormat::Keyfile),
+            "ifcfg" => Some(NetworkFormat::Ifcfg),
+            _ => None,
+        }
+    }
+
+    // 配置文件所在目录
+    pub fn directory(&self) -> &'static str {
+        match self {
+            NetworkFormat::Keyfile => "/etc/NetworkManager/system-connections",
+            NetworkFormat::Ifcfg => "/etc/sysconfig/network-scripts",
+        }
+    }
+}
+
+impl BondMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BondMode::BalanceRr => "balance-rr",
+            BondMode::ActiveBackup => "active-backup",
+            BondMode::BalanceXor => "balance-xor",
+            BondMode::Broadcast => "broadcast",
+            BondMode::Lacp => "802.3ad",
+            BondMode::BalanceTlb => "balance-tlb",
+            BondMode::BalanceAlb => "balance-alb",
+        }
+    }
+}
+
+impl LacpRate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LacpRate::Slow => "slow",
+            LacpRate::Fast => "fast",
+        }
+    }
+}
+
+impl XmitHashPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            XmitHashPolicy::Layer2 => "layer2",
+            XmitHashPolicy::Layer23 => "layer2+3",
+            XmitHashPolicy::Layer34 => "layer3+4",
+            XmitHashPolicy::Encap23 => "encap2+3",
+            XmitHashPolicy::Encap34 => "encap3+4",
+        }
+    }
+}
+
+impl BondConfig {
+    // 内核 bonding 驱动的参数，keyfile 的 [bond] 节和 ifcfg 的 BONDING_OPTS 使用同样的名称
+    fn option_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut options = vec![
+            ("mode", self.mode.as_str().to_string()),
+            ("miimon", self.miimon.to_string()),
+        ];
+        if let Some(rate) = self.lacp_rate {
+            options.push(("lacp_rate", rate.as_str().to_string()));
+        }
+        if let Some(policy) = self.xmit_hash_policy {
+            options.push(("xmit_hash_policy", policy.as_str().to_string()));
+        }
+        options
+    }
+
+    // kickstart 的 --bondopts ，以逗号分隔
+    pub fn options(&self) -> String {
+        let options: Vec<String> = self
+            .option_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        options.join(",")
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.lacp_rate.is_some() && self.mode != BondMode::Lacp {
+            return Err(format!(
+                "lacp_rate requires bond mode 802.3ad, not {}",
+                self.mode.as_str()
+            ));
+        }
+        if self.xmit_hash_policy.is_some()
+            && !matches!(
+                self.mode,
+                BondMode::Lacp | BondMode::BalanceXor | BondMode::BalanceTlb
+            )
+        {
+            return Err(format!(
+                "xmit_hash_policy is not used by bond mode {}",
+                self.mode.as_str()
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn validate_mtu(mtu: u32) -> Result<(), String> {
+    if (68..=9216).contains(&mtu) {
+        Ok(())
+    } else {
+        Err(format!("mtu must be between 68 and 9216, got {mtu}"))
+    }
+}
+
+impl NetworkInterface {
+    // 连接名称和接口名称
+    pub fn name(&self) -> String {
+        match self.vlan_id {
+            Some(vlan_id) => format!("{BOND}.{vlan_id}"),
+            None => BOND.to_string(),
+        }
+    }
+
+    pub fn ipv4_addresses(&self) -> impl Iterator<Item = &Cidr> {
+        self.addresses.iter().filter(|cidr| cidr.address.is_ipv4())
+    }
+
+    pub fn ipv6_addresses(&self) -> impl Iterator<Item = &Cidr> {
+        self.addresses.iter().filter(|cidr| cidr.address.is_ipv6())
+    }
+
+    // 地址是否在接口直连的网段内
+    fn reaches(&self, address: IpAddr) -> bool {
+        self.addresses.iter().any(|cidr| cidr.contains(address))
+    }
+
+    fn validate(&self, bond_mtu: u32) -> Result<(), String> {
+        let name = self.name();
+        if let Some(vlan_id) = self.vlan_id
+            && !(1..=4094).contains(&vlan_id)
+        {
+            return Err(format!("vlan_id must be between 1 and 4094, got {vlan_id}"));
+        }
+        if let Some(mtu) = self.mtu {
+            validate_mtu(mtu)?;
+            if mtu > bond_mtu {
+                return Err(format!(
+                    "{name} mtu {mtu} is larger than the bond mtu {bond_mtu}"
+                ));
+            }
+        }
+        if self.addresses.is_empty() {
+            return Err(format!("{name} has no addresses"));
+        }
+        if let Some(gateway) = self.gateway
+            && !self.reaches(IpAddr::V4(gateway))
+        {
+            return Err(format!(
+                "{name} gateway {gateway} is not in any IPv4 subnet of the interface"
+            ));
+        }
+        if let Some(gateway) = self.ipv6_gateway
+            && !self.reaches(IpAddr::V6(gateway))
+        {
+            return Err(format!(
+                "{name} ipv6_gateway {gateway} is not in any IPv6 subnet of the interface"
+            ));
+        }
+        for route in &self.routes {
+            if route.destination.address.is_ipv4() != route.gateway.is_ipv4() {
+                return Err(format!(
+                    "{name} route to {} has a gateway of another address family",
+                    route.destination
+                ));
+            }
+            if !self.reaches(route.gateway) {
+                return Err(format!(
+                    "{name} route gateway {} is not in any subnet of the interface",
+                    route.gateway
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NetworkProfile {
+    // 按主机的 public_ip_addr 和 vlan_id 推算：802.3ad 绑定上一个 VLAN 子接口，/24 网段，网关为 .1
+    pub fn from_host_fields(public_ip_addr: &str, vlan_id: u32) -> Option<Self> {
+        let address: Ipv4Addr = public_ip_addr.parse().ok()?;
+        Some(NetworkProfile {
+            bond: BondConfig::default(),
+            mtu: None,
+            interfaces: vec![NetworkInterface {
+                vlan_id: Some(vlan_id),
+                mtu: None,
+                addresses: vec![Cidr {
+                    address: IpAddr::V4(address),
+                    prefix: LEGACY_PREFIX,
+                }],
+                gateway: default_gateway(public_ip_addr).parse().ok(),
+                ipv6_gateway: None,
+                routes: Vec::new(),
+            }],
+            dns: Vec::new(),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.bond.validate()?;
+        if let Some(mtu) = self.mtu {
+            validate_mtu(mtu)?;
+        }
+        if self.interfaces.is_empty() {
+            return Err("at least one interface is required".to_string());
+        }
+        let mut names = HashSet::new();
+        let mut addresses = HashSet::new();
+        for interface in &self.interfaces {
+            if !names.insert(interface.name()) {
+                return Err(format!("{} is defined more than once", interface.name()));
+            }
+            interface.validate(self.mtu.unwrap_or(DEFAULT_MTU))?;
+            for cidr in &interface.addresses {
+                if !addresses.insert(cidr.address) {
+                    return Err(format!("address {} is used more than once", cidr.address));
+                }
+            }
+        }
+        if self
+            .interfaces
+            .iter()
+            .filter(|interface| interface.gateway.is_some())
+            .count()
+            > 1
+        {
+            return Err("only one interface may have a gateway".to_string());
+        }
+        if self
+            .interfaces
+            .iter()
+            .filter(|interface| interface.ipv6_gateway.is_some())
+            .count()
+            > 1
+        {
+            return Err("only one interface may have an ipv6_gateway".to_string());
+        }
+        Ok(())
+    }
+
+    // 带默认网关的接口，DNS 配置在该接口上；都没有网关时为第一个接口
+    pub fn primary(&self) -> &NetworkInterface {
+        self.interfaces
+            .iter()
+            .find(|interface| interface.gateway.is_some())
+            .or_else(|| {
+                self.interfaces
+                    .iter()
+                    .find(|interface| interface.ipv6_gateway.is_some())
+            })
+            .unwrap_or(&self.interfaces[0])
+    }
+
+    // 装机后确认主机上线的业务地址：主接口的第一个地址
+    pub fn address(&self) -> Option<IpAddr> {
+        if self.interfaces.is_empty() {
+            return None;
+        }
+        self.primary().addresses.first().map(|cidr| cidr.address)
+    }
+
+    fn is_primary(&self, interface: &NetworkInterface) -> bool {
+        std::ptr::eq(self.primary(), interface)
+    }
+
+    // 接口 IPv4 或 IPv6 的网关
+    fn gateway(interface: &NetworkInterface, ipv4: bool) -> Option<IpAddr> {
+        match ipv4 {
+            true => interface.gateway.map(IpAddr::V4),
+            false => interface.ipv6_gateway.map(IpAddr::V6),
+        }
+    }
+
+    // keyfile 的 [ipv4] 和 [ipv6] 节，没有接口时不配置地址
+    fn keyfile_ip_sections(&self, interface: Option<&NetworkInterface>) -> String {
+        let mut sections = String::new();
+        for (family, ipv4) in [("ipv4", true), ("ipv6", false)] {
+            sections.push_str(&format!("\n[{family}]\n"));
+            if !ipv4 {
+                sections.push_str("addr-gen-mode=eui64\n");
+            }
+            let addresses: Vec<&Cidr> = interface
+                .map(|interface| {
+                    interface
+                        .addresses
+                        .iter()
+                        .filter(|cidr| cidr.address.is_ipv4() == ipv4)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let Some(interface) = interface.filter(|_| !addresses.is_empty()) else {
+                sections.push_str(if ipv4 {
+                    "method=disabled\n"
+                } else {
+                    "method=ignore\n"
+                });
+                continue;
+            };
+            sections.push_str("method=manual\n");
+            for (i, cidr) in addresses.iter().enumerate() {
+                sections.push_str(&format!("address{}={cidr}\n", i + 1));
+            }
+            if let Some(gateway) = Self::gateway(interface, ipv4) {
+                sections.push_str(&format!("gateway={gateway}\n"));
+            }
+            let dns: Vec<String> = self
+                .dns
+                .iter()
+                .filter(|server| server.is_ipv4() == ipv4)
+                .map(|server| format!("{server};"))
+                .collect();
+            if self.is_primary(interface) && !dns.is_empty() {
+                sections.push_str(&format!("dns={}\n", dns.concat()));
+            }
+            let routes = interface
+                .routes
+                .iter()
+                .filter(|route| route.destination.address.is_ipv4() == ipv4);
+            for (i, route) in routes.enumerate() {
+                sections.push_str(&format!(
+                    "route{}={},{}",
+                    i + 1,
+                    route.destination,
+                    route.gateway
+                ));
+                if let Some(metric) = route.metric {
+                    sections.push_str(&format!(",{metric}"));
+                }
+                sections.push('\n');
+            }
+        }
+        sections
+    }
+
+    fn keyfile_mtu_section(mtu: Option<u32>) -> String {
+        mtu.map(|mtu| format!("\n[ethernet]\nmtu={mtu}\n"))
+            .unwrap_or_default()
+    }
+
+    // NetworkManager 的 keyfile ：绑定、成员网卡和各 VLAN 接口各一个 .nmconnection 文件
+    fn keyfiles(&self, slaves: &[String]) -> Vec<NetworkFile> {
+        let untagged = self
+            .interfaces
+            .iter()
+            .find(|interface| interface.vlan_id.is_none());
+        let bond_options: String = self
+            .bond
+            .option_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect();
+        let mut files = vec![NetworkFile {
+            name: format!("{BOND}.nmconnection"),
+            content: format!(
+                "[connection]\nid={BOND}\ntype=bond\ninterface-name={BOND}\nautoconnect-slaves=1\n{}\n[bond]\n{bond_options}{}",
+                Self::keyfile_mtu_section(self.mtu),
+                self.keyfile_ip_sections(untagged)
+            ),
+        }];
+        for nic in slaves {
+            files.push(NetworkFile {
+                name: format!("{nic}.nmconnection"),
+                content: format!(
+                    "[connection]\nid={nic}\ntype=ethernet\ninterface-name={nic}\nmaster={BOND}\nslave-type=bond\n{}",
+                    Self::keyfile_mtu_section(self.mtu)
+                ),
+            });
+        }
+        for interface in &self.interfaces {
+            let Some(vlan_id) = interface.vlan_id else {
+                continue;
+            };
+            let name = interface.name();
+            files.push(NetworkFile {
+                name: format!("{name}.nmconnection"),
+                content: format!(
+                    "[connection]\nid={name}\ntype=vlan\ninterface-name={name}\n{}\n[vlan]\nid={vlan_id}\nparent={BOND}\n{}",
+                    Self::keyfile_mtu_section(interface.mtu),
+                    self.keyfile_ip_sections(Some(interface))
+                ),
+            });
+        }
+        files
+    }
+
+    // ifcfg 文件中接口的地址、网关和 DNS
+    fn ifcfg_ip_lines(&self, interface: &NetworkInterface) -> String {
+        let mut lines = String::new();
+        for (i, cidr) in interface.ipv4_addresses().enumerate() {
+            lines.push_str(&format!(
+                "IPADDR{i}={}\nPREFIX{i}={}\n",
+                cidr.address, cidr.prefix
+            ));
+        }
+        if let Some(gateway) = interface.gateway {
+            lines.push_str(&format!("GATEWAY={gateway}\n"));
+        }
+        let ipv6_addresses: Vec<String> = interface.ipv6_addresses().map(Cidr::to_string).collect();
+        match ipv6_addresses.split_first() {
+            Some((first, secondaries)) => {
+                lines.push_str(&format!(
+                    "IPV6INIT=yes\nIPV6_AUTOCONF=no\nIPV6ADDR={first}\n"
+                ));
+                if !secondaries.is_empty() {
+                    lines.push_str(&format!(
+                        "IPV6ADDR_SECONDARIES=\"{}\"\n",
+                        secondaries.join(" ")
+                    ));
+                }
+                if let Some(gateway) = interface.ipv6_gateway {
+                    lines.push_str(&format!("IPV6_DEFAULTGW={gateway}\n"));
+                }
+            }
+            None => lines.push_str("IPV6INIT=no\n"),
+        }
+        if self.is_primary(interface) {
+            for (i, server) in self.dns.iter().enumerate() {
+                lines.push_str(&format!("DNS{}={server}\n", i + 1));
+            }
+        }
+        lines
+    }
+
+    // 接口静态路由的 route-<接口> 和 route6-<接口> 文件
+    fn ifcfg_route_files(interface: &NetworkInterface) -> Vec<NetworkFile> {
+        let name = interface.name();
+        let mut files = Vec::new();
+        let ipv4_routes: String = interface
+            .routes
+            .iter()
+            .filter(|route| route.destination.address.is_ipv4())
+            .enumerate()
+            .map(|(i, route)| {
+                let metric = route
+                    .metric
+                    .map(|metric| format!("METRIC{i}={metric}\n"))
+                    .unwrap_or_default();
+                format!(
+                    "ADDRESS{i}={}\nNETMASK{i}={}\nGATEWAY{i}={}\n{metric}",
+                    route.destination.address,
+                    route.destination.netmask(),
+                    route.gateway
+                )
+            })
+            .collect();
+        if !ipv4_routes.is_empty() {
+            files.push(NetworkFile {
+                name: format!("route-{name}"),
+                content: ipv4_routes,
+            });
+        }
+        let ipv6_routes: String = interface
+            .routes
+            .iter()
+            .filter(|route| route.destination.address.is_ipv6())
+            .map(|route| match route.metric {
+                Some(metric) => format!(
+                    "{} via {} metric {metric}\n",
+                    route.destination, route.gateway
+                ),
+                None => format!("{} via {}\n", route.destination, route.gateway),
+            })
+            .collect();
+        if !ipv6_routes.is_empty() {
+            files.push(NetworkFile {
+                name: format!("route6-{name}"),
+                content: ipv6_routes,
+            });
+        }
+        files
+    }
+
+    fn ifcfg_mtu_line(mtu: Option<u32>) -> String {
+        mtu.map(|mtu| format!("MTU={mtu}\n")).unwrap_or_default()
+    }
+
+    // 旧版本 NetworkManager 的 ifcfg 文件：绑定、成员网卡和各 VLAN 接口的 ifcfg-<接口> 以及静态路由文件
+    fn ifcfg_files(&self, slaves: &[String]) -> Vec<NetworkFile> {
+        let bond_options: Vec<String> = self
+            .bond
+            .option_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        let mut bond = format!(
+            "DEVICE={BOND}\nNAME={BOND}\nTYPE=Bond\nBONDING_MASTER=yes\nBONDING_OPTS=\"{}\"\nONBOOT=yes\nBOOTPROTO=none\nAUTOCONNECT_SLAVES=yes\n{}",
+            bond_options.join(" "),
+            Self::ifcfg_mtu_line(self.mtu)
+        );
+        match self
+            .interfaces
+            .iter()
+            .find(|interface| interface.vlan_id.is_none())
+        {
+            Some(interface) => bond.push_str(&self.ifcfg_ip_lines(interface)),
+            None => bond.push_str("IPV6INIT=no\n"),
+        }
+        let mut files = vec![NetworkFile {
+            name: format!("ifcfg-{BOND}"),
+            content: bond,
+        }];
+        for nic in slaves {
+            files.push(NetworkFile {
+                name: format!("ifcfg-{nic}"),
+                content: format!(
+                    "DEVICE={nic}\nNAME={nic}\nTYPE=Ethernet\nMASTER={BOND}\nSLAVE=yes\nONBOOT=yes\nBOOTPROTO=none\n{}",
+                    Self::ifcfg_mtu_line(self.mtu)
+                ),
+            });
+        }
+        for interface in &self.interfaces {
+            if let Some(vlan_id) = interface.vlan_id {
+                let name = interface.name();
+                files.push(NetworkFile {
+                    name: format!("ifcfg-{name}"),
+                    content: format!(
+                        "DEVICE={name}\nNAME={name}\nTYPE=Vlan\nVLAN=yes\nPHYSDEV={BOND}\nVLAN_ID={vlan_id}\nONBOOT=yes\nBOOTPROTO=none\n{}{}",
+                        Self::ifcfg_mtu_line(interface.mtu),
+                        self.ifcfg_ip_lines(interface)
+                    ),
+                });
+            }
+            files.extend(Self::ifcfg_route_files(interface));
+        }
+        files
+    }
+
+    // 装机后写入主机的网络配置文件，slaves 为选中的成员网卡
+    pub fn files(&self, format: NetworkFormat, slaves: &[String]) -> Vec<NetworkFile> {
+        match format {
+            NetworkFormat::Keyfile => self.keyfiles(slaves),
+            NetworkFormat::Ifcfg => self.ifcfg_files(slaves),
+        }
+    }
+
+    // kickstart 的 network 命令，每个接口一行，$slaves 为 %pre 中找到的成员网卡。
+    // network 命令只支持每个地址族一个地址，其余地址和静态路由在装机后配置网络时生效
+    pub fn kickstart_commands(&self, hostname: &str) -> Vec<String> {
+        self.interfaces
+            .iter()
+            .enumerate()
+            .map(|(i, interface)| {
+                let mut args = vec![
+                    format!("--device={BOND}"),
+                    "--bondslaves=$slaves".to_string(),
+                    format!("--bondopts={}", self.bond.options()),
+                ];
+                if let Some(vlan_id) = interface.vlan_id {
+                    args.push(format!("--vlanid={vlan_id}"));
+                }
+                if let Some(mtu) = interface.mtu.or(self.mtu) {
+                    args.push(format!("--mtu={mtu}"));
+                }
+                args.push("--bootproto=static".to_string());
+                match interface.ipv4_addresses().next() {
+                    Some(cidr) => {
+                        args.push(format!("--ip={}", cidr.address));
+                        args.push(format!("--netmask={}", cidr.netmask()));
+                        if let Some(gateway) = interface.gateway {
+                            args.push(format!("--gateway={gateway}"));
+                        }
+                    }
+                    None => args.push("--noipv4".to_string()),
+                }
+                match interface.ipv6_addresses().next() {
+                    Some(cidr) => {
+                        args.push(format!("--ipv6={cidr}"));
+                        if let Some(gateway) = interface.ipv6_gateway {
+                            args.push(format!("--ipv6gateway={gateway}"));
+                        }
+                    }
+                    None => args.push("--noipv6".to_string()),
+                }
+                if self.is_primary(interface) {
+                    if !self.dns.is_empty() {
+                        let dns: Vec<String> = self.dns.iter().map(IpAddr::to_string).collect();
+                        args.push(format!("--nameserver={}", dns.join(",")));
+                    }
+                } else {
+                    args.push("--nodefroute".to_string());
+                }
+                args.push("--onboot=yes".to_string());
+                if i == 0 {
+                    args.push(format!("--hostname={hostname}"));
+                }
+                format!("network {}", args.join(" "))
+            })
+            .collect()
+    }
+}
+
+// 主机登记的网络配置
+fn stored_profile(conn: &Connection, serial: &str) -> Option<NetworkProfile> {
+    conn.query_row(
+        "SELECT profile FROM network_profiles WHERE serial = ?1",
+        params![serial],
+        |row| row.get::<_, String>(0),
+    )
+    .optional()
+    .unwrap()
+    .and_then(|profile| match serde_json::from_str(&profile) {
+        Ok(profile) => Some(profile),
+        Err(e) => {
+            println!("[ERROR] Invalid network profile of host {serial}: {e}");
+            None
+        }
+    })
+}
+
+// 主机生效的网络配置和来源：登记的网络配置优先，没有时按 public_ip_addr 和 vlan_id 推算
+fn resolve(conn: &Connection, serial: &str) -> Option<(&'static str, NetworkProfile)> {
+    if let Some(profile) = stored_profile(conn, serial) {
+        return Some(("profile", profile));
+    }
+    let fields: Option<(Option<String>, Option<u32>)> = conn
+        .query_row(
+            "SELECT public_ip_addr, vlan_id FROM hosts WHERE serial = ?1",
+            params![serial],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .unwrap();
+    match fields {
+        Some((Some(public_ip_addr), Some(vlan_id))) => {
+            NetworkProfile::from_host_fields(&public_ip_addr, vlan_id)
+                .map(|profile| ("host_fields", profile))
+        }
+        _ => None,
+    }
+}
+
+// 主机生效的网络配置，没有登记也无法推算时返回 None
+pub fn host_profile(conn: &Connection, serial: &str) -> Option<NetworkProfile> {
+    resolve(conn, serial).map(|(_, profile)| profile)
+}
+
+fn host_exists(conn: &Connection, serial: &str) -> bool {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM hosts WHERE serial = ?1)",
+        params![serial],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+// 处理 GET /api/hosts/{serial}/network
+pub async fn get_host_network(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    if !host_exists(&conn, &serial) {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    }
+    match resolve(&conn, &serial) {
+        Some((source, profile)) => HttpResponse::Ok().json(HostNetwork { source, profile }),
+        None => HttpResponse::NotFound().json(json!({
+            "error": format!("host {serial} has no network profile, public_ip_addr or vlan_id")
+        })),
+    }
+}
+
+// 处理 PUT /api/hosts/{serial}/network
+pub async fn put_host_network(
+    serial: web::Path<String>,
+    profile: web::Json<NetworkProfile>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let profile = profile.into_inner();
+    if let Err(e) = profile.validate() {
+        return HttpResponse::BadRequest().json(json!({ "error": e }));
+    }
+    if profile.address().is_none() {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "primary interface has no address" }));
+    }
+    let conn = db_pool.get().unwrap();
+    if !host_exists(&conn, &serial) {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    }
+    let updated_at = Local::now()
+        .naive_local()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    conn.execute(
+        "INSERT OR REPLACE INTO network_profiles (serial, profile, updated_at) VALUES (?1, ?2, ?3)",
+        params![
+            serial.as_str(),
+            serde_json::to_string(&profile).unwrap(),
+            updated_at
+        ],
+    )
+    .unwrap();
+    println!("[INFO] Network profile of host {serial} updated by API");
+    HttpResponse::Ok().json(HostNetwork {
+        source: "profile",
+        profile,
+    })
+}
+
+// 处理 DELETE /api/hosts/{serial}/network ，之后按 public_ip_addr 和 vlan_id 推算
+pub async fn delete_host_network(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let deleted = conn
+        .execute(
+            "DELETE FROM network_profiles WHERE serial = ?1",
+            params![serial.as_str()],
+        )
+        .unwrap();
+    if deleted == 0 {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} has no network profile") }));
+    }
+    println!("[INFO] Network profile of host {serial} deleted by API");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(json: serde_json::Value) -> Result<NetworkProfile, String> {
+        let profile: NetworkProfile = serde_json::from_value(json).map_err(|e| e.to_string())?;
+        profile.validate().map(|_| profile)
+    }
+
+    #[test]
+    fn host_fields_match_legacy_network() {
+        let profile = NetworkProfile::from_host_fields("192.168.10.11", 100).unwrap();
+        profile.validate().unwrap();
+        let files = profile.files(
+            NetworkFormat::Keyfile,
+            &["ens1f0".to_string(), "ens2f0".to_string()],
+        );
+        let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "bond0.nmconnection",
+                "ens1f0.nmconnection",
+                "ens2f0.nmconnection",
+                "bond0.100.nmconnection"
+            ]
+        );
+        assert_eq!(
+            files[0].content,
+            "[connection]\nid=bond0\ntype=bond\ninterface-name=bond0\nautoconnect-slaves=1\n\n\
+             [bond]\nmode=802.3ad\nmiimon=100\n\n\
+             [ipv4]\nmethod=disabled\n\n\
+             [ipv6]\naddr-gen-mode=eui64\nmethod=ignore\n"
+        );
+        assert_eq!(
+            files[1].content,
+            "[connection]\nid=ens1f0\ntype=ethernet\ninterface-name=ens1f0\nmaster=bond0\nslave-type=bond\n"
+        );
+        assert_eq!(
+            files[3].content,
+            "[connection]\nid=bond0.100\ntype=vlan\ninterface-name=bond0.100\n\n\
+             [vlan]\nid=100\nparent=bond0\n\n\
+             [ipv4]\nmethod=manual\naddress1=192.168.10.11/24\ngateway=192.168.10.1\n\n\
+             [ipv6]\naddr-gen-mode=eui64\nmethod=ignore\n"
+        );
+        assert_eq!(
+            profile.kickstart_commands("node-01"),
+            [
+                "network --device=bond0 --bondslaves=$slaves --bondopts=mode=802.3ad,miimon=100 --vlanid=100 --bootproto=static --ip=192.168.10.11 --netmask=255.255.255.0 --gateway=192.168.10.1 --noipv6 --onboot=yes --hostname=node-01"
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_vlans_routes_and_ipv6() {
+        let profile = profile(json!({
+            "bond": {"mode": "802.3ad", "lacp_rate": "fast", "xmit_hash_policy": "layer3+4"},
+            "mtu": 9000,
+            "dns": ["10.0.0.53", "2001:db8::53"],
+            "interfaces": [
+                {"vlan_id": 200, "mtu": 1500, "addresses": ["10.20.0.5/16"],
+                 "routes": [{"destination": "10.30.0.0/16", "gateway": "10.20.0.254", "metric": 50}]},
+                {"vlan_id": 100, "addresses": ["192.168.10.11/26", "2001:db8::11/64"],
+                 "gateway": "192.168.10.62", "ipv6_gateway": "2001:db8::1"}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(profile.primary().vlan_id, Some(100));
+        let slaves = ["ens1f0".to_string()];
+        let keyfiles = profile.files(NetworkFormat::Keyfile, &slaves);
+        assert!(keyfiles[0].content.contains(
+            "\n[ethernet]\nmtu=9000\n\n[bond]\nmode=802.3ad\nmiimon=100\nlacp_rate=fast\nxmit_hash_policy=layer3+4\n"
+        ));
+        assert!(
+            keyfiles[1]
+                .content
+                .ends_with("slave-type=bond\n\n[ethernet]\nmtu=9000\n")
+        );
+        assert!(keyfiles[2].content.contains(
+            "[ipv4]\nmethod=manual\naddress1=10.20.0.5/16\nroute1=10.30.0.0/16,10.20.0.254,50\n"
+        ));
+        assert!(keyfiles[3].content.contains(
+            "[ipv4]\nmethod=manual\naddress1=192.168.10.11/26\ngateway=192.168.10.62\ndns=10.0.0.53;\n\n\
+             [ipv6]\naddr-gen-mode=eui64\nmethod=manual\naddress1=2001:db8::11/64\ngateway=2001:db8::1\ndns=2001:db8::53;\n"
+        ));
+
+        let ifcfg = profile.files(NetworkFormat::Ifcfg, &slaves);
+        let names: Vec<&str> = ifcfg.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "ifcfg-bond0",
+                "ifcfg-ens1f0",
+                "ifcfg-bond0.200",
+                "route-bond0.200",
+                "ifcfg-bond0.100"
+            ]
+        );
+        assert!(ifcfg[0].content.contains(
+            "BONDING_OPTS=\"mode=802.3ad miimon=100 lacp_rate=fast xmit_hash_policy=layer3+4\"\n"
+        ));
+        assert!(ifcfg[1].content.contains("MASTER=bond0\nSLAVE=yes\n"));
+        assert_eq!(
+            ifcfg[3].content,
+            "ADDRESS0=10.30.0.0\nNETMASK0=255.255.0.0\nGATEWAY0=10.20.0.254\nMETRIC0=50\n"
+        );
+        assert!(ifcfg[4].content.ends_with(
+            "VLAN_ID=100\nONBOOT=yes\nBOOTPROTO=none\n\
+             IPADDR0=192.168.10.11\nPREFIX0=26\nGATEWAY=192.168.10.62\n\
+             IPV6INIT=yes\nIPV6_AUTOCONF=no\nIPV6ADDR=2001:db8::11/64\nIPV6_DEFAULTGW=2001:db8::1\n\
+             DNS1=10.0.0.53\nDNS2=2001:db8::53\n"
+        ));
+        let kickstart = profile.kickstart_commands("node-01");
+        assert!(kickstart[0].contains("--vlanid=200 --mtu=1500"));
+        assert!(kickstart[0].contains("--netmask=255.255.0.0 --noipv6 --nodefroute"));
+        assert!(kickstart[1].contains(
+            "--netmask=255.255.255.192 --gateway=192.168.10.62 --ipv6=2001:db8::11/64 --ipv6gateway=2001:db8::1 --nameserver=10.0.0.53,2001:db8::53"
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let interface = |extra: serde_json::Value| {
+            let mut interface = json!({"vlan_id": 100, "addresses": ["192.168.10.11/24"]});
+            interface
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            interface
+        };
+        let error = |json: serde_json::Value| profile(json).unwrap_err();
+        assert!(error(json!({"interfaces": []})).contains("at least one"));
+        assert!(
+            error(json!({"interfaces": [interface(json!({"gateway": "192.168.11.1"}))]}))
+                .contains("not in any IPv4 subnet")
+        );
+        assert!(
+            error(json!({"interfaces": [interface(json!({"addresses": ["192.168.10.11/33"]}))]}))
+                .contains("invalid prefix length")
+        );
+        assert!(
+            error(
+                json!({"bond": {"mode": "active-backup", "lacp_rate": "fast"},
+                         "interfaces": [interface(json!({}))]})
+            )
+            .contains("lacp_rate")
+        );
+        assert!(
+            error(json!({"interfaces": [interface(json!({"mtu": 9000}))]}))
+                .contains("larger than the bond mtu")
+        );
+        assert!(
+            error(json!({"interfaces": [interface(json!({})), interface(json!({}))]}))
+                .contains("more than once")
+        );
+        assert!(
+            error(json!({"interfaces": [interface(json!({"routes": [
+                {"destination": "10.0.0.0/8", "gateway": "2001:db8::1"}
+            ]}))]}))
+            .contains("another address family")
+        );
+    }
+}
@@ -0,0 +1,587 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 网卡选择规则：主机发现和装机后收集网卡信息，按主机或主机组的规则选出做绑定的网卡
+use actix_web::{HttpResponse, Responder, web};
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::command_execute::{CommandOutput, SshTarget};
+use crate::executor::CommandExecutor;
+
+// 收集所有物理网卡的信息，每行一块网卡，字段以制表符分隔：
+// 名称、MAC 地址、驱动、速率（Mb/s）、PCI 地址、端口类型、LLDP 对端交换机名称和端口
+pub const COLLECT_COMMAND: &str = r#"for dev in /sys/class/net/*; do
+  [ -e "$dev/device" ] || continue
+  nic=${dev##*/}
+  driver=$(basename "$(readlink "$dev/device/driver")")
+  speed=$(cat "$dev/speed" 2>/dev/null)
+  pci=$(basename "$(readlink -f "$dev/device")")
+  port=$(ethtool "$nic" 2>/dev/null | awk -F': ' '/Port:/ {print $2; exit}')
+  lldp=$(lldpcli show neighbors ports "$nic" -f keyvalue 2>/dev/null)
+  switch=$(echo "$lldp" | awk -F= '/\.chassis\.name=/ {print $2; exit}')
+  switch_port=$(echo "$lldp" | awk -F= '/\.port\.ifname=/ {print $2; exit}')
+  printf '%s\t%s\t%s\t%s\t%s\t%s\t%s\t%s\n' "$nic" "$(cat "$dev/address")" "$driver" "$speed" "$pci" "$port" "$switch" "$switch_port"
+done
+"#;
+
+// 没有配置规则时的默认规则：选 2 个光口
+const DEFAULT_PORT: &str = "FIBRE";
+const DEFAULT_COUNT: usize = 2;
+
+// 一块网卡收集到的信息
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NicFacts {
+    pub name: String,
+    pub mac_address: String,
+    pub driver: Option<String>,
+    // 未连接时读不到速率
+    pub speed: Option<u32>,
+    pub pci_address: Option<String>,
+    // ethtool 显示的端口类型，例如 FIBRE
+    pub port: Option<String>,
+    pub lldp_system: Option<String>,
+    pub lldp_port: Option<String>,
+}
+
+// 网卡选择规则：所有设置的条件都满足的网卡参与选择
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NicRule {
+    pub driver: Option<String>,
+    pub port: Option<String>,
+    // 最低速率，单位 Mb/s
+    pub min_speed: Option<u32>,
+    // PCI 地址前缀，例如 0000:3b 或 3b:00
+    pub pci_slot: Option<String>,
+    pub mac_addresses: Option<Vec<String>>,
+    // LLDP 对端交换机名称
+    pub lldp_neighbor: Option<String>,
+    // 选出的网卡数量
+    #[serde(default = "default_count")]
+    pub count: usize,
+}
+
+fn default_count() -> usize {
+    DEFAULT_COUNT
+}
+
+#[derive(Serialize)]
+pub struct NicRuleRecord {
+    id: i64,
+    host_group: Option<String>,
+    serial: Option<String>,
+    #[serde(flatten)]
+    rule: NicRule,
+}
+
+#[derive(Deserialize)]
+pub struct NewNicRule {
+    host_group: Option<String>,
+    serial: Option<String>,
+    #[serde(flatten)]
+    rule: NicRule,
+}
+
+// GET /api/hosts/{serial}/nics 的返回：收集到的网卡、生效的规则和选择结果
+#[derive(Serialize)]
+pub struct NicSelection {
+    collected_at: Option<String>,
+    nics: Vec<NicFacts>,
+    // 生效规则的编号，使用默认规则时为空
+    rule_id: Option<i64>,
+    rule: NicRule,
+    selected: Vec<NicFacts>,
+    error: Option<String>,
+}
+
+impl Default for NicRule {
+    fn default() -> Self {
+        NicRule {
+            driver: None,
+            port: Some(DEFAULT_PORT.to_string()),
+            min_speed: None,
+            pci_slot: None,
+            mac_addresses: None,
+            lldp_neighbor: None,
+            count: DEFAULT_COUNT,
+        }
+    }
+}
+
+impl NicRule {
+    fn validate(&self) -> Result<(), String> {
+        if self.count == 0 {
+            return Err("count must be at least 1".to_string());
+        }
+        for mac in self.mac_addresses.iter().flatten() {
+            let valid = mac.split(':').count() == 6
+                && mac
+                    .split(':')
+                    .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()));
+            if !valid {
+                return Err(format!("invalid MAC address: {mac}"));
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, nic: &NicFacts) -> bool {
+        let pci_address = nic.pci_address.as_deref().unwrap_or_default();
+        self.driver
+            .as_ref()
+            .is_none_or(|driver| nic.driver.as_ref() == Some(driver))
+            && self.port.as_ref().is_none_or(|port| {
+                nic.port
+                    .as_ref()
+                    .is_some_and(|nic_port| nic_port.eq_ignore_ascii_case(port))
+            })
+            && self
+                .min_speed
+                .is_none_or(|min_speed| nic.speed.is_some_and(|speed| speed >= min_speed))
+            && self.pci_slot.as_ref().is_none_or(|slot| {
+                !pci_address.is_empty()
+                    && (pci_address.starts_with(slot.as_str())
+                        || pci_address
+                            .split_once(':')
+                            .is_some_and(|(_, address)| address.starts_with(slot.as_str())))
+            })
+            && self
+                .mac_addresses
+                .as_ref()
+                .is_none_or(|macs| macs.contains(&nic.mac_address))
+            && self
+                .lldp_neighbor
+                .as_ref()
+                .is_none_or(|neighbor| nic.lldp_system.as_ref() == Some(neighbor))
+    }
+
+    // 选出 count 块网卡。匹配的网卡按 PCI 地址排序后轮流从每块网卡设备（同一 PCI 设备的不同端口）取一个口，
+    // 使绑定跨越不同的网卡设备：两块双口网卡时选第 1 和第 3 个口
+    pub fn select<'a>(&self, nics: &'a [NicFacts]) -> Result<Vec<&'a NicFacts>, String> {
+        let mut devices: BTreeMap<(Option<&str>, &str), Vec<&NicFacts>> = BTreeMap::new();
+        for nic in nics.iter().filter(|nic| self.matches(nic)) {
+            // 没有 PCI 地址的网卡各自视为一个设备
+            let device = match nic.pci_address.as_deref() {
+                Some(address) => (
+                    Some(
+                        address
+                            .rsplit_once('.')
+                            .map_or(address, |(device, _)| device),
+                    ),
+                    "",
+                ),
+                None => (None, nic.name.as_str()),
+            };
+            devices.entry(device).or_default().push(nic);
+        }
+        let mut ports: Vec<Vec<&NicFacts>> = devices.into_values().collect();
+        for device_ports in &mut ports {
+            device_ports.sort_by(|a, b| (&a.pci_address, &a.name).cmp(&(&b.pci_address, &b.name)));
+        }
+        let matched: usize = ports.iter().map(Vec::len).sum();
+        if matched < self.count {
+            return Err(format!(
+                "{matched} of {} NICs match the rule, {} needed",
+                nics.len(),
+                self.count
+            ));
+        }
+        let depth = ports.iter().map(Vec::len).max().unwrap_or_default();
+        Ok((0..depth)
+            .flat_map(|i| {
+                ports
+                    .iter()
+                    .filter_map(move |device_ports| device_ports.get(i))
+            })
+            .copied()
+            .take(self.count)
+            .collect())
+    }
+}
+
+fn optional_field(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+// 解析 COLLECT_COMMAND 的输出
+pub fn parse_facts(output: &str) -> Vec<NicFacts> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            // 网卡名称用作装机后网络配置的文件名，忽略含有其他字符的名称
+            let name = optional_field(fields.next()).filter(|name| {
+                name.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+            })?;
+            let mac_address = optional_field(fields.next())?.to_lowercase();
+            Some(NicFacts {
+                name,
+                mac_address,
+                driver: optional_field(fields.next()),
+                speed: fields.next().and_then(|speed| speed.trim().parse().ok()),
+                pci_address: optional_field(fields.next()),
+                port: optional_field(fields.next()),
+                lldp_system: optional_field(fields.next()),
+                lldp_port: optional_field(fields.next()),
+            })
+        })
+        .collect()
+}
+
+// 在主机上收集网卡信息，SSH 失败或没有物理网卡时返回 None
+pub async fn collect<E: CommandExecutor>(
+    executor: &E,
+    target: &SshTarget,
+) -> Option<Vec<NicFacts>> {
+    executor
+        .run_ssh_command(target, COLLECT_COMMAND)
+        .await
+        .ok()
+        .and_then(CommandOutput::into_stdout)
+        .map(|output| parse_facts(&output))
+        .filter(|nics| !nics.is_empty())
+}
+
+// 保存主机最近一次收集到的网卡信息
+pub fn store_facts(conn: &Connection, serial: &str, nics: &[NicFacts], collected_at: &str) {
+    conn.execute("DELETE FROM host_nics WHERE serial = ?1", params![serial])
+        .unwrap();
+    for nic in nics {
+        conn.execute(
+            "INSERT INTO host_nics (serial, name, mac_address, driver, speed, pci_address, port, lldp_system, lldp_port, collected_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                serial,
+                nic.name,
+                nic.mac_address,
+                nic.driver,
+                nic.speed,
+                nic.pci_address,
+                nic.port,
+                nic.lldp_system,
+                nic.lldp_port,
+                collected_at
+            ],
+        )
+        .unwrap();
+    }
+}
+
+// 读取保存的网卡信息和收集时间
+pub fn load_facts(conn: &Connection, serial: &str) -> (Vec<NicFacts>, Option<String>) {
+    let mut stmt = conn
+        .prepare(
+            "SELECT name, mac_address, driver, speed, pci_address, port, lldp_system, lldp_port, collected_at FROM host_nics WHERE serial = ?1 ORDER BY name",
+        )
+        .unwrap();
+    let mut collected_at = None;
+    let nics = stmt
+        .query_map(params![serial], |row| {
+            Ok((
+                NicFacts {
+                    name: row.get(0)?,
+                    mac_address: row.get(1)?,
+                    driver: row.get(2)?,
+                    speed: row.get(3)?,
+                    pci_address: row.get(4)?,
+                    port: row.get(5)?,
+                    lldp_system: row.get(6)?,
+                    lldp_port: row.get(7)?,
+                },
+                row.get::<_, String>(8)?,
+            ))
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|(nic, time)| {
+            collected_at = Some(time);
+            nic
+        })
+        .collect();
+    (nics, collected_at)
+}
+
+fn rule_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<NicRule> {
+    Ok(NicRule {
+        driver: row.get(offset)?,
+        port: row.get(offset + 1)?,
+        min_speed: row.get(offset + 2)?,
+        pci_slot: row.get(offset + 3)?,
+        mac_addresses: row
+            .get::<_, Option<String>>(offset + 4)?
+            .map(|macs| macs.split(',').map(String::from).collect()),
+        lldp_neighbor: row.get(offset + 5)?,
+        count: row.get(offset + 6)?,
+    })
+}
+
+// 选择主机生效的规则：序列号匹配优先于主机组匹配，均未匹配时使用通用规则，数据库中没有时使用默认规则
+pub fn resolve(conn: &Connection, serial: &str) -> (Option<i64>, NicRule) {
+    conn.query_row(
+        r#"
+        SELECT id, driver, port, min_speed, pci_slot, mac_addresses, lldp_neighbor, count
+        FROM nic_rules
+        WHERE (host_group IS NULL OR host_group = (SELECT host_group FROM hosts WHERE serial = ?1))
+          AND (serial IS NULL OR serial = ?1)
+        ORDER BY serial IS NOT NULL DESC, host_group IS NOT NULL DESC
+        LIMIT 1
+        "#,
+        params![serial],
+        |row| Ok((Some(row.get(0)?), rule_from_row(row, 1)?)),
+    )
+    .optional()
+    .unwrap()
+    .unwrap_or_default()
+}
+
+// 按保存的网卡信息和生效的规则为主机选择网卡
+pub fn select_for_host(conn: &Connection, serial: &str) -> Result<Vec<NicFacts>, String> {
+    let (nics, _) = load_facts(conn, serial);
+    if nics.is_empty() {
+        return Err(format!("no NICs collected for host {serial}"));
+    }
+    let (_, rule) = resolve(conn, serial);
+    rule.select(&nics)
+        .map(|selected| selected.into_iter().cloned().collect())
+}
+
+// 处理 GET /api/hosts/{serial}/nics ，预览按当前规则会选出的网卡
+pub async fn get_host_nics(
+    serial: web::Path<String>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let exists: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM hosts WHERE serial = ?1)",
+            params![serial.as_str()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    if !exists {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("host {serial} not found") }));
+    }
+    let (nics, collected_at) = load_facts(&conn, &serial);
+    let (rule_id, rule) = resolve(&conn, &serial);
+    let (selected, error) = match rule.select(&nics) {
+        Ok(selected) => (selected.into_iter().cloned().collect(), None),
+        Err(e) => (Vec::new(), Some(e)),
+    };
+    HttpResponse::Ok().json(NicSelection {
+        collected_at,
+        nics,
+        rule_id,
+        rule,
+        selected,
+        error,
+    })
+}
+
+// 处理 GET /api/nic-rules
+pub async fn list_nic_rules(db_pool: web::Data<Pool<SqliteConnectionManager>>) -> impl Responder {
+    let conn = db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, host_group, serial, driver, port, min_speed, pci_slot, mac_addresses, lldp_neighbor, count FROM nic_rules ORDER BY id",
+        )
+        .unwrap();
+    let records: Vec<NicRuleRecord> = stmt
+        .query_map([], |row| {
+            Ok(NicRuleRecord {
+                id: row.get(0)?,
+                host_group: row.get(1)?,
+                serial: row.get(2)?,
+                rule: rule_from_row(row, 3)?,
+            })
+        })
+        .unwrap()
+        .filter_map(Result::ok)
+        .collect();
+    HttpResponse::Ok().json(records)
+}
+
+// 处理 POST /api/nic-rules ，同一主机组和序列号的规则会被替换
+pub async fn create_nic_rule(
+    rule: web::Json<NewNicRule>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let NewNicRule {
+        host_group,
+        serial,
+        mut rule,
+    } = rule.into_inner();
+    // 空字符串视为不限定
+    let host_group = optional_field(host_group.as_deref());
+    let serial = optional_field(serial.as_deref());
+    rule.driver = optional_field(rule.driver.as_deref());
+    rule.port = optional_field(rule.port.as_deref());
+    rule.pci_slot = optional_field(rule.pci_slot.as_deref()).map(|slot| slot.to_lowercase());
+    rule.lldp_neighbor = optional_field(rule.lldp_neighbor.as_deref());
+    rule.mac_addresses = rule.mac_addresses.map(|macs| {
+        macs.iter()
+            .map(|mac| mac.trim().to_lowercase())
+            .collect::<Vec<_>>()
+    });
+    if let Err(e) = rule.validate() {
+        return HttpResponse::BadRequest().json(json!({ "error": e }));
+    }
+    let conn = db_pool.get().unwrap();
+    conn.execute(
+        "DELETE FROM nic_rules WHERE host_group IS ?1 AND serial IS ?2",
+        params![host_group, serial],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO nic_rules (host_group, serial, driver, port, min_speed, pci_slot, mac_addresses, lldp_neighbor, count) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            host_group,
+            serial,
+            rule.driver,
+            rule.port,
+            rule.min_speed,
+            rule.pci_slot,
+            rule.mac_addresses.as_ref().map(|macs| macs.join(",")),
+            rule.lldp_neighbor,
+            rule.count
+        ],
+    )
+    .unwrap();
+    let id = conn.last_insert_rowid();
+    println!(
+        "[INFO] NIC rule {id} stored (host group: {}, serial: {})",
+        host_group.as_deref().unwrap_or("*"),
+        serial.as_deref().unwrap_or("*")
+    );
+    HttpResponse::Created().json(json!({ "id": id }))
+}
+
+// 处理 DELETE /api/nic-rules/{id}
+pub async fn delete_nic_rule(
+    id: web::Path<i64>,
+    db_pool: web::Data<Pool<SqliteConnectionManager>>,
+) -> impl Responder {
+    let id = id.into_inner();
+    let conn = db_pool.get().unwrap();
+    let deleted = conn
+        .execute("DELETE FROM nic_rules WHERE id = ?1", params![id])
+        .unwrap();
+    if deleted == 0 {
+        return HttpResponse::NotFound()
+            .json(json!({ "error": format!("NIC rule {id} not found") }));
+    }
+    println!("[INFO] NIC rule {id} deleted");
+    HttpResponse::NoContent().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+
+    const FACTS: &str = "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\tsw-a\tEth1/1\n\
+                         ens1f1\t52:54:00:00:00:02\tixgbe\t10000\t0000:3b:00.1\tFIBRE\tsw-b\tEth1/1\n\
+                         ens2f0\t52:54:00:00:00:03\tice\t25000\t0000:5e:00.0\tFIBRE\tsw-a\tEth1/2\n\
+                         ens2f1\t52:54:00:00:00:04\tice\t\t0000:5e:00.1\tFIBRE\t\t\n\
+                         eno1\t52:54:00:00:00:05\ttg3\t1000\t0000:01:00.0\tTwisted Pair\t\t\n";
+
+    fn names(selected: Vec<&NicFacts>) -> Vec<&str> {
+        selected.iter().map(|nic| nic.name.as_str()).collect()
+    }
+
+    #[test]
+    fn default_rule_spreads_across_cards() {
+        let nics = parse_facts(FACTS);
+        assert_eq!(nics.len(), 5);
+        assert_eq!(nics[3].speed, None);
+        assert_eq!(nics[4].port.as_deref(), Some("Twisted Pair"));
+        let rule = NicRule::default();
+        assert_eq!(names(rule.select(&nics).unwrap()), ["ens1f0", "ens2f0"]);
+        assert_eq!(
+            names(rule.select(&nics[..2]).unwrap()),
+            ["ens1f0", "ens1f1"]
+        );
+        assert!(rule.select(&nics[3..]).is_err());
+    }
+
+    #[test]
+    fn rules_filter_by_facts() {
+        let nics = parse_facts(FACTS);
+        let rule = |f: fn(&mut NicRule)| {
+            let mut rule = NicRule {
+                port: None,
+                ..NicRule::default()
+            };
+            f(&mut rule);
+            rule
+        };
+        let select = |rule: NicRule| names(rule.select(&nics).unwrap_or_default());
+        assert_eq!(
+            select(rule(|r| r.driver = Some("ixgbe".into()))),
+            ["ens1f0", "ens1f1"]
+        );
+        assert_eq!(
+            select(rule(|r| r.min_speed = Some(10000))),
+            ["ens1f0", "ens2f0"]
+        );
+        assert_eq!(
+            select(rule(|r| r.pci_slot = Some("5e:00".into()))),
+            ["ens2f0", "ens2f1"]
+        );
+        assert_eq!(
+            select(rule(|r| {
+                r.mac_addresses = Some(vec!["52:54:00:00:00:04".into(), "52:54:00:00:00:05".into()])
+            })),
+            ["eno1", "ens2f1"]
+        );
+        assert_eq!(
+            select(rule(|r| r.lldp_neighbor = Some("sw-a".into()))),
+            ["ens1f0", "ens2f0"]
+        );
+        assert!(select(rule(|r| r.lldp_neighbor = Some("sw-b".into()))).is_empty());
+    }
+
+    #[test]
+    fn serial_rule_overrides_group_rule() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, host_group) VALUES ('S1', 'rack-a'), ('S2', 'rack-a'), ('S3', NULL)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO nic_rules (host_group, serial, driver, count) VALUES ('rack-a', NULL, 'ice', 2), (NULL, 'S1', 'ixgbe', 2)",
+            [],
+        )
+        .unwrap();
+        assert_eq!(resolve(&conn, "S1").1.driver.as_deref(), Some("ixgbe"));
+        assert_eq!(resolve(&conn, "S2").1.driver.as_deref(), Some("ice"));
+        assert_eq!(resolve(&conn, "S3"), (None, NicRule::default()));
+
+        store_facts(&conn, "S2", &parse_facts(FACTS), "2025-01-01 00:00:00");
+        let selected = select_for_host(&conn, "S2").unwrap();
+        assert_eq!(selected[1].name, "ens2f1");
+        assert!(select_for_host(&conn, "S3").is_err());
+    }
+}
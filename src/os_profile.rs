@@ -0,0 +1,216 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 操作系统配置档：发行版使用的安装程序、需要追加的内核参数和内置的应答文件模板。
+// 登记操作系统时选择配置档，iPXE 脚本中的 {{ installer_args }} 和应答文件模板据此生成
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::installer::InstallerFamily;
+use crate::network_profile::NetworkFormat;
+use crate::template::Vars;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OsProfile {
+    Kylin,
+    OpenEuler,
+    Sles,
+    Debian,
+    Ubuntu,
+}
+
+// ipxe 表中一个操作系统的安装设置
+pub struct OsSettings {
+    pub installer: InstallerFamily,
+    pub profile: Option<OsProfile>,
+    // 安装源地址，模板中为 {{ repo_url }}
+    pub repo_url: Option<String>,
+    // 装机后网络配置文件的格式
+    pub network_format: NetworkFormat,
+}
+
+impl OsProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OsProfile::Kylin => "kylin",
+            OsProfile::OpenEuler => "openeuler",
+            OsProfile::Sles => "sles",
+            OsProfile::Debian => "debian",
+            OsProfile::Ubuntu => "ubuntu",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "kylin" => Some(OsProfile::Kylin),
+            "openeuler" => Some(OsProfile::OpenEuler),
+            "sles" => Some(OsProfile::Sles),
+            "debian" => Some(OsProfile::Debian),
+            "ubuntu" => Some(OsProfile::Ubuntu),
+            _ => None,
+        }
+    }
+
+    pub fn installer(&self) -> InstallerFamily {
+        match self {
+            OsProfile::Kylin | OsProfile::OpenEuler => InstallerFamily::Anaconda,
+            OsProfile::Sles => InstallerFamily::Autoyast,
+            OsProfile::Debian => InstallerFamily::DebianInstaller,
+            OsProfile::Ubuntu => InstallerFamily::Subiquity,
+        }
+    }
+
+    // 在安装程序的参数之外追加的内核参数模板。麒麟的 anaconda 仍需要 ksdevice ，Ubuntu 通过 url 下载安装镜像
+    fn extra_kernel_args(&self) -> &'static str {
+        match self {
+            OsProfile::Kylin => "inst.repo={{ repo_url }} inst.sshd inst.text ksdevice=bootif",
+            OsProfile::OpenEuler => "inst.repo={{ repo_url }} inst.sshd inst.text",
+            OsProfile::Sles => "install={{ repo_url }} textmode=1",
+            OsProfile::Debian => "netcfg/choose_interface=auto",
+            OsProfile::Ubuntu => "ip=dhcp url={{ repo_url }}",
+        }
+    }
+
+    // 版本中没有导入应答文件模板时使用的内置模板，Debian 和 Ubuntu 需要自行导入
+    pub fn builtin_answer_template(&self) -> Option<&'static str> {
+        match self {
+            OsProfile::Kylin | OsProfile::OpenEuler => {
+                Some(include_str!("../samples/ks-install.cfg"))
+            }
+            OsProfile::Sles => Some(include_str!("../samples/autoyast.xml")),
+            OsProfile::Debian | OsProfile::Ubuntu => None,
+        }
+    }
+}
+
+impl fmt::Display for OsProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// 检查配置档与安装程序一致
+pub fn check_profile(installer: InstallerFamily, profile: Option<OsProfile>) -> Result<(), String> {
+    match profile {
+        Some(profile) if profile.installer() != installer => Err(format!(
+            "profile {profile} uses installer {}, not {installer}",
+            profile.installer()
+        )),
+        _ => Ok(()),
+    }
+}
+
+// 装机脚本中 {{ installer_args }} 的模板：安装程序获取应答文件的参数，加上配置档追加的参数
+pub fn kernel_args(installer: InstallerFamily, profile: Option<OsProfile>) -> String {
+    match profile {
+        Some(profile) => format!(
+            "{} {}",
+            installer.kernel_args(),
+            profile.extra_kernel_args()
+        ),
+        None => installer.kernel_args().to_string(),
+    }
+}
+
+// 读取操作系统的安装设置，未登记的操作系统返回 None
+pub fn os_settings(conn: &Connection, os: &str) -> Option<OsSettings> {
+    conn.query_row(
+        "SELECT installer, profile, repo_url, network_format FROM ipxe WHERE os = ?1",
+        params![os],
+        |row| {
+            Ok(OsSettings {
+                installer: row
+                    .get::<_, Option<String>>(0)?
+                    .as_deref()
+                    .and_then(InstallerFamily::parse)
+                    .unwrap_or_default(),
+                profile: row
+                    .get::<_, Option<String>>(1)?
+                    .as_deref()
+                    .and_then(OsProfile::parse),
+                repo_url: row.get(2)?,
+                network_format: row
+                    .get::<_, Option<String>>(3)?
+                    .as_deref()
+                    .and_then(NetworkFormat::parse)
+                    .unwrap_or_default(),
+            })
+        },
+    )
+    .optional()
+    .unwrap()
+}
+
+// 操作系统的 repo_url 覆盖同名的全局变量
+pub fn insert_os_vars(conn: &Connection, os: &str, vars: &mut Vars) {
+    if let Some(repo_url) = os_settings(conn, os).and_then(|settings| settings.repo_url) {
+        vars.insert("repo_url".to_string(), repo_url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::install_state::Progress;
+    use crate::template;
+
+    #[test]
+    fn profiles_extend_installer_args() {
+        assert_eq!(
+            kernel_args(InstallerFamily::Autoyast, Some(OsProfile::Sles)),
+            "autoyast={{ answer_url }} install={{ repo_url }} textmode=1"
+        );
+        assert_eq!(
+            kernel_args(InstallerFamily::Anaconda, None),
+            "inst.ks={{ answer_url }}"
+        );
+        assert!(check_profile(InstallerFamily::Anaconda, Some(OsProfile::OpenEuler)).is_ok());
+        assert!(check_profile(InstallerFamily::Anaconda, Some(OsProfile::Sles)).is_err());
+    }
+
+    #[test]
+    fn builtin_templates_are_valid() {
+        for profile in [
+            OsProfile::Kylin,
+            OsProfile::OpenEuler,
+            OsProfile::Sles,
+            OsProfile::Debian,
+            OsProfile::Ubuntu,
+        ] {
+            if let Some(answer_template) = profile.builtin_answer_template() {
+                template::variables(answer_template).unwrap();
+                profile
+                    .installer()
+                    .validate_answer_template(profile.as_str(), answer_template)
+                    .unwrap();
+                // 装好的系统首次启动后上报 85 ，否则主机停在 80 。AutoYaST 在 init-scripts 中上报
+                let report = format!("progress={}", Progress::RebootedToSystem as i32);
+                let firstboot = match profile.installer() {
+                    InstallerFamily::Autoyast => answer_template
+                        .split_once("<init-scripts")
+                        .and_then(|(_, rest)| rest.split_once("</init-scripts>"))
+                        .is_some_and(|(scripts, _)| {
+                            scripts.contains(&report) || scripts.contains("{{ firstboot_command }}")
+                        }),
+                    _ => answer_template.contains(&report),
+                };
+                assert!(firstboot, "{profile} has no hook reporting 85");
+            }
+        }
+    }
+}
@@ -14,290 +14,802 @@
  * limitations under the License.
 */
 
-// 主机装机进度控制代码：这段代码用于监控主机上 /tmp/install-progress.ack 文件并做相应的处理
-use chrono::Utc;
+// 主机装机进度控制代码：这段代码根据数据库中的安装进度启动安装、重启主机并处理装机后的配置
+use chrono::{Local, NaiveDateTime, Utc};
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::params;
-use tokio::process::Command;
 use tokio::time::Duration;
 
-use crate::command_execute::run_ssh_command_on_host;
+use crate::bmc::BmcTarget;
+use crate::command_execute::{CommandOutput, SshTarget};
+use crate::config::ProgressConfig;
+use crate::credentials::InstallPhase;
+use crate::executor::CommandExecutor;
+use crate::host_keys;
+use crate::install_state::{Progress, TransitionSource, set_install_progress};
+use crate::network_apply::{self, ApplyState};
+use crate::network_profile;
+use crate::nic_rules;
+use crate::os_profile::os_settings;
 
 struct Host {
     ip_address: String,
-    hostname: String,
-    public_ip_addr: String,
-    ipmi_address: String,
-    vlan_id: u32,
+    hostname: Option<String>,
+    ipmi_address: Option<String>,
+    serial: String,
+    os: String,
 }
 
-pub enum Progress {
-    NotConfigured = 0,
-    RebootingToKickstart = 5,
-    KickstartLoaded = 10,
-    PreInstallFinished = 20,
-    PostInstallFinished = 60,
-    InstallFinished = 80,
-    RebootedToSystem = 85,
+impl Host {
+    fn ssh_target(&self, phase: InstallPhase) -> SshTarget {
+        SshTarget::new(&self.ip_address, phase, Some(&self.os), Some(&self.serial))
+    }
+
+    fn ipmi(&self) -> &str {
+        self.ipmi_address.as_deref().unwrap_or("unknown")
+    }
+}
+
+// 收集查询到的主机，读取失败的记录输出日志后跳过
+fn collect_hosts<T>(rows: impl Iterator<Item = rusqlite::Result<T>>) -> Vec<T> {
+    rows.filter_map(|row| {
+        row.map_err(|e| println!("[WARN] Skipping host record: {e}"))
+            .ok()
+    })
+    .collect()
 }
 
-// 将所有尚未开始安装但是配置了操作系统的机器，安装进度置为正在重启到kickstart
-async fn start_kickstart_installation(db_pool: Pool<SqliteConnectionManager>) {
+// 将装机队列中尚未开始安装或要求重装、且配置了操作系统的机器，安装进度置为正在重启到kickstart
+async fn start_kickstart_installation<E: CommandExecutor>(
+    executor: &E,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
     let db_pool_clone = db_pool.clone();
     let hosts_to_process = tokio::task::spawn_blocking(move || {
         let conn = db_pool.get().unwrap();
         // 查询 install_queue 表，并与 hosts 表进行 LEFT JOIN
-        // 同时在 SQL 查询中检查 ipxe 表是否存在相应的脚本
+        // 同时在 SQL 查询中检查操作系统是否有启用的 iPXE 脚本版本
         let mut stmt = conn
             .prepare(
                 r#"
                 SELECT
                     h.ip_address,
                     h.hostname,
-                    h.public_ip_addr,
-                    h.vlan_id,
-                    iq.ipmi_address
+                    iq.ipmi_address,
+                    h.serial,
+                    h.os,
+                    h.install_progress
                 FROM install_queue iq
                 LEFT JOIN hosts h ON iq.ipmi_address = h.ipmi_address
-                WHERE h.install_progress = ?1
+                WHERE h.install_progress IN (?1, ?2)
                   AND h.os IS NOT NULL
-                  AND EXISTS (SELECT 1 FROM ipxe WHERE os = h.os AND script IS NOT NULL)
+                  AND EXISTS (SELECT 1 FROM ipxe WHERE os = h.os AND active_version IS NOT NULL)
                 "#,
             )
             .unwrap();
         let host_iter = stmt
-            .query_map(params![Progress::NotConfigured as i32], |row| {
-                Ok(Host {
-                    ip_address: row.get(0)?,
-                    hostname: row.get(1)?,
-                    public_ip_addr: row.get(2)?,
-                    vlan_id: row.get(3)?,
-                    ipmi_address: row.get(4)?, // 获取 ipmi_address
-                })
-            })
+            .query_map(
+                params![Progress::NotConfigured as i32, Progress::Installed as i32],
+                |row| {
+                    Ok((
+                        Host {
+                            ip_address: row.get(0)?,
+                            hostname: row.get(1)?,
+                            ipmi_address: row.get(2)?, // 获取 ipmi_address
+                            serial: row.get(3)?,
+                            os: row.get(4)?,
+                        },
+                        row.get::<_, i32>(5)?,
+                    ))
+                },
+            )
             .unwrap();
-        let hosts: Vec<Host> = host_iter.filter_map(Result::ok).collect();
-        hosts
+        collect_hosts(host_iter)
     })
     .await
     .expect("Failed to get hosts from database");
-    for host in hosts_to_process {
-        // 更新主机状态到 RebootingToKickstart
-        run_ssh_command_on_host(
-            &host.ip_address,
-            &format!(
-                "echo \"{}\" >/tmp/install-progress",
-                Progress::RebootingToKickstart as i32
-            ),
-        )
-        .await;
+    for (host, progress) in hosts_to_process {
+        // 由服务端把安装进度置为 RebootingToKickstart ，不依赖主机上的 SSH 。
+        // 排队重装的已安装主机先回到 NotConfigured
+        let conn = db_pool_clone.get().unwrap();
+        let reinstall = if progress == Progress::Installed as i32 {
+            set_install_progress(
+                &conn,
+                &host.serial,
+                Progress::NotConfigured,
+                TransitionSource::Server,
+            )
+        } else {
+            Ok(true)
+        };
+        if let Err(e) = reinstall.and_then(|_| {
+            set_install_progress(
+                &conn,
+                &host.serial,
+                Progress::RebootingToKickstart,
+                TransitionSource::Server,
+            )
+        }) {
+            println!(
+                "[WARN] Cannot start installation of host {} (IPMI: {}): {e}",
+                host.ip_address,
+                host.ipmi()
+            );
+            continue;
+        }
         println!(
             "[INFO] Setting host {} (IPMI: {}) install progress to: RebootingToKickstart",
-            host.ip_address, host.ipmi_address
+            host.ip_address,
+            host.ipmi()
         );
-        // SSH 命令成功后，删除 install_queue 中的 ipmi_address
-        let ipmi_address = host.ipmi_address.clone();
-        let conn = db_pool_clone.get().unwrap();
+        // 删除 install_queue 中的 ipmi_address ，记录安装次数，并等待重启
         conn.execute(
             "DELETE FROM install_queue WHERE ipmi_address = ?1",
-            params![ipmi_address],
+            params![host.ipmi_address],
         )
         .unwrap();
+        conn.execute(
+            "UPDATE hosts SET install_attempts = install_attempts + 1, kickstart_rebooted = NULL WHERE serial = ?1",
+            params![host.serial],
+        )
+        .unwrap();
+        // 重装后的操作系统会生成新的主机密钥
+        host_keys::reset(&conn, &host.serial, Some(InstallPhase::System));
+        drop(conn);
+        // 同步主机上的进度文件，避免之后的主机发现把旧的进度报告回来；主机不可达时不影响重启
+        executor
+            .run_ssh_command(
+                &host.ssh_target(InstallPhase::Installer),
+                &format!(
+                    "echo \"{}\" >/tmp/install-progress",
+                    Progress::RebootingToKickstart as i32
+                ),
+            )
+            .await
+            .ok();
     }
 }
 
-// 将所有安装进度为 RebootingToKickstart 的主机重启到 kickstart
-async fn reboot_host_to_kickstart(db_pool: Pool<SqliteConnectionManager>) {
+// 重启主机进入 PXE ：优先通过 BMC 带外重启，只有 BMC 不可用时才通过 SSH 在主机上执行带内 ipmitool
+async fn reboot_to_pxe<E: CommandExecutor>(
+    executor: &E,
+    bmc: &BmcTarget,
+    ssh: Option<&SshTarget>,
+) -> Result<(), String> {
+    let e = match executor.bmc_pxe_reboot(bmc).await {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    let Some(ssh) = ssh.filter(|_| e.can_fall_back()) else {
+        return Err(e.to_string());
+    };
+    println!("[WARN] {e}, rebooting {} in-band instead", ssh.ip_address);
+    executor
+        .ipmi_pxe_reboot(ssh, bmc.boot_mode)
+        .await
+        .map_err(|ssh_error| format!("{e}; in-band reboot failed: {ssh_error:?}"))
+}
+
+// 将所有安装进度为 RebootingToKickstart 且尚未重启的主机重启到 kickstart 。重启通过 BMC 完成，
+// 不等待主机确认；重启失败的主机在下一轮重试
+async fn reboot_host_to_kickstart<E: CommandExecutor>(
+    executor: &E,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
+    let db_pool_clone = db_pool.clone();
     // 将数据库操作移动到阻塞线程
     let hosts: Vec<Host> = tokio::task::spawn_blocking(move || {
         let conn = db_pool.get().unwrap();
         let mut stmt = conn
             .prepare(
-                "SELECT ip_address, hostname, public_ip_addr, vlan_id, ipmi_address FROM hosts WHERE install_progress = ?1 AND os IS NOT NULL",
+                "SELECT ip_address, hostname, ipmi_address, serial, os FROM hosts WHERE install_progress = ?1 AND os IS NOT NULL AND kickstart_rebooted IS NULL",
             )
             .unwrap();
-        stmt.query_map(params![Progress::RebootingToKickstart as i32], |row| {
-            Ok(Host {
-                ip_address: row.get(0)?,
-                hostname: row.get(1)?,
-                public_ip_addr: row.get(2)?,
-                vlan_id: row.get(3)?,
-                ipmi_address: row.get(4)?,
+        collect_hosts(
+            stmt.query_map(params![Progress::RebootingToKickstart as i32], |row| {
+                Ok(Host {
+                    ip_address: row.get(0)?,
+                    hostname: row.get(1)?,
+                    ipmi_address: row.get(2)?,
+                    serial: row.get(3)?,
+                    os: row.get(4)?,
+                })
             })
-        })
-        .unwrap()
-        .filter_map(Result::ok)
-        .collect()
+            .unwrap(),
+        )
     })
     .await
     .unwrap();
-    // 检查每个主机的 /tmp/install-progress.ack 文件是否为 RebootingToKickstart
     for host in hosts {
-        let progress_on_host =
-            run_ssh_command_on_host(&host.ip_address, &format!("cat /tmp/install-progress.ack"))
-                .await
-                .unwrap_or("".to_string());
-        if progress_on_host.trim() == (Progress::RebootingToKickstart as i32).to_string() {
-            run_ssh_command_on_host(
-                &host.ip_address,
-                "ipmitool chassis bootdev pxe options=efiboot;/sbin/reboot",
-            )
-            .await;
-            println!("[INFO] Rebooting host: {}", host.ip_address);
+        let target = host.ssh_target(InstallPhase::Installer);
+        let bmc = BmcTarget::lookup(
+            &db_pool_clone.get().unwrap(),
+            host.ipmi_address.as_deref().unwrap_or_default(),
+        );
+        if let Err(e) = reboot_to_pxe(executor, &bmc, Some(&target)).await {
+            println!(
+                "[ERROR] Reboot host {} (IPMI: {}) failed: {e}",
+                host.ip_address,
+                host.ipmi()
+            );
+            continue;
         }
+        println!("[INFO] Rebooting host: {}", host.ip_address);
+        let conn = db_pool_clone.get().unwrap();
+        conn.execute(
+            "UPDATE hosts SET kickstart_rebooted = ?1 WHERE serial = ?2",
+            params![
+                Local::now()
+                    .naive_local()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                host.serial
+            ],
+        )
+        .unwrap();
+        // kickstart 安装程序会生成新的主机密钥
+        host_keys::reset(&conn, &host.serial, Some(InstallPhase::Installer));
     }
 }
 
 // 将已经装好重启完毕的机器配置主机名和网络
-async fn configure_host_after_installation(db_pool: Pool<SqliteConnectionManager>) {
+async fn configure_host_after_installation<E: CommandExecutor>(
+    executor: &E,
+    config: &ProgressConfig,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
     let db_pool_clone = db_pool.clone();
     // 将数据库操作移动到阻塞线程
     let hosts: Vec<Host> = tokio::task::spawn_blocking(move || {
         let conn = db_pool.get().unwrap();
         let mut stmt = conn
             .prepare(
-                "SELECT ip_address, hostname, public_ip_addr, vlan_id, ipmi_address FROM hosts WHERE install_progress = ?1 AND os IS NOT NULL",
+                "SELECT ip_address, hostname, ipmi_address, serial, os FROM hosts WHERE install_progress = ?1 AND os IS NOT NULL",
             )
             .unwrap();
-        stmt.query_map(params![Progress::RebootedToSystem as i32], |row| {
-            Ok(Host {
-                ip_address: row.get(0)?,
-                hostname: row.get(1)?,
-                public_ip_addr: row.get(2)?,
-                vlan_id: row.get(3)?,
-                ipmi_address: row.get(4)?,
+        collect_hosts(
+            stmt.query_map(params![Progress::RebootedToSystem as i32], |row| {
+                Ok(Host {
+                    ip_address: row.get(0)?,
+                    hostname: row.get(1)?,
+                    ipmi_address: row.get(2)?,
+                    serial: row.get(3)?,
+                    os: row.get(4)?,
+                })
             })
-        })
-        .unwrap()
-        .filter_map(Result::ok)
-        .collect()
+            .unwrap(),
+        )
     })
     .await
     .unwrap();
     // 对所有的机器进行网络配置
     for host in hosts {
-        // 获取主机所有网卡
-        let nics = run_ssh_command_on_host(
-            &host.ip_address,
-            r#"
-            for dev in /sys/class/net/*/uevent; do
-                nic=$(cat ${dev} | grep INTERFACE | awk -F'=' '{print $2}')
-                port=$(ethtool ${nic} | awk '/Port/ {print$NF}')
-                link=$(ethtool ${nic} | awk '/Link/ {print$NF}')
-                [[ "$port" == "FIBRE" ]] && [[ "$nic" != "lo" ]] && echo "$nic"
-            done
-            echo
-            "#,
-        )
-        .await;
+        let Some(hostname) = &host.hostname else {
+            println!(
+                "[ERROR] Host {} has no hostname, skipping network configuration",
+                host.ip_address
+            );
+            continue;
+        };
+        let (profile, format) = {
+            let conn = db_pool_clone.get().unwrap();
+            (
+                network_profile::host_profile(&conn, &host.serial),
+                os_settings(&conn, &host.os)
+                    .map(|settings| settings.network_format)
+                    .unwrap_or_default(),
+            )
+        };
+        let Some(profile) = profile else {
+            println!(
+                "[ERROR] Host {} has no network profile, public_ip_addr or vlan_id",
+                host.ip_address
+            );
+            continue;
+        };
+        // 应用脚本等待和服务端 ping 的都是网络配置中主接口的地址
+        let Some(address) = profile.address().map(|address| address.to_string()) else {
+            println!(
+                "[ERROR] Host {} has no address on the primary interface",
+                host.ip_address
+            );
+            continue;
+        };
+        let target = host.ssh_target(InstallPhase::System);
+        // 收集主机网卡信息，按生效的规则选出做绑定的网卡
+        let nics = nic_rules::collect(executor, &target).await;
         if let Some(nics) = nics {
-            let hostname = host.hostname;
-            let nic_1 = nics.lines().nth(0).unwrap().trim();
-            let nic_2 = match nics.lines().count() {
-                4 => nics.lines().nth(2).map(|s| s.trim()).unwrap(),
-                2 => nics.lines().nth(1).map(|s| s.trim()).unwrap(),
-                _ => {
+            let (rule_id, rule) = {
+                let conn = db_pool_clone.get().unwrap();
+                let collected_at = Local::now()
+                    .naive_local()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string();
+                nic_rules::store_facts(&conn, &host.serial, &nics, &collected_at);
+                nic_rules::resolve(&conn, &host.serial)
+            };
+            let slaves: Vec<String> = match rule.select(&nics) {
+                Ok(selected) => selected.into_iter().map(|nic| nic.name.clone()).collect(),
+                Err(e) => {
                     println!(
-                        "[ERROR] Unexpected number of NICs for host {}: {}",
+                        "[ERROR] Cannot select NICs for host {} (rule: {}): {e}",
                         host.ip_address,
-                        nics.lines().count()
+                        rule_id.map_or("default".to_string(), |id| id.to_string())
                     );
                     continue;
                 }
             };
-            let public_ip_addr = host.public_ip_addr;
-            let gateway = public_ip_addr
-                .split('.')
-                .take(3)
-                .collect::<Vec<&str>>()
-                .join(".")
-                + ".1";
-            let vlan_id = host.vlan_id;
-            run_ssh_command_on_host(&host.ip_address, &format!("
-                    mkdir -p /tmp/.install
-                    cat >/tmp/.install/network-config.sh <<-'EOF'
-                        #!/bin/bash
-                        hostnamectl set-hostname --static {hostname}
-                        rm -f /etc/sysconfig/network-scripts/ifcfg-*
-                        nmcli -t -f uuid connection show | xargs nmcli connection delete
-                        nmcli connection add type bond ifname bond0 con-name bond0 mode 4 ipv4.method disabled ipv6.method ignore ipv6.addr-gen-mode eui64
-                        nmcli connection add type bond-slave ifname {nic_1} con-name {nic_1} master bond0
-                        nmcli connection add type bond-slave ifname {nic_2} con-name {nic_2} master bond0
-                        nmcli connection up bond0
-                        nmcli con add type vlan ifname bond0.{vlan_id} con-name bond0.{vlan_id} id {vlan_id} dev bond0
-                        nmcli connection modify bond0.{vlan_id} ipv4.method manual ipv4.addresses {public_ip_addr}/24
-                        nmcli connection modify bond0.{vlan_id} ipv4.gateway {gateway}
-                        nmcli connection up bond0.{vlan_id}
-                        nmcli connection reload
-                        ping -c10 {public_ip_addr}
-                        nmcli connection show
-                        cat /proc/net/bonding/bond0 | grep Aggregator
-                    "
-                )).await;
-            run_ssh_command_on_host(&host.ip_address, "sed -i 's/^[[:space:]]*//' /tmp/.install/network-config.sh;chmod +x /tmp/.install/network-config.sh").await;
-            run_ssh_command_on_host(
-                &host.ip_address,
-                "nohup /tmp/.install/network-config.sh &>/tmp/.install/network-config.log &",
-            )
-            .await;
-            println!(
-                "[INFO] Host {} configured with network and hostname.",
-                host.ip_address
+            let push = network_apply::push(
+                format,
+                hostname,
+                &address,
+                &profile,
+                &slaves,
+                config.network_rollback_secs,
             );
-        } else {
-            // ping主机公网IP，如果通，将安装进度设置为安装完成
-            let mut command = Command::new("ping");
-            command
-                .arg("-c")
-                .arg("1")
-                .arg("-W")
-                .arg("1")
-                .arg(&host.public_ip_addr);
-            let result = command.output().await;
-            match result {
-                Ok(output) => {
-                    if output.status.success() {
-                        println!("[INFO] Ping to {} successful!", &host.public_ip_addr);
-                        let conn = db_pool_clone.get().unwrap();
-                        conn.execute(
-                            "UPDATE hosts SET install_progress = ?1 WHERE public_ip_addr = ?2",
-                            params![100, &host.public_ip_addr],
-                        )
-                        .unwrap();
-                    } else {
-                        let stdout = String::from_utf8_lossy(&output.stdout);
-                        let stderr = String::from_utf8_lossy(&output.stderr);
-                        println!(
-                            "[INFO] Ping to {} failed with non-zero exit code. stdout: {}, stderr: {}",
-                            &host.public_ip_addr, stdout, stderr
-                        );
-                    }
+            // 同一版本的配置正在应用、已经生效或已经回滚时不再下发，配置变化后重新下发
+            let result = executor
+                .run_ssh_command(&target, &format!("cat {}", network_apply::RESULT_FILE))
+                .await
+                .ok()
+                .and_then(CommandOutput::into_stdout)
+                .and_then(|output| network_apply::parse_result(&output))
+                .filter(|result| result.version == push.version);
+            match result.map(|result| result.state) {
+                Some(ApplyState::Applying) => {
+                    println!(
+                        "[INFO] Host {} is applying network configuration {}",
+                        host.ip_address, push.version
+                    );
                 }
-                Err(e) => {
-                    println!("[ERROR] Failed to execute ping command: {}", e);
+                Some(ApplyState::Applied) => {
+                    confirm_installed(executor, &db_pool_clone, &host, &address).await;
+                }
+                Some(ApplyState::RolledBack) => {
+                    println!(
+                        "[ERROR] Host {} rolled back network configuration {}: {} did not come up",
+                        host.ip_address, push.version, address
+                    );
+                    let conn = db_pool_clone.get().unwrap();
+                    conn.execute(
+                        "UPDATE hosts SET progress_message = ?1 WHERE serial = ?2",
+                        params![
+                            format!(
+                                "network configuration {} rolled back: {} did not come up within {}s",
+                                push.version, address, config.network_rollback_secs
+                            ),
+                            host.serial
+                        ],
+                    )
+                    .unwrap();
+                }
+                None => {
+                    println!(
+                        "[INFO] Host {} bonding NICs: {}",
+                        host.ip_address,
+                        slaves.join(", ")
+                    );
+                    executor.run_ssh_command(&target, &push.command).await.ok();
+                    println!(
+                        "[INFO] Host {} pushed {} network configuration {}",
+                        host.ip_address,
+                        format.as_str(),
+                        push.version
+                    );
                 }
             }
+        } else {
+            confirm_installed(executor, &db_pool_clone, &host, &address).await;
             println!("[WARN] No NICs found for host: {}", host.ip_address);
         }
     }
 }
 
+// ping主机业务地址，如果通，将安装进度设置为安装完成
+async fn confirm_installed<E: CommandExecutor>(
+    executor: &E,
+    db_pool: &Pool<SqliteConnectionManager>,
+    host: &Host,
+    address: &str,
+) {
+    if executor.ping(address).await {
+        println!("[INFO] Ping to {} successful!", address);
+        let conn = db_pool.get().unwrap();
+        set_install_progress(
+            &conn,
+            &host.serial,
+            Progress::Installed,
+            TransitionSource::Server,
+        )
+        .ok();
+    }
+}
+
+// 正在安装的主机，用于检查阶段超时
+struct InstallingHost {
+    serial: String,
+    ipmi_address: Option<String>,
+    progress: i32,
+    progress_updated: Option<String>,
+    install_attempts: u32,
+}
+
+// 将在某一阶段停留超过超时时间的主机置为 TimedOut ，未达到最多安装次数时重新排队并通过 BMC 断电重启
+async fn handle_stage_timeouts<E: CommandExecutor>(
+    executor: &E,
+    config: &ProgressConfig,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
+    let db_pool_clone = db_pool.clone();
+    // 将数据库操作移动到阻塞线程
+    let hosts: Vec<InstallingHost> = tokio::task::spawn_blocking(move || {
+        let conn = db_pool_clone.get().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT serial, ipmi_address, install_progress, COALESCE(progress_updated, last_updated), install_attempts FROM hosts WHERE serial IS NOT NULL AND install_progress BETWEEN ?1 AND ?2",
+            )
+            .unwrap();
+        collect_hosts(stmt
+            .query_map(
+                params![
+                    Progress::RebootingToKickstart as i32,
+                    Progress::RebootedToSystem as i32
+                ],
+                |row| {
+                    Ok(InstallingHost {
+                        serial: row.get(0)?,
+                        ipmi_address: row.get(1)?,
+                        progress: row.get(2)?,
+                        progress_updated: row.get(3)?,
+                        install_attempts: row.get(4)?,
+                    })
+                },
+            )
+            .unwrap())
+    })
+    .await
+    .unwrap();
+    let now = Local::now().naive_local();
+    for host in hosts {
+        let serial = host.serial;
+        let attempts = host.install_attempts;
+        let Ok(progress) = Progress::try_from(host.progress) else {
+            continue;
+        };
+        let Some(timeout) = config.timeouts.for_stage(progress) else {
+            continue;
+        };
+        let Some(updated) = host
+            .progress_updated
+            .and_then(|updated| NaiveDateTime::parse_from_str(&updated, "%Y-%m-%d %H:%M:%S").ok())
+        else {
+            continue;
+        };
+        let elapsed = now.signed_duration_since(updated).num_seconds();
+        if elapsed < timeout as i64 {
+            continue;
+        }
+        let conn = db_pool.get().unwrap();
+        if set_install_progress(&conn, &serial, Progress::TimedOut, TransitionSource::Server)
+            .is_err()
+        {
+            continue;
+        }
+        println!(
+            "[WARN] Host {serial} timed out at {progress} after {elapsed}s (attempt {attempts}/{})",
+            config.max_attempts
+        );
+        if attempts >= config.max_attempts {
+            println!("[WARN] Host {serial} reached max install attempts, not retrying");
+            continue;
+        }
+        let Some(ipmi_address) = host.ipmi_address else {
+            println!("[WARN] Host {serial} has no IPMI address, cannot retry installation");
+            continue;
+        };
+        // 重新排队，主机从 PXE 启动进入装机环境上报 NotConfigured 后再次开始安装
+        conn.execute(
+            "INSERT OR IGNORE INTO install_queue (ipmi_address) VALUES (?1)",
+            params![ipmi_address],
+        )
+        .unwrap();
+        let bmc = BmcTarget::lookup(&conn, &ipmi_address);
+        // 重新进入的装机环境会生成新的主机密钥
+        host_keys::reset(&conn, &serial, Some(InstallPhase::Installer));
+        drop(conn);
+        match reboot_to_pxe(executor, &bmc, None).await {
+            Ok(()) => {
+                println!("[INFO] Host {serial} (IPMI: {ipmi_address}) re-queued and power cycled")
+            }
+            Err(e) => {
+                println!("[ERROR] Power cycle host {serial} (IPMI: {ipmi_address}) failed: {e}")
+            }
+        }
+    }
+}
+
+// 执行一轮装机进度控制
+pub async fn progress_control_cycle<E: CommandExecutor>(
+    executor: &E,
+    config: &ProgressConfig,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
+    // 处理超时的主机
+    handle_stage_timeouts(executor, config, db_pool.clone()).await;
+    // 将所有满足装机条件的机器状态设置为RebootingToKickstart
+    start_kickstart_installation(executor, db_pool.clone()).await;
+    // 重启所有状态为RebootingToKickstart的机器
+    reboot_host_to_kickstart(executor, db_pool.clone()).await;
+    // 配置所有已经装机完成的机器
+    configure_host_after_installation(executor, config, db_pool).await;
+}
+
 // 持续监控主机状态，并在达到进度时下发操作
-pub async fn progress_control(interval_secs: u64, db_pool: Pool<SqliteConnectionManager>) {
+pub async fn progress_control<E: CommandExecutor>(
+    executor: E,
+    config: ProgressConfig,
+    db_pool: Pool<SqliteConnectionManager>,
+) {
     loop {
         // 记录开始时间
         let start_time = Utc::now();
-        // 将所有满足装机条件的机器状态设置为RebootingToKickstart
-        start_kickstart_installation(db_pool.clone()).await;
-        // 重启所有状态为RebootingToKickstart的机器
-        reboot_host_to_kickstart(db_pool.clone()).await;
-        // 配置所有已经装机完成的机器
-        configure_host_after_installation(db_pool.clone()).await;
+        progress_control_cycle(&executor, &config, db_pool.clone()).await;
         // 如果当前时间与上次检查时间间隔小于指定的间隔，则等待剩余时间
         let elapsed_time = Utc::now().signed_duration_since(start_time).num_seconds();
-        if elapsed_time < interval_secs as i64 {
-            tokio::time::sleep(Duration::from_secs(interval_secs - elapsed_time as u64)).await;
+        if elapsed_time < config.interval_secs as i64 {
+            tokio::time::sleep(Duration::from_secs(
+                config.interval_secs - elapsed_time as u64,
+            ))
+            .await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+    use crate::fake_executor::{FakeExecutor, FakeHost};
+    use crate::network_profile::{NetworkFormat, NetworkProfile};
+
+    fn db_pool_with_host(progress: Progress) -> Pool<SqliteConnectionManager> {
+        // 内存数据库每个连接相互独立，连接池只保留一个连接
+        let db_pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        let conn = db_pool.get().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO hosts (serial, ip_address, ipmi_address, os, hostname, public_ip_addr, vlan_id, install_progress) VALUES ('S1', '10.0.0.5', '10.1.0.5', 'os', 'node-01', '192.168.10.11', 100, ?1)",
+            params![progress as i32],
+        )
+        .unwrap();
+        db_pool
+    }
+
+    fn enqueue(db_pool: &Pool<SqliteConnectionManager>) {
+        let conn = db_pool.get().unwrap();
+        conn.execute("INSERT INTO ipxe (os, active_version) VALUES ('os', 1)", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO install_queue (ipmi_address) VALUES ('10.1.0.5')",
+            [],
+        )
+        .unwrap();
+    }
+
+    fn progress(db_pool: &Pool<SqliteConnectionManager>) -> i32 {
+        db_pool
+            .get()
+            .unwrap()
+            .query_row("SELECT install_progress FROM hosts", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unreachable_host_is_rebooted_through_bmc() {
+        let db_pool = db_pool_with_host(Progress::NotConfigured);
+        enqueue(&db_pool);
+        let executor = FakeExecutor::new();
+        let mut host = FakeHost::new("S1", Some("10.1.0.5"));
+        host.reachable = false;
+        executor.add_host("10.0.0.5", host);
+
+        // 主机上的 SSH 不可用，服务端仍然开始安装并通过 BMC 重启主机
+        start_kickstart_installation(&executor, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::RebootingToKickstart as i32);
+        reboot_host_to_kickstart(&executor, db_pool.clone()).await;
+        assert_eq!(executor.host("10.0.0.5").power_cycles, 1);
+        assert_eq!(executor.host("10.0.0.5").reboots, 1);
+
+        // 已经重启过的主机在进入 kickstart 之前不会再次重启
+        reboot_host_to_kickstart(&executor, db_pool).await;
+        assert_eq!(executor.host("10.0.0.5").power_cycles, 1);
+    }
+
+    #[tokio::test]
+    async fn host_without_network_fields_is_rebooted() {
+        let db_pool = db_pool_with_host(Progress::NotConfigured);
+        db_pool
+            .get()
+            .unwrap()
+            .execute(
+                "UPDATE hosts SET hostname = NULL, public_ip_addr = NULL",
+                [],
+            )
+            .unwrap();
+        enqueue(&db_pool);
+        let executor = FakeExecutor::new();
+        executor.add_host("10.0.0.5", FakeHost::new("S1", Some("10.1.0.5")));
+
+        // 主机名和公网地址只在装机完成后配置网络时使用，缺少时不影响开始安装
+        start_kickstart_installation(&executor, db_pool.clone()).await;
+        reboot_host_to_kickstart(&executor, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::RebootingToKickstart as i32);
+        assert_eq!(executor.host("10.0.0.5").power_cycles, 1);
+    }
+
+    #[tokio::test]
+    async fn queued_installed_host_is_reinstalled() {
+        let db_pool = db_pool_with_host(Progress::Installed);
+        let executor = FakeExecutor::new();
+        let mut host = FakeHost::new("S1", Some("10.1.0.5"));
+        host.phase = InstallPhase::System;
+        executor.add_host("10.0.0.5", host);
+
+        // 不在队列中的已安装主机保持不变
+        start_kickstart_installation(&executor, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::Installed as i32);
+
+        // 操作员将主机加入装机队列后重新安装
+        enqueue(&db_pool);
+        start_kickstart_installation(&executor, db_pool.clone()).await;
+        reboot_host_to_kickstart(&executor, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::RebootingToKickstart as i32);
+        assert_eq!(executor.host("10.0.0.5").power_cycles, 1);
+        let events: Vec<i32> = {
+            let conn = db_pool.get().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT to_progress FROM install_events ORDER BY id")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        assert_eq!(events, [0, 5]);
+    }
+
+    #[tokio::test]
+    async fn too_few_matching_nics_are_not_configured() {
+        let db_pool = db_pool_with_host(Progress::RebootedToSystem);
+        let executor = FakeExecutor::new();
+        let mut host = FakeHost::new("S1", None);
+        host.phase = InstallPhase::System;
+        host.respond(
+            "ethtool",
+            "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\t\t\n\
+             eno1\t52:54:00:00:00:05\ttg3\t1000\t0000:01:00.0\tTwisted Pair\t\t\n",
+        );
+        executor.add_host("10.0.0.5", host);
+        configure_host_after_installation(&executor, &ProgressConfig::default(), db_pool).await;
+        let commands = executor.host("10.0.0.5").commands;
+        assert_eq!(commands.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rolled_back_network_is_not_pushed_again() {
+        let db_pool = db_pool_with_host(Progress::RebootedToSystem);
+        let config = ProgressConfig::default();
+        let executor = FakeExecutor::new();
+        let mut host = FakeHost::new("S1", None);
+        host.phase = InstallPhase::System;
+        host.respond(
+            "ethtool",
+            "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\t\t\n\
+             ens2f0\t52:54:00:00:00:03\tixgbe\t10000\t0000:5e:00.0\tFIBRE\t\t\n",
+        );
+        executor.add_host("10.0.0.5", host);
+        configure_host_after_installation(&executor, &config, db_pool.clone()).await;
+        let commands = executor.host("10.0.0.5").commands;
+        assert_eq!(commands.len(), 3);
+        assert!(commands[2].contains("nohup /tmp/.install/network/apply.sh"));
+
+        // 主机回滚了同一版本的配置，服务端记录原因，不再下发
+        let profile = NetworkProfile::from_host_fields("192.168.10.11", 100).unwrap();
+        let push = network_apply::push(
+            NetworkFormat::Keyfile,
+            "node-01",
+            "192.168.10.11",
+            &profile,
+            &["ens1f0".to_string(), "ens2f0".to_string()],
+            config.network_rollback_secs,
+        );
+        assert_eq!(push.command, commands[2]);
+        executor.with_host("10.0.0.5", |host| {
+            host.set_file(
+                network_apply::RESULT_FILE,
+                &format!("rolled-back {}\n", push.version),
+            )
+        });
+        configure_host_after_installation(&executor, &config, db_pool.clone()).await;
+        assert_eq!(executor.host("10.0.0.5").commands.len(), 5);
+        let message: String = db_pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT progress_message FROM hosts WHERE serial = 'S1'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(message.contains("rolled back"));
+
+        // 更换网络配置文件格式后重新下发
+        db_pool
+            .get()
+            .unwrap()
+            .execute(
+                "INSERT INTO ipxe (os, network_format) VALUES ('os', 'ifcfg')",
+                [],
+            )
+            .unwrap();
+        configure_host_after_installation(&executor, &config, db_pool).await;
+        let commands = executor.host("10.0.0.5").commands;
+        assert_eq!(commands.len(), 8);
+        assert!(commands[7].contains("/staged/ifcfg-bond0.100"));
+    }
+
+    #[tokio::test]
+    async fn network_profile_address_is_confirmed() {
+        let db_pool = db_pool_with_host(Progress::RebootedToSystem);
+        let config = ProgressConfig::default();
+        // 网络配置中的业务地址与 public_ip_addr 不同
+        let profile = NetworkProfile::from_host_fields("192.168.20.7", 200).unwrap();
+        db_pool
+            .get()
+            .unwrap()
+            .execute(
+                "INSERT INTO network_profiles (serial, profile, updated_at) VALUES ('S1', ?1, '2025-01-01 00:00:00')",
+                params![serde_json::to_string(&profile).unwrap()],
+            )
+            .unwrap();
+        let executor = FakeExecutor::new();
+        let mut host = FakeHost::new("S1", None);
+        host.phase = InstallPhase::System;
+        host.respond(
+            "ethtool",
+            "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\t\t\n\
+             ens2f0\t52:54:00:00:00:03\tixgbe\t10000\t0000:5e:00.0\tFIBRE\t\t\n",
+        );
+        executor.add_host("10.0.0.5", host);
+        configure_host_after_installation(&executor, &config, db_pool.clone()).await;
+        let commands = executor.host("10.0.0.5").commands;
+        assert!(commands[2].contains(" 192.168.20.7/"));
+        assert!(!commands[2].contains("192.168.10.11"));
+
+        // 配置生效后 ping 网络配置中的地址确认装机完成
+        let push = network_apply::push(
+            NetworkFormat::Keyfile,
+            "node-01",
+            "192.168.20.7",
+            &profile,
+            &["ens1f0".to_string(), "ens2f0".to_string()],
+            config.network_rollback_secs,
+        );
+        executor.with_host("10.0.0.5", |host| {
+            host.set_file(
+                network_apply::RESULT_FILE,
+                &format!("applied {}\n", push.version),
+            )
+        });
+        executor.set_pingable("192.168.10.11", true);
+        configure_host_after_installation(&executor, &config, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::RebootedToSystem as i32);
+        executor.set_pingable("192.168.20.7", true);
+        configure_host_after_installation(&executor, &config, db_pool.clone()).await;
+        assert_eq!(progress(&db_pool), Progress::Installed as i32);
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// Redfish 客户端：设置一次性启动覆盖、复位主机、读取电源状态和序列号，供不适合使用 IPMI 的 BMC 使用
+use reqwest::{Client, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+use crate::bmc::{BmcCredential, BmcError, PowerState};
+use crate::boot_mode::BootMode;
+
+// 一次性启动覆盖的目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootOverride {
+    Pxe,
+    UefiHttp,
+    Hdd,
+}
+
+impl BootOverride {
+    fn as_redfish(&self) -> &'static str {
+        match self {
+            BootOverride::Pxe => "Pxe",
+            BootOverride::UefiHttp => "UefiHttp",
+            BootOverride::Hdd => "Hdd",
+        }
+    }
+}
+
+// ComputerSystem.Reset 支持的复位类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetType {
+    On,
+    ForceOff,
+    ForceRestart,
+}
+
+impl ResetType {
+    fn as_redfish(&self) -> &'static str {
+        match self {
+            ResetType::On => "On",
+            ResetType::ForceOff => "ForceOff",
+            ResetType::ForceRestart => "ForceRestart",
+        }
+    }
+}
+
+pub struct RedfishClient {
+    base_url: String,
+    credential: BmcCredential,
+    http: Client,
+}
+
+impl RedfishClient {
+    // address 为 BMC 地址时使用 https ，测试中可以传入带 http:// 的完整地址；BMC 通常使用自签名证书，不校验证书
+    pub fn new(
+        address: &str,
+        credential: BmcCredential,
+        timeout_secs: u64,
+    ) -> Result<Self, BmcError> {
+        let base_url = if address.starts_with("http://") || address.starts_with("https://") {
+            address.trim_end_matches('/').to_string()
+        } else {
+            format!("https://{address}")
+        };
+        let http = Client::builder()
+            .danger_accept_invalid_certs(true)
+            .timeout(Duration::from_secs(timeout_secs))
+            .build()
+            .map_err(|e| BmcError::NotConfigured(format!("cannot create HTTP client: {e}")))?;
+        Ok(RedfishClient {
+            base_url,
+            credential,
+            http,
+        })
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Value, BmcError> {
+        let url = format!("{}{path}", self.base_url);
+        let mut request = self
+            .http
+            .request(method.clone(), &url)
+            .basic_auth(&self.credential.username, Some(&self.credential.password));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.map_err(|e| {
+            if e.is_connect() || e.is_timeout() {
+                BmcError::Unreachable(format!("{method} {url}: {e}"))
+            } else {
+                BmcError::Failed(format!("{method} {url}: {e}"))
+            }
+        })?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(BmcError::Failed(format!("{method} {url}: {status} {text}")));
+        }
+        if status == StatusCode::NO_CONTENT || text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&text)
+            .map_err(|e| BmcError::Failed(format!("{method} {url}: invalid JSON: {e}")))
+    }
+
+    // 第一个 ComputerSystem 的路径，例如 /redfish/v1/Systems/1
+    async fn system_path(&self) -> Result<String, BmcError> {
+        let systems = self
+            .request(Method::GET, "/redfish/v1/Systems", None)
+            .await?;
+        systems["Members"][0]["@odata.id"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| BmcError::Failed("no ComputerSystem found".to_string()))
+    }
+
+    async fn system(&self) -> Result<(String, Value), BmcError> {
+        let path = self.system_path().await?;
+        let system = self.request(Method::GET, &path, None).await?;
+        Ok((path, system))
+    }
+
+    // 读取主机序列号
+    pub async fn serial_number(&self) -> Result<String, BmcError> {
+        let (_, system) = self.system().await?;
+        system["SerialNumber"]
+            .as_str()
+            .map(|serial| serial.trim().to_string())
+            .filter(|serial| !serial.is_empty())
+            .ok_or_else(|| BmcError::Failed("ComputerSystem has no SerialNumber".to_string()))
+    }
+
+    pub async fn power_state(&self) -> Result<PowerState, BmcError> {
+        let (_, system) = self.system().await?;
+        match system["PowerState"].as_str() {
+            Some("On") | Some("PoweringOn") => Ok(PowerState::On),
+            Some("Off") | Some("PoweringOff") => Ok(PowerState::Off),
+            other => Err(BmcError::Failed(format!(
+                "unexpected PowerState: {}",
+                other.unwrap_or("none")
+            ))),
+        }
+    }
+
+    // 设置下次启动时一次性以指定模式从指定设备启动
+    pub async fn set_boot_override(
+        &self,
+        target: BootOverride,
+        mode: BootMode,
+    ) -> Result<(), BmcError> {
+        let path = self.system_path().await?;
+        self.request(
+            Method::PATCH,
+            &path,
+            Some(json!({
+                "Boot": {
+                    "BootSourceOverrideEnabled": "Once",
+                    "BootSourceOverrideTarget": target.as_redfish(),
+                    "BootSourceOverrideMode": mode.as_redfish(),
+                }
+            })),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn reset(&self, reset_type: ResetType) -> Result<(), BmcError> {
+        let (path, system) = self.system().await?;
+        let target = system["Actions"]["#ComputerSystem.Reset"]["target"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or(format!("{path}/Actions/ComputerSystem.Reset"));
+        self.request(
+            Method::POST,
+            &target,
+            Some(json!({ "ResetType": reset_type.as_redfish() })),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    // 断电重启，已关机的主机改为开机
+    pub async fn power_cycle(&self) -> Result<(), BmcError> {
+        let reset_type = match self.power_state().await? {
+            PowerState::On => ResetType::ForceRestart,
+            PowerState::Off => ResetType::On,
+        };
+        self.reset(reset_type).await
+    }
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// HTTP 路由：装机网络中的主机只能访问装机接口，管理接口（主机、操作系统、凭据、主机密钥等）
+// 在单独的监听地址上提供，不经过 nginx 暴露给装机网络
+use actix_web::web;
+
+use crate::bmc::{
+    create_bmc_credential, delete_bmc_credential, get_power_state, list_bmc_credentials,
+};
+use crate::boot_mode::get_partitioning;
+use crate::credentials::{create_credential, delete_credential, list_credentials};
+use crate::host_keys::{list_host_key_alerts, list_host_keys, reset_host_keys};
+use crate::hosts_api::{create_host, delete_host, get_host_by_serial, list_hosts, update_host};
+use crate::install_events::{get_host_events, get_install_stats};
+use crate::install_queue::{cancel_install, enqueue_hosts, list_install_queue};
+use crate::installer::{
+    get_autoinstall_meta_data, get_autoinstall_user_data, get_autoyast, get_preseed,
+};
+use crate::installer_api::{ping, report_progress};
+use crate::ipxe_catalog::{create_os, delete_os, export_os_version, get_os, list_os, update_os};
+use crate::ipxe_script::get_ipxe_script;
+use crate::kickstart::get_kickstart;
+use crate::network_profile::{delete_host_network, get_host_network, put_host_network};
+use crate::nic_rules::{create_nic_rule, delete_nic_rule, get_host_nics, list_nic_rules};
+
+// 装机接口：iPXE 脚本、应答文件、进度上报和心跳，监听 server.bind
+pub fn installer(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/ipxe/{serial}", web::get().to(get_ipxe_script))
+        .route("/api/kickstart/{serial}", web::get().to(get_kickstart))
+        .route("/api/preseed/{serial}", web::get().to(get_preseed))
+        .route("/api/autoyast/{serial}", web::get().to(get_autoyast))
+        .route(
+            "/api/autoinstall/{serial}/user-data",
+            web::get().to(get_autoinstall_user_data),
+        )
+        .route(
+            "/api/autoinstall/{serial}/meta-data",
+            web::get().to(get_autoinstall_meta_data),
+        )
+        .route(
+            "/api/partitioning/{serial}",
+            web::get().to(get_partitioning),
+        )
+        .route("/api/ping", web::get().to(ping))
+        .route("/api/progress/{serial}", web::post().to(report_progress));
+}
+
+// 管理接口，监听 server.admin_bind
+pub fn admin(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api/hosts", web::get().to(list_hosts))
+        .route("/api/hosts", web::post().to(create_host))
+        .route("/api/hosts/{serial}", web::get().to(get_host_by_serial))
+        .route("/api/hosts/{serial}", web::patch().to(update_host))
+        .route("/api/hosts/{serial}", web::delete().to(delete_host))
+        .route("/api/hosts/{serial}/events", web::get().to(get_host_events))
+        .route("/api/hosts/{serial}/power", web::get().to(get_power_state))
+        .route("/api/hosts/{serial}/nics", web::get().to(get_host_nics))
+        .route(
+            "/api/hosts/{serial}/network",
+            web::get().to(get_host_network),
+        )
+        .route(
+            "/api/hosts/{serial}/network",
+            web::put().to(put_host_network),
+        )
+        .route(
+            "/api/hosts/{serial}/network",
+            web::delete().to(delete_host_network),
+        )
+        .route("/api/install-stats", web::get().to(get_install_stats))
+        .route("/api/os", web::get().to(list_os))
+        .route("/api/os", web::post().to(create_os))
+        .route("/api/os/{os}", web::get().to(get_os))
+        .route("/api/os/{os}", web::patch().to(update_os))
+        .route("/api/os/{os}", web::delete().to(delete_os))
+        .route(
+            "/api/os/{os}/versions/{version}",
+            web::get().to(export_os_version),
+        )
+        .route("/api/install-queue", web::get().to(list_install_queue))
+        .route("/api/install-queue", web::post().to(enqueue_hosts))
+        .route(
+            "/api/install-queue/{host}",
+            web::delete().to(cancel_install),
+        )
+        .route("/api/credentials", web::get().to(list_credentials))
+        .route("/api/credentials", web::post().to(create_credential))
+        .route("/api/credentials/{id}", web::delete().to(delete_credential))
+        .route("/api/bmc-credentials", web::get().to(list_bmc_credentials))
+        .route(
+            "/api/bmc-credentials",
+            web::post().to(create_bmc_credential),
+        )
+        .route(
+            "/api/bmc-credentials/{id}",
+            web::delete().to(delete_bmc_credential),
+        )
+        .route("/api/nic-rules", web::get().to(list_nic_rules))
+        .route("/api/nic-rules", web::post().to(create_nic_rule))
+        .route("/api/nic-rules/{id}", web::delete().to(delete_nic_rule))
+        .route("/api/host-keys", web::get().to(list_host_keys))
+        .route("/api/host-keys/alerts", web::get().to(list_host_key_alerts))
+        .route("/api/host-keys/{serial}", web::delete().to(reset_host_keys));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database_init::init_db;
+    use actix_web::{App, http::StatusCode, test};
+    use r2d2::Pool;
+    use r2d2_sqlite::SqliteConnectionManager;
+
+    #[actix_web::test]
+    async fn admin_routes_are_not_served_to_installers() {
+        let db_pool = Pool::builder()
+            .max_size(1)
+            .build(SqliteConnectionManager::memory())
+            .unwrap();
+        init_db(&db_pool.get().unwrap());
+        let installer_app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_pool.clone()))
+                .configure(installer),
+        )
+        .await;
+        let admin_app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(db_pool))
+                .configure(admin),
+        )
+        .await;
+        for (method, uri) in [
+            (test::TestRequest::delete(), "/api/host-keys/S1"),
+            (test::TestRequest::get(), "/api/hosts"),
+            (test::TestRequest::get(), "/api/os"),
+            (test::TestRequest::get(), "/api/credentials"),
+            (test::TestRequest::delete(), "/api/credentials/1"),
+            (test::TestRequest::get(), "/api/bmc-credentials"),
+        ] {
+            let response = test::call_service(&installer_app, method.uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        let response = test::call_service(
+            &admin_app,
+            test::TestRequest::get().uri("/api/hosts").to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(
+            &installer_app,
+            test::TestRequest::get()
+                .uri("/api/ping?serial=S1")
+                .to_request(),
+        )
+        .await;
+        assert!(response.status().is_success());
+    }
+}
@@ -0,0 +1,228 @@
+/*
+ * Copyright 2025 Xiping Hu <hxp@hxp.plus>
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *    http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+*/
+
+// 模板渲染：脚本中的 {{ 变量 }} 替换为主机字段和全局配置，iPXE 自身的 ${...} 变量原样保留
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::config::TemplateConfig;
+
+static TEMPLATE_CONFIG: OnceLock<TemplateConfig> = OnceLock::new();
+
+pub type Vars = BTreeMap<String, String>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    // {{ 没有对应的 }}
+    Unterminated(usize),
+    // 变量名为空或包含非法字符
+    InvalidName(String),
+    // 模板引用了未设置的变量
+    Missing(Vec<String>),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unterminated(offset) => {
+                write!(f, "unterminated {{{{ at byte {offset}")
+            }
+            TemplateError::InvalidName(name) => write!(f, "invalid variable name {name:?}"),
+            TemplateError::Missing(names) => {
+                write!(f, "missing variables: {}", names.join(", "))
+            }
+        }
+    }
+}
+
+// 启动时设置全局模板变量
+pub fn init(config: &TemplateConfig) {
+    TEMPLATE_CONFIG.set(config.clone()).ok();
+}
+
+// 允许导入脚本和应答文件模板的目录
+pub fn import_dir() -> String {
+    TEMPLATE_CONFIG
+        .get()
+        .cloned()
+        .unwrap_or_default()
+        .import_dir
+}
+
+// 变量名只能由字母、数字和下划线组成
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Var(&'a str),
+}
+
+fn parse(template: &str) -> Result<Vec<Segment<'_>>, TemplateError> {
+    let mut segments = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        segments.push(Segment::Text(&rest[..start]));
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            return Err(TemplateError::Unterminated(
+                template.len() - rest.len() + start,
+            ));
+        };
+        let name = after[..end].trim();
+        if !is_valid_name(name) {
+            return Err(TemplateError::InvalidName(name.to_string()));
+        }
+        segments.push(Segment::Var(name));
+        rest = &after[end + 2..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+// 检查模板语法，返回模板引用的变量
+pub fn variables(template: &str) -> Result<Vec<String>, TemplateError> {
+    let mut names: Vec<String> = parse(template)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Var(name) => Some(name.to_string()),
+            Segment::Text(_) => None,
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+// 渲染模板，所有缺失的变量一起报告
+pub fn render(template: &str, vars: &Vars) -> Result<String, TemplateError> {
+    let mut output = String::with_capacity(template.len());
+    let mut missing = Vec::new();
+    for segment in parse(template)? {
+        match segment {
+            Segment::Text(text) => output.push_str(text),
+            Segment::Var(name) => match vars.get(name) {
+                Some(value) => output.push_str(value),
+                None => missing.push(name.to_string()),
+            },
+        }
+    }
+    if missing.is_empty() {
+        Ok(output)
+    } else {
+        missing.sort();
+        missing.dedup();
+        Err(TemplateError::Missing(missing))
+    }
+}
+
+// 全局变量：server_url 和配置文件 [templates.vars] 中的变量
+pub fn global_vars() -> Vars {
+    let config = TEMPLATE_CONFIG.get().cloned().unwrap_or_default();
+    let mut vars = config.vars;
+    vars.insert("server_url".to_string(), config.server_url);
+    vars
+}
+
+// 主机变量覆盖同名的全局变量，主机上未设置的字段不提供，引用时报告缺失；主机不存在时返回 None
+pub fn host_vars(conn: &Connection, serial: &str) -> Option<Vars> {
+    let fields: [Option<String>; 8] = conn
+        .query_row(
+            "SELECT serial, hostname, os, public_ip_addr, vlan_id, mac_address, ipmi_address, boot_mode FROM hosts WHERE serial = ?1",
+            params![serial],
+            |row| {
+                Ok([
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get::<_, Option<u32>>(4)?.map(|vlan_id| vlan_id.to_string()),
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                ])
+            },
+        )
+        .optional()
+        .unwrap()?;
+    let names = [
+        "serial",
+        "hostname",
+        "os",
+        "public_ip_addr",
+        "vlan_id",
+        "mac",
+        "ipmi_address",
+        "boot_mode",
+    ];
+    let mut vars = global_vars();
+    for (name, value) in names.into_iter().zip(fields) {
+        if let Some(value) = value {
+            vars.insert(name.to_string(), value);
+        }
+    }
+    Some(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_and_keeps_ipxe_variables() {
+        let vars = Vars::from([
+            ("serial".to_string(), "SN0001".to_string()),
+            ("server_url".to_string(), "http://10.0.0.1".to_string()),
+        ]);
+        let rendered = render(
+            "#!ipxe\nkernel {{server_url}}/vmlinuz serial={{ serial }} BOOTIF=01-${netX/mac:hexhyp}\n",
+            &vars,
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "#!ipxe\nkernel http://10.0.0.1/vmlinuz serial=SN0001 BOOTIF=01-${netX/mac:hexhyp}\n"
+        );
+    }
+
+    #[test]
+    fn reports_all_missing_variables() {
+        let vars = Vars::from([("serial".to_string(), "SN0001".to_string())]);
+        assert_eq!(
+            render("{{hostname}} {{serial}} {{vlan_id}} {{hostname}}", &vars),
+            Err(TemplateError::Missing(vec![
+                "hostname".to_string(),
+                "vlan_id".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert_eq!(variables("a {{ b"), Err(TemplateError::Unterminated(2)));
+        assert!(matches!(
+            variables("{{ a b }}"),
+            Err(TemplateError::InvalidName(_))
+        ));
+        assert_eq!(
+            variables("{{b}}{{a}}{{b}}").unwrap(),
+            ["a".to_string(), "b".to_string()]
+        );
+    }
+}
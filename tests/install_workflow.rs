@@ -0,0 +1,404 @@
+// 使用 FakeExecutor 模拟一台主机，离线走完从发现到装机完成的全过程
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+use std::fs;
+use std::path::PathBuf;
+
+use cloudboot_lce::boot_mode::BootMode;
+use cloudboot_lce::config::ProgressConfig;
+use cloudboot_lce::credentials::InstallPhase;
+use cloudboot_lce::database_init::init_db;
+use cloudboot_lce::fake_executor::{FakeExecutor, FakeHost};
+use cloudboot_lce::hosts_discovery::discover_hosts;
+use cloudboot_lce::progress_control::progress_control_cycle;
+
+const IP: &str = "10.0.0.5";
+const SERIAL: &str = "SN0001";
+const PUBLIC_IP: &str = "192.168.10.11";
+// 两块双口光口网卡，按默认规则选出每块网卡的第一个口
+const NIC_FACTS: &str = "ens1f0\t52:54:00:00:00:01\tixgbe\t10000\t0000:3b:00.0\tFIBRE\t\t\n\
+                         ens1f1\t52:54:00:00:00:02\tixgbe\t10000\t0000:3b:00.1\tFIBRE\t\t\n\
+                         ens2f0\t52:54:00:00:00:03\tixgbe\t10000\t0000:5e:00.0\tFIBRE\t\t\n\
+                         ens2f1\t52:54:00:00:00:04\tixgbe\t10000\t0000:5e:00.1\tFIBRE\t\t\n";
+
+struct TestEnv {
+    dir: PathBuf,
+    leases_file: String,
+    db_pool: Pool<SqliteConnectionManager>,
+    executor: FakeExecutor,
+    config: ProgressConfig,
+}
+
+impl TestEnv {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("cloudboot-lce-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let leases_file = dir.join("dhcpd.leases");
+        fs::write(
+            &leases_file,
+            format!(
+                "lease {IP} {{\n  starts 4 2025/01/01 00:00:00;\n  ends 4 2099/01/01 00:00:00;\n  hardware ethernet 52:54:00:AA:BB:CC;\n}}\n"
+            ),
+        )
+        .unwrap();
+        let db_pool = Pool::new(SqliteConnectionManager::file(dir.join("test.db"))).unwrap();
+        init_db(&db_pool.get().unwrap());
+        TestEnv {
+            leases_file: leases_file.to_string_lossy().to_string(),
+            dir,
+            executor: FakeExecutor::new().with_host_keys(db_pool.clone()),
+            db_pool,
+            config: ProgressConfig::default(),
+        }
+    }
+
+    async fn discover(&self) {
+        discover_hosts(&self.executor, &self.leases_file, 4, self.db_pool.clone()).await;
+    }
+
+    async fn control(&self) {
+        progress_control_cycle(&self.executor, &self.config, self.db_pool.clone()).await;
+    }
+
+    fn progress(&self) -> Option<i32> {
+        self.db_pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT install_progress FROM hosts WHERE serial = ?1",
+                params![SERIAL],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    fn configure_and_enqueue(&self) {
+        let conn = self.db_pool.get().unwrap();
+        conn.execute(
+            "INSERT INTO ipxe (os, active_version) VALUES ('Kylin-V10SP4-X86', 1)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO ipxe_versions (os, version, script, created_at) VALUES ('Kylin-V10SP4-X86', 1, '#!ipxe', '2025-01-01 00:00:00')",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE hosts SET os = 'Kylin-V10SP4-X86', hostname = 'node-01', public_ip_addr = ?1, vlan_id = 100 WHERE serial = ?2",
+            params![PUBLIC_IP, SERIAL],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO install_queue (ipmi_address) VALUES ('10.1.0.5')",
+            [],
+        )
+        .unwrap();
+    }
+
+    // 从装机队列开始安装，直到主机重启进入 kickstart
+    async fn start_installation(&self) {
+        self.control().await;
+        assert_eq!(self.progress(), Some(5));
+        assert_eq!(
+            self.query::<u32>("SELECT COUNT(*) FROM hosts WHERE kickstart_rebooted IS NOT NULL"),
+            1
+        );
+    }
+
+    fn query<T: rusqlite::types::FromSql>(&self, sql: &str) -> T {
+        self.db_pool
+            .get()
+            .unwrap()
+            .query_row(sql, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    // 模拟主机在当前阶段停留了很久
+    fn age_progress(&self) {
+        self.db_pool
+            .get()
+            .unwrap()
+            .execute(
+                "UPDATE hosts SET progress_updated = '2000-01-01 00:00:00' WHERE serial = ?1",
+                params![SERIAL],
+            )
+            .unwrap();
+    }
+
+    // 模拟装机程序在主机上写入安装进度
+    fn report(&self, progress: i32) {
+        self.executor.with_host(IP, |host| {
+            host.set_file("/tmp/install-progress", &format!("{progress}\n"))
+        });
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+#[tokio::test]
+async fn host_goes_from_not_configured_to_installed() {
+    let env = TestEnv::new("workflow");
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n"),
+    );
+
+    // BootOS 中的主机被发现并入库
+    env.discover().await;
+    assert_eq!(env.progress(), Some(0));
+    assert_eq!(
+        env.executor.host(IP).file("/tmp/install-progress.ack"),
+        Some("0")
+    );
+    assert_eq!(
+        env.query::<String>("SELECT mac_address FROM hosts"),
+        "52:54:00:aa:bb:cc".to_string()
+    );
+
+    // 配置操作系统和网络并加入装机队列，服务端开始安装并通过 BMC 重启主机到 kickstart
+    env.configure_and_enqueue();
+    env.start_installation().await;
+    assert_eq!(env.executor.host(IP).reboots, 1);
+    assert_eq!(env.executor.host(IP).power_cycles, 1);
+
+    // kickstart 安装过程中报告进度
+    for progress in [10, 20, 60, 80] {
+        env.report(progress);
+        env.discover().await;
+        assert_eq!(env.progress(), Some(progress));
+    }
+    assert_eq!(env.executor.host(IP).reboots, 1);
+
+    // 安装完成后重启进入操作系统，需要换用操作系统的凭据
+    env.executor
+        .with_host(IP, |host| host.phase = InstallPhase::System);
+    env.report(85);
+    env.discover().await;
+    assert_eq!(env.progress(), Some(85));
+
+    // 服务端配置网络，配置生效后主机 DHCP 地址失效，业务地址可以 ping 通
+    env.executor
+        .with_host(IP, |host| host.respond("ethtool", NIC_FACTS));
+    env.control().await;
+    let commands = env.executor.host(IP).commands;
+    let push = commands
+        .iter()
+        .find(|c| c.contains("nohup"))
+        .expect("network configuration pushed");
+    assert!(push.contains("/staged/ens1f0.nmconnection"));
+    assert!(push.contains("/staged/ens2f0.nmconnection"));
+    assert!(!push.contains("/staged/ens1f1.nmconnection"));
+    assert!(push.contains("/staged/bond0.100.nmconnection"));
+    assert_eq!(env.progress(), Some(85));
+
+    env.executor.with_host(IP, |host| host.reachable = false);
+    env.executor.set_pingable(PUBLIC_IP, true);
+    env.control().await;
+    assert_eq!(env.progress(), Some(100));
+
+    // 每次进度变化都记录在装机历史中
+    let conn = env.db_pool.get().unwrap();
+    let mut stmt = conn
+        .prepare("SELECT to_progress, source FROM install_events WHERE serial = ?1 ORDER BY id")
+        .unwrap();
+    let events: Vec<(i32, String)> = stmt
+        .query_map(params![SERIAL], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        events,
+        [
+            (0, "discovery"),
+            (5, "server"),
+            (10, "discovery"),
+            (20, "discovery"),
+            (60, "discovery"),
+            (80, "discovery"),
+            (85, "discovery"),
+            (100, "server"),
+        ]
+        .map(|(progress, source)| (progress, source.to_string()))
+    );
+}
+
+#[tokio::test]
+async fn host_without_os_is_not_installed() {
+    let env = TestEnv::new("no-os");
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n"),
+    );
+    env.discover().await;
+    env.db_pool
+        .get()
+        .unwrap()
+        .execute(
+            "INSERT INTO install_queue (ipmi_address) VALUES ('10.1.0.5')",
+            [],
+        )
+        .unwrap();
+    env.control().await;
+    env.discover().await;
+    env.control().await;
+    assert_eq!(env.progress(), Some(0));
+    assert_eq!(env.executor.host(IP).reboots, 0);
+}
+
+#[tokio::test]
+async fn illegal_progress_is_rejected_and_not_acknowledged() {
+    let env = TestEnv::new("illegal");
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n"),
+    );
+    env.discover().await;
+    assert_eq!(env.progress(), Some(0));
+
+    // 服务端未触发安装，主机不能直接进入 kickstart 阶段
+    env.report(10);
+    env.discover().await;
+    assert_eq!(env.progress(), Some(0));
+    assert_eq!(
+        env.executor.host(IP).file("/tmp/install-progress.ack"),
+        Some("0")
+    );
+
+    // 装机程序报告失败
+    env.report(-1);
+    env.discover().await;
+    assert_eq!(env.progress(), Some(-1));
+}
+
+#[tokio::test]
+async fn new_host_is_registered_as_not_configured() {
+    let env = TestEnv::new("new-host");
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "60\n"),
+    );
+
+    // 未纳管的主机报告安装中的进度：以 NotConfigured 入库，进度被状态机拒绝且不确认
+    env.discover().await;
+    assert_eq!(env.progress(), Some(0));
+    assert_eq!(
+        env.executor.host(IP).file("/tmp/install-progress.ack"),
+        None
+    );
+    assert_eq!(
+        env.query::<u32>("SELECT COUNT(*) FROM install_events WHERE to_progress = 0"),
+        1
+    );
+
+    // 主机重新进入装机环境报告 0 后确认
+    env.report(0);
+    env.discover().await;
+    assert_eq!(
+        env.executor.host(IP).file("/tmp/install-progress.ack"),
+        Some("0")
+    );
+    assert_eq!(env.query::<u32>("SELECT COUNT(*) FROM install_events"), 1);
+}
+
+#[tokio::test]
+async fn ipmi_address_is_kept_when_lookup_fails() {
+    let env = TestEnv::new("ipmi-lookup");
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n"),
+    );
+    env.discover().await;
+
+    // 带内读取带外管理地址失败时保留之前记录的地址
+    env.executor.with_host(IP, |host| host.ipmi_address = None);
+    env.discover().await;
+    assert_eq!(
+        env.query::<String>("SELECT ipmi_address FROM hosts"),
+        "10.1.0.5".to_string()
+    );
+}
+
+#[tokio::test]
+async fn stuck_host_times_out_and_is_retried() {
+    let mut env = TestEnv::new("timeout");
+    env.config.max_attempts = 2;
+    env.executor.add_host(
+        IP,
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n"),
+    );
+    env.discover().await;
+    env.configure_and_enqueue();
+    env.start_installation().await;
+    env.report(10);
+    env.discover().await;
+    assert_eq!(env.query::<u32>("SELECT install_attempts FROM hosts"), 1);
+
+    // 主机卡在 KickstartLoaded ，超时后重新排队并断电重启
+    env.control().await;
+    assert_eq!(env.progress(), Some(10));
+    env.age_progress();
+    env.control().await;
+    assert_eq!(env.progress(), Some(-2));
+    assert_eq!(env.executor.host(IP).power_cycles, 2);
+    assert_eq!(env.query::<u32>("SELECT COUNT(*) FROM install_queue"), 1);
+
+    // 主机重新进入装机环境后开始第二次安装，装机环境的新密钥重新固定且不告警
+    env.report(0);
+    env.discover().await;
+    assert_eq!(env.progress(), Some(0));
+    assert_eq!(
+        env.query::<String>("SELECT fingerprint FROM host_key_pins WHERE phase = 'installer'"),
+        env.executor.host(IP).installer_key
+    );
+    assert_eq!(env.query::<u32>("SELECT COUNT(*) FROM host_key_alerts"), 0);
+    env.start_installation().await;
+    assert_eq!(env.query::<u32>("SELECT install_attempts FROM hosts"), 2);
+
+    // 达到最多安装次数后不再重试
+    env.age_progress();
+    env.control().await;
+    assert_eq!(env.progress(), Some(-2));
+    assert_eq!(env.executor.host(IP).power_cycles, 3);
+    assert_eq!(env.query::<u32>("SELECT COUNT(*) FROM install_queue"), 0);
+}
+
+#[tokio::test]
+async fn unreachable_bmc_falls_back_to_in_band_reboot() {
+    let env = TestEnv::new("in-band");
+    let mut host =
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n");
+    host.bmc_reachable = false;
+    env.executor.add_host(IP, host);
+    env.discover().await;
+    env.configure_and_enqueue();
+    env.start_installation().await;
+    let host = env.executor.host(IP);
+    assert_eq!((host.reboots, host.power_cycles), (1, 0));
+}
+
+#[tokio::test]
+async fn bios_host_is_rebooted_in_legacy_mode() {
+    let env = TestEnv::new("bios");
+    let mut host =
+        FakeHost::new(SERIAL, Some("10.1.0.5")).with_file("/tmp/install-progress", "0\n");
+    host.boot_mode = BootMode::Bios;
+    host.bmc_reachable = false;
+    env.executor.add_host(IP, host);
+    env.discover().await;
+    assert_eq!(
+        env.query::<String>("SELECT boot_mode FROM hosts"),
+        "bios".to_string()
+    );
+    env.configure_and_enqueue();
+    env.start_installation().await;
+    let host = env.executor.host(IP);
+    assert_eq!(host.reboots, 1);
+    assert_eq!(host.pxe_boot_mode, Some(BootMode::Bios));
+}
@@ -0,0 +1,188 @@
+// 在本地启动模拟的 Redfish 服务，验证启动覆盖、复位和序列号读取
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+
+use cloudboot_lce::bmc::{self, BmcCredential, BmcError, BmcProtocol, BmcTarget, PowerState};
+use cloudboot_lce::boot_mode::BootMode;
+use cloudboot_lce::config::{IpmiConfig, RedfishConfig};
+use cloudboot_lce::database_init::init_db;
+use cloudboot_lce::redfish::{BootOverride, RedfishClient};
+
+const SYSTEM: &str = "/redfish/v1/Systems/1";
+
+struct MockBmc {
+    serial: String,
+    power: &'static str,
+    boot: Value,
+    resets: Vec<String>,
+}
+
+type State = web::Data<Arc<Mutex<MockBmc>>>;
+
+fn authorized(req: &HttpRequest) -> bool {
+    let expected = format!("Basic {}", BASE64.encode("admin:secret"));
+    req.headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        == Some(expected.as_str())
+}
+
+async fn systems(req: HttpRequest) -> HttpResponse {
+    if !authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    HttpResponse::Ok().json(json!({ "Members": [{ "@odata.id": SYSTEM }] }))
+}
+
+async fn system(req: HttpRequest, state: State) -> HttpResponse {
+    if !authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let bmc = state.lock().unwrap();
+    HttpResponse::Ok().json(json!({
+        "@odata.id": SYSTEM,
+        "SerialNumber": bmc.serial,
+        "PowerState": bmc.power,
+        "Boot": bmc.boot,
+        "Actions": {
+            "#ComputerSystem.Reset": {
+                "target": format!("{SYSTEM}/Actions/ComputerSystem.Reset")
+            }
+        }
+    }))
+}
+
+async fn patch_system(req: HttpRequest, body: web::Json<Value>, state: State) -> HttpResponse {
+    if !authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    state.lock().unwrap().boot = body["Boot"].clone();
+    HttpResponse::NoContent().finish()
+}
+
+async fn reset(req: HttpRequest, body: web::Json<Value>, state: State) -> HttpResponse {
+    if !authorized(&req) {
+        return HttpResponse::Unauthorized().finish();
+    }
+    let mut bmc = state.lock().unwrap();
+    bmc.resets
+        .push(body["ResetType"].as_str().unwrap_or_default().to_string());
+    bmc.power = "On";
+    HttpResponse::NoContent().finish()
+}
+
+// 启动模拟 BMC ，返回其地址和状态
+fn start_mock(serial: &str, power: &'static str) -> (String, Arc<Mutex<MockBmc>>) {
+    let state = Arc::new(Mutex::new(MockBmc {
+        serial: serial.to_string(),
+        power,
+        boot: json!({}),
+        resets: Vec::new(),
+    }));
+    let data = web::Data::new(state.clone());
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .route("/redfish/v1/Systems", web::get().to(systems))
+            .route(SYSTEM, web::get().to(system))
+            .route(SYSTEM, web::patch().to(patch_system))
+            .route(
+                "/redfish/v1/Systems/1/Actions/ComputerSystem.Reset",
+                web::post().to(reset),
+            )
+    })
+    .workers(1)
+    .bind("127.0.0.1:0")
+    .unwrap();
+    let address = format!("http://{}", server.addrs()[0]);
+    actix_web::rt::spawn(server.run());
+    (address, state)
+}
+
+fn credential(password: &str) -> BmcCredential {
+    BmcCredential {
+        username: "admin".to_string(),
+        password: password.to_string(),
+    }
+}
+
+#[actix_web::test]
+async fn sets_one_time_boot_override_and_restarts() {
+    let (address, state) = start_mock("SN0001", "On");
+    let client = RedfishClient::new(&address, credential("secret"), 5).unwrap();
+    assert_eq!(client.serial_number().await.unwrap(), "SN0001");
+    assert_eq!(client.power_state().await.unwrap(), PowerState::On);
+    client
+        .set_boot_override(BootOverride::UefiHttp, BootMode::Uefi)
+        .await
+        .unwrap();
+    client.power_cycle().await.unwrap();
+    let bmc = state.lock().unwrap();
+    assert_eq!(bmc.boot["BootSourceOverrideEnabled"], "Once");
+    assert_eq!(bmc.boot["BootSourceOverrideTarget"], "UefiHttp");
+    assert_eq!(bmc.boot["BootSourceOverrideMode"], "UEFI");
+    assert_eq!(bmc.resets, ["ForceRestart"]);
+}
+
+#[actix_web::test]
+async fn powered_off_host_is_powered_on() {
+    let (address, state) = start_mock("SN0001", "Off");
+    let client = RedfishClient::new(&address, credential("secret"), 5).unwrap();
+    client.power_cycle().await.unwrap();
+    assert_eq!(state.lock().unwrap().resets, ["On"]);
+}
+
+#[actix_web::test]
+async fn wrong_password_and_unreachable_bmc_are_distinguished() {
+    let (address, _) = start_mock("SN0001", "On");
+    let client = RedfishClient::new(&address, credential("wrong"), 5).unwrap();
+    assert!(matches!(
+        client.power_state().await,
+        Err(BmcError::Failed(_))
+    ));
+    let client = RedfishClient::new("http://127.0.0.1:1", credential("secret"), 5).unwrap();
+    assert!(matches!(
+        client.power_state().await,
+        Err(BmcError::Unreachable(_))
+    ));
+}
+
+#[actix_web::test]
+async fn pxe_reboot_checks_serial_before_reset() {
+    let (address, state) = start_mock("SN0001", "On");
+    let db_pool = Pool::builder()
+        .max_size(1)
+        .build(SqliteConnectionManager::memory())
+        .unwrap();
+    init_db(&db_pool.get().unwrap());
+    let ipmi = IpmiConfig {
+        user: "admin".to_string(),
+        password: "secret".to_string(),
+        ..IpmiConfig::default()
+    };
+    bmc::init(&ipmi, &RedfishConfig::default(), db_pool);
+    let mut target = BmcTarget {
+        address,
+        vendor: None,
+        serial: Some("SN0002".to_string()),
+        protocol: BmcProtocol::Redfish,
+        boot_mode: Some(BootMode::Bios),
+    };
+    assert!(matches!(
+        bmc::pxe_reboot(&target).await,
+        Err(BmcError::Failed(_))
+    ));
+    assert!(state.lock().unwrap().resets.is_empty());
+
+    target.serial = Some("SN0001".to_string());
+    bmc::pxe_reboot(&target).await.unwrap();
+    let bmc = state.lock().unwrap();
+    assert_eq!(bmc.boot["BootSourceOverrideTarget"], "Pxe");
+    assert_eq!(bmc.boot["BootSourceOverrideMode"], "Legacy");
+    assert_eq!(bmc.resets, ["ForceRestart"]);
+}